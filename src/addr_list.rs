@@ -0,0 +1,88 @@
+use fadroma::{
+    cosmwasm_std::{StdResult, Storage, CanonicalAddr},
+    storage::{
+        self, FixedSegmentSizeKey,
+        iterable::{IterableStorage, Iter}
+    }
+};
+
+const POSITIONS: &[u8] = b"addr_list_positions";
+
+/// A list of instance addresses from which an address can be removed
+/// without searching for it. Removing an address moves the last one
+/// in the list into its place.
+pub(crate) struct AddrList {
+    key: Vec<u8>
+}
+
+impl AddrList {
+    /// Each segment is length prefixed so that different
+    /// segments can't produce the same storage key.
+    pub fn new(segments: &[&[u8]]) -> Self {
+        let mut key = Vec::with_capacity(
+            segments.iter().map(|x| 4 + x.len()).sum()
+        );
+
+        for segment in segments {
+            key.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            key.extend_from_slice(segment);
+        }
+
+        Self { key }
+    }
+
+    #[inline]
+    pub fn iter<'a>(&self, storage: &'a dyn Storage) -> StdResult<Iter<'a, CanonicalAddr>> {
+        self.storage().iter(storage)
+    }
+
+    #[inline]
+    pub fn len(&self, storage: &dyn Storage) -> StdResult<u64> {
+        self.storage().len(storage)
+    }
+
+    pub fn push(&self, storage: &mut dyn Storage, addr: &CanonicalAddr) -> StdResult<()> {
+        let index = self.storage().push(storage, addr)?;
+
+        storage::save(storage, self.position_key(addr), &index)
+    }
+
+    #[inline]
+    pub fn position(&self, storage: &dyn Storage, addr: &CanonicalAddr) -> StdResult<Option<u64>> {
+        storage::load(storage, self.position_key(addr))
+    }
+
+    #[inline]
+    pub fn contains(&self, storage: &dyn Storage, addr: &CanonicalAddr) -> StdResult<bool> {
+        Ok(self.position(storage, addr)?.is_some())
+    }
+
+    /// Returns `false` if the address isn't in the list.
+    pub fn remove(&self, storage: &mut dyn Storage, addr: &CanonicalAddr) -> StdResult<bool> {
+        let Some(index) = self.position(storage, addr)? else {
+            return Ok(false);
+        };
+
+        storage::remove(storage, self.position_key(addr));
+
+        if let Some(moved) = self.storage().swap_remove(storage, index)? {
+            storage::save(storage, self.position_key(&moved), &index)?;
+        }
+
+        Ok(true)
+    }
+
+    #[inline]
+    fn storage(&self) -> IterableStorage<CanonicalAddr, FixedSegmentSizeKey<'_, 1>> {
+        IterableStorage::new(FixedSegmentSizeKey::from([self.key.as_slice()]))
+    }
+
+    fn position_key(&self, addr: &CanonicalAddr) -> Vec<u8> {
+        let mut key = Vec::with_capacity(POSITIONS.len() + self.key.len() + addr.len());
+        key.extend_from_slice(POSITIONS);
+        key.extend_from_slice(&self.key);
+        key.extend_from_slice(addr.as_slice());
+
+        key
+    }
+}
@@ -0,0 +1,276 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{StdResult, StdError, Response, Deps, DepsMut, MessageInfo, Addr},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::SingleItem,
+    core::{Canonize, Humanize},
+    admin,
+    namespace
+};
+
+use crate::{
+    GenericFactory, ExtraData, FactoryHooks, FactoryError, AddrList, TokenGate,
+    Config, Pagination, PaginatedResponse
+};
+
+/// Who can create instances. Overrides the `AUTH` parameter
+/// of the factory once set with [`crate::ExecuteMsg::SetAuthMode`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    AdminOnly,
+    Anyone,
+    /// The admin and the addresses in the creator allowlist.
+    Allowlist,
+    /// The admin and the holders of a token.
+    TokenGated(TokenGate)
+}
+
+namespace!(AuthModeNs, b"auth_mode");
+const AUTH_MODE: SingleItem<AuthMode, AuthModeNs> = SingleItem::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    #[admin::require_admin]
+    pub fn set_auth_mode(
+        deps: DepsMut,
+        info: MessageInfo,
+        mode: AuthMode
+    ) -> StdResult<Response> {
+        AUTH_MODE.save(deps.storage, &mode)?;
+
+        Ok(Response::default())
+    }
+
+    /// Adds addresses to the creator allowlist which is used
+    /// when the auth mode is [`AuthMode::Allowlist`].
+    #[admin::require_admin]
+    pub fn add_creators(
+        deps: DepsMut,
+        info: MessageInfo,
+        addrs: Vec<String>
+    ) -> StdResult<Response> {
+        let allowlist = Self::creator_allowlist();
+
+        for addr in addrs {
+            let addr = addr.as_str().canonize(deps.api)?;
+
+            if !allowlist.contains(deps.storage, &addr)? {
+                allowlist.push(deps.storage, &addr)?;
+            }
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Removes addresses from the creator allowlist. Addresses
+    /// that are not in it are ignored.
+    #[admin::require_admin]
+    pub fn remove_creators(
+        deps: DepsMut,
+        info: MessageInfo,
+        addrs: Vec<String>
+    ) -> StdResult<Response> {
+        let allowlist = Self::creator_allowlist();
+
+        for addr in addrs {
+            allowlist.remove(deps.storage, &addr.as_str().canonize(deps.api)?)?;
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Removing an address moves the last one into its place.
+    pub fn list_creators(deps: Deps, pagination: Pagination) ->
+        StdResult<PaginatedResponse<Addr>>
+    {
+        let allowlist = Self::creator_allowlist();
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate(&config, allowlist.iter(deps.storage)?, |x| x.humanize(deps.api))
+    }
+
+    /// The stored auth mode or the one implied by `AUTH` if not set.
+    pub fn auth_mode(deps: Deps) -> StdResult<AuthMode> {
+        let mode = AUTH_MODE.load(deps.storage)?.unwrap_or(if AUTH {
+            AuthMode::AdminOnly
+        } else {
+            AuthMode::Anyone
+        });
+
+        Ok(mode)
+    }
+
+    /// Adds addresses to the creator blocklist. Blocked addresses cannot
+    /// create instances regardless of the [`AuthMode`].
+    #[admin::require_admin]
+    pub fn block_creators(
+        deps: DepsMut,
+        info: MessageInfo,
+        addrs: Vec<String>
+    ) -> StdResult<Response> {
+        let blocklist = Self::creator_blocklist();
+
+        for addr in addrs {
+            let addr = addr.as_str().canonize(deps.api)?;
+
+            if !blocklist.contains(deps.storage, &addr)? {
+                blocklist.push(deps.storage, &addr)?;
+            }
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Removes addresses from the creator blocklist. Addresses
+    /// that are not in it are ignored.
+    #[admin::require_admin]
+    pub fn unblock_creators(
+        deps: DepsMut,
+        info: MessageInfo,
+        addrs: Vec<String>
+    ) -> StdResult<Response> {
+        let blocklist = Self::creator_blocklist();
+
+        for addr in addrs {
+            blocklist.remove(deps.storage, &addr.as_str().canonize(deps.api)?)?;
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Unblocking an address moves the last one into its place.
+    pub fn list_blocked_creators(deps: Deps, pagination: Pagination) ->
+        StdResult<PaginatedResponse<Addr>>
+    {
+        let blocklist = Self::creator_blocklist();
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate(&config, blocklist.iter(deps.storage)?, |x| x.humanize(deps.api))
+    }
+
+    /// Grants addresses the operator role. Operators can create instances
+    /// in any [`AuthMode`] and update their extra data but cannot
+    /// change the code or perform any other admin action.
+    #[admin::require_admin]
+    pub fn add_operators(
+        deps: DepsMut,
+        info: MessageInfo,
+        addrs: Vec<String>
+    ) -> StdResult<Response> {
+        let operators = Self::operators();
+
+        for addr in addrs {
+            let addr = addr.as_str().canonize(deps.api)?;
+
+            if !operators.contains(deps.storage, &addr)? {
+                operators.push(deps.storage, &addr)?;
+            }
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Revokes the operator role. Addresses that
+    /// are not operators are ignored.
+    #[admin::require_admin]
+    pub fn remove_operators(
+        deps: DepsMut,
+        info: MessageInfo,
+        addrs: Vec<String>
+    ) -> StdResult<Response> {
+        let operators = Self::operators();
+
+        for addr in addrs {
+            operators.remove(deps.storage, &addr.as_str().canonize(deps.api)?)?;
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Removing an operator moves the last one into its place.
+    pub fn list_operators(deps: Deps, pagination: Pagination) ->
+        StdResult<PaginatedResponse<Addr>>
+    {
+        let operators = Self::operators();
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate(&config, operators.iter(deps.storage)?, |x| x.humanize(deps.api))
+    }
+
+    /// Fails if the sender is neither the admin nor an operator.
+    pub fn assert_admin_or_operator(deps: Deps, info: &MessageInfo) -> StdResult<()> {
+        if Self::is_admin_or_operator(deps, info)? {
+            Ok(())
+        } else {
+            Err(StdError::generic_err("Unauthorized"))
+        }
+    }
+
+    /// Fails if the sender is blocked or cannot create
+    /// instances under the current [`AuthMode`].
+    pub fn assert_can_create(deps: Deps, info: &MessageInfo) -> StdResult<()> {
+        Self::assert_not_blocked(deps, &info.sender)?;
+
+        if Self::is_admin_or_operator(deps, info)? {
+            return Ok(());
+        }
+
+        let sender = info.sender.as_str().canonize(deps.api)?;
+
+        match Self::auth_mode(deps)? {
+            AuthMode::AdminOnly => admin::assert(deps, info),
+            AuthMode::Anyone => Ok(()),
+            AuthMode::Allowlist => {
+                if !Self::creator_allowlist().contains(deps.storage, &sender)? {
+                    return Err(FactoryError::NotAllowedToCreate.into());
+                }
+
+                Ok(())
+            }
+            AuthMode::TokenGated(gate) =>
+                Self::assert_holds_token(deps, &gate, &info.sender)
+        }
+    }
+
+    fn is_admin_or_operator(deps: Deps, info: &MessageInfo) -> StdResult<bool> {
+        if admin::assert(deps, info).is_ok() {
+            return Ok(true);
+        }
+
+        let sender = info.sender.as_str().canonize(deps.api)?;
+
+        Self::operators().contains(deps.storage, &sender)
+    }
+
+    pub(crate) fn assert_not_blocked(deps: Deps, sender: &Addr) -> StdResult<()> {
+        let sender = sender.as_str().canonize(deps.api)?;
+
+        if Self::creator_blocklist().contains(deps.storage, &sender)? {
+            return Err(FactoryError::CreatorBlocked.into());
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn creator_allowlist() -> AddrList {
+        AddrList::new(&[b"creator_allowlist"])
+    }
+
+    #[inline]
+    fn creator_blocklist() -> AddrList {
+        AddrList::new(&[b"creator_blocklist"])
+    }
+
+    #[inline]
+    fn operators() -> AddrList {
+        AddrList::new(&[b"operators"])
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{StdResult, StdError, Response, Deps, DepsMut, MessageInfo, Binary, WasmMsg},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::SingleItem,
+    core::Humanize,
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks};
+
+/// A message being sent to every registered instance.
+/// See [`crate::ExecuteMsg::StartBroadcast`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct Broadcast {
+    pub msg: Binary,
+    /// The ID of the instance that the next batch starts from.
+    pub next_id: u64,
+    /// The number of instances that the message was sent to so far.
+    pub sent: u64
+}
+
+namespace!(BroadcastNs, b"broadcast");
+const BROADCAST: SingleItem<Broadcast, BroadcastNs> = SingleItem::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Stores `msg` to be sent to every registered instance by
+    /// [`GenericFactory::continue_broadcast`]. Only one broadcast
+    /// can be in progress at a time.
+    #[admin::require_admin]
+    pub fn start_broadcast(
+        deps: DepsMut,
+        info: MessageInfo,
+        msg: Binary
+    ) -> StdResult<Response> {
+        if BROADCAST.load(deps.storage)?.is_some() {
+            return Err(StdError::generic_err("A broadcast is already in progress."));
+        }
+
+        BROADCAST.save(deps.storage, &Broadcast { msg, next_id: 0, sent: 0 })?;
+
+        Ok(Response::default())
+    }
+
+    /// Sends the broadcast message to the instances with the next `batch_size`
+    /// IDs. Removed instances are skipped but count towards the batch. The
+    /// broadcast is finished once all instances have been sent it, including
+    /// the ones registered after it was started.
+    #[admin::require_admin]
+    pub fn continue_broadcast(
+        deps: DepsMut,
+        info: MessageInfo,
+        batch_size: u32
+    ) -> StdResult<Response> {
+        let Some(mut broadcast) = BROADCAST.load(deps.storage)? else {
+            return Err(StdError::generic_err("No broadcast is in progress."));
+        };
+
+        let (msgs, done) = Self::send_batch(
+            deps.as_ref(),
+            &broadcast.msg,
+            &mut broadcast.next_id,
+            batch_size
+        )?;
+
+        broadcast.sent += msgs.len() as u64;
+
+        if done {
+            BROADCAST.remove(deps.storage);
+        } else {
+            BROADCAST.save(deps.storage, &broadcast)?;
+        }
+
+        Ok(Response::default()
+            .add_messages(msgs)
+            .add_attribute_plaintext("broadcast_sent", broadcast.sent.to_string())
+            .add_attribute_plaintext("broadcast_done", done.to_string())
+        )
+    }
+
+    /// Stops the broadcast in progress. Instances that
+    /// were already sent the message are not affected.
+    #[admin::require_admin]
+    pub fn cancel_broadcast(deps: DepsMut, info: MessageInfo) -> StdResult<Response> {
+        BROADCAST.remove(deps.storage);
+
+        Ok(Response::default())
+    }
+
+    #[inline]
+    pub fn broadcast(deps: Deps) -> StdResult<Option<Broadcast>> {
+        BROADCAST.load(deps.storage)
+    }
+
+    /// Creates the messages that send `msg` to the instances with the next
+    /// `batch_size` IDs starting from `next_id`, which is moved past them.
+    /// Removed instances are skipped but count towards the batch. Also
+    /// returns whether all instances have been sent the message.
+    pub(crate) fn send_batch(
+        deps: Deps,
+        msg: &Binary,
+        next_id: &mut u64,
+        batch_size: u32
+    ) -> StdResult<(Vec<WasmMsg>, bool)> {
+        if batch_size == 0 {
+            return Err(StdError::generic_err("Batch size must be greater than zero."));
+        }
+
+        let end = Self::instance_times().len(deps.storage)?;
+        let batch_end = end.min(next_id.saturating_add(batch_size as u64));
+        let mut msgs = Vec::with_capacity(batch_size as usize);
+
+        for id in *next_id..batch_end {
+            // Removed instances are skipped.
+            let Some(instance) = Self::load_by_id(deps, id)? else {
+                continue;
+            };
+
+            msgs.push(WasmMsg::Execute {
+                contract_addr: instance.contract.address.humanize(deps.api)?.into_string(),
+                code_hash: instance.contract.code_hash,
+                msg: msg.clone(),
+                funds: vec![]
+            });
+        }
+
+        *next_id = batch_end;
+
+        Ok((msgs, batch_end >= end))
+    }
+}
@@ -0,0 +1,513 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut,
+        Env, MessageInfo, Addr, CanonicalAddr, Timestamp
+    },
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{SingleItem, ItemSpace, TypedKey},
+    core::{ContractCode, Canonize, Humanize},
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, Config, RegisteredCode, CONTRACT};
+
+/// A change of the default contract code that was scheduled by the admin.
+/// Anyone can apply it using [`crate::ExecuteMsg::ApplyCodeChange`] once
+/// `available_at` is reached and, if required, it has been confirmed.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct PendingCodeChange {
+    pub code: ContractCode,
+    pub proposer: Addr,
+    pub available_at: Timestamp,
+    pub confirmed: bool
+}
+
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct StoredCodeChange {
+    code: ContractCode,
+    proposer: CanonicalAddr,
+    proposed_at_height: u64,
+    /// In nanoseconds since Timestamp doesn't
+    /// implement the Fadroma binary serialization.
+    available_at: u64,
+    confirmed: bool
+}
+
+/// A code registration that was scheduled by the admin because
+/// [`Config::code_change_delay`] or [`Config::confirm_code_changes`] is set.
+/// Anyone can apply it using [`crate::ExecuteMsg::ApplyCodeRegistration`] once
+/// `available_at` is reached and, if required, it has been confirmed.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct PendingCodeRegistration {
+    pub code: RegisteredCode,
+    pub proposer: Addr,
+    pub available_at: Timestamp,
+    pub confirmed: bool
+}
+
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct StoredCodeRegistration {
+    code: RegisteredCode,
+    proposer: CanonicalAddr,
+    proposed_at_height: u64,
+    /// In nanoseconds.
+    available_at: u64,
+    confirmed: bool
+}
+
+/// A decrease of [`Config::code_change_delay`] that was requested with
+/// [`crate::ExecuteMsg::UpdateConfig`]. It takes effect once the delay
+/// that was in place when it was requested has passed.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct PendingDelayDecrease {
+    pub delay: u64,
+    pub available_at: Timestamp
+}
+
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct StoredDelayDecrease {
+    delay: u64,
+    /// In nanoseconds.
+    available_at: u64
+}
+
+/// A change of [`Config::confirm_code_changes`] or [`Config::code_change_confirmer`]
+/// requested with [`crate::ExecuteMsg::UpdateConfig`]. While code changes must be
+/// confirmed by a confirmer, it only takes effect once the current confirmer
+/// accepts it with [`crate::ExecuteMsg::ConfirmConfirmerChange`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct ConfirmerChange {
+    pub confirm_code_changes: bool,
+    pub code_change_confirmer: Option<Addr>
+}
+
+namespace!(PendingCodeChangeNs, b"pending_code_change");
+const PENDING_CODE_CHANGE: SingleItem<
+    StoredCodeChange,
+    PendingCodeChangeNs
+> = SingleItem::new();
+
+namespace!(PendingCodeRegistrationsNs, b"pending_code_registrations");
+const PENDING_CODE_REGISTRATIONS: ItemSpace<
+    StoredCodeRegistration,
+    PendingCodeRegistrationsNs,
+    TypedKey<String>
+> = ItemSpace::new();
+
+namespace!(PendingConfirmerChangeNs, b"pending_confirmer_change");
+const PENDING_CONFIRMER_CHANGE: SingleItem<
+    ConfirmerChange,
+    PendingConfirmerChangeNs
+> = SingleItem::new();
+
+namespace!(PendingDelayDecreaseNs, b"pending_delay_decrease");
+const PENDING_DELAY_DECREASE: SingleItem<
+    StoredDelayDecrease,
+    PendingDelayDecreaseNs
+> = SingleItem::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Confirms the pending code change. The `code` must match the pending
+    /// one exactly. Can only be called by [`Config::code_change_confirmer`] or
+    /// by the admin if not set, in which case it must happen at least one block
+    /// after the change was proposed. The change is applied immediately if
+    /// its delay has already passed.
+    pub fn confirm_code_change(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        code: ContractCode
+    ) -> StdResult<Response> {
+        Self::assert_code_change_confirmer(deps.as_ref(), &info)?;
+
+        let Some(mut pending) = PENDING_CODE_CHANGE.load(deps.storage)? else {
+            return Err(Self::no_pending_code_change());
+        };
+
+        if pending.code != code {
+            return Err(StdError::generic_err(
+                "Confirmed code doesn't match the pending code change."
+            ));
+        }
+
+        let sender = info.sender.canonize(deps.api)?;
+
+        if sender == pending.proposer && env.block.height <= pending.proposed_at_height {
+            return Err(StdError::generic_err(
+                "The proposer must confirm the code change in a later block."
+            ));
+        }
+
+        pending.confirmed = true;
+        PENDING_CODE_CHANGE.save(deps.storage, &pending)?;
+
+        if env.block.time.nanos() >= pending.available_at {
+            return Self::apply_code_change(deps, env);
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Confirms the code registration pending under `key` the same way as
+    /// [`GenericFactory::confirm_code_change`]. The `code` must match the
+    /// pending one exactly. The registration is applied immediately if its
+    /// delay has already passed.
+    pub fn confirm_code_registration(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        key: String,
+        code: ContractCode
+    ) -> StdResult<Response> {
+        Self::assert_code_change_confirmer(deps.as_ref(), &info)?;
+
+        let Some(mut pending) = PENDING_CODE_REGISTRATIONS.load(deps.storage, &key)? else {
+            return Err(Self::no_pending_code_registration(&key));
+        };
+
+        if pending.code.code != code {
+            return Err(StdError::generic_err(
+                "Confirmed code doesn't match the pending code registration."
+            ));
+        }
+
+        let sender = info.sender.canonize(deps.api)?;
+
+        if sender == pending.proposer && env.block.height <= pending.proposed_at_height {
+            return Err(StdError::generic_err(
+                "The proposer must confirm the code registration in a later block."
+            ));
+        }
+
+        pending.confirmed = true;
+        PENDING_CODE_REGISTRATIONS.save(deps.storage, &key, &pending)?;
+
+        if env.block.time.nanos() >= pending.available_at {
+            return Self::apply_code_registration(deps, env, key);
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Accepts the pending [`ConfirmerChange`], which must match `change`
+    /// exactly. Can only be called by the current [`Config::code_change_confirmer`].
+    pub fn confirm_confirmer_change(
+        deps: DepsMut,
+        info: MessageInfo,
+        change: ConfirmerChange
+    ) -> StdResult<Response> {
+        let mut config = Config::load(deps.storage)?;
+
+        if config.code_change_confirmer.as_ref() != Some(&info.sender) {
+            return Err(StdError::generic_err("Unauthorized"));
+        }
+
+        let Some(pending) = PENDING_CONFIRMER_CHANGE.load(deps.storage)? else {
+            return Err(StdError::generic_err("No confirmer change is pending."));
+        };
+
+        if pending != change {
+            return Err(StdError::generic_err(
+                "Confirmed change doesn't match the pending confirmer change."
+            ));
+        }
+
+        config.confirm_code_changes = change.confirm_code_changes;
+        config.code_change_confirmer = change.code_change_confirmer;
+        config.save(deps.storage)?;
+
+        PENDING_CONFIRMER_CHANGE.remove(deps.storage);
+
+        Ok(Response::default())
+    }
+
+    pub fn apply_code_change(mut deps: DepsMut, env: Env) -> StdResult<Response> {
+        let Some(pending) = PENDING_CODE_CHANGE.load(deps.storage)? else {
+            return Err(Self::no_pending_code_change());
+        };
+
+        let available_at = Timestamp::from_nanos(pending.available_at);
+
+        if env.block.time < available_at {
+            return Err(StdError::generic_err(format!(
+                "Code change cannot be applied before {}.",
+                available_at.seconds()
+            )));
+        }
+
+        if !pending.confirmed {
+            return Err(StdError::generic_err("Code change hasn't been confirmed."));
+        }
+
+        let proposer = pending.proposer.humanize(deps.api)?;
+
+        let event = Self::record_code_change(deps.branch(), &env, &proposer, &pending.code)?;
+        CONTRACT.save(deps.storage, &pending.code)?;
+        PENDING_CODE_CHANGE.remove(deps.storage);
+
+        Ok(Response::default().add_event(event))
+    }
+
+    /// Registers the code that was scheduled under `key`
+    /// with [`crate::ExecuteMsg::RegisterCode`].
+    pub fn apply_code_registration(mut deps: DepsMut, env: Env, key: String) -> StdResult<Response> {
+        let Some(pending) = PENDING_CODE_REGISTRATIONS.load(deps.storage, &key)? else {
+            return Err(Self::no_pending_code_registration(&key));
+        };
+
+        let available_at = Timestamp::from_nanos(pending.available_at);
+
+        if env.block.time < available_at {
+            return Err(StdError::generic_err(format!(
+                "Code registration cannot be applied before {}.",
+                available_at.seconds()
+            )));
+        }
+
+        if !pending.confirmed {
+            return Err(StdError::generic_err("Code registration hasn't been confirmed."));
+        }
+
+        Self::save_registered_code(deps.branch(), &pending.code)?;
+        PENDING_CODE_REGISTRATIONS.remove(deps.storage, &key);
+
+        Ok(Response::default())
+    }
+
+    pub fn pending_code_change(deps: Deps) -> StdResult<Option<PendingCodeChange>> {
+        let Some(pending) = PENDING_CODE_CHANGE.load(deps.storage)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(PendingCodeChange {
+            code: pending.code,
+            proposer: pending.proposer.humanize(deps.api)?,
+            available_at: Timestamp::from_nanos(pending.available_at),
+            confirmed: pending.confirmed
+        }))
+    }
+
+    pub fn pending_code_registration(
+        deps: Deps,
+        key: String
+    ) -> StdResult<Option<PendingCodeRegistration>> {
+        let Some(pending) = PENDING_CODE_REGISTRATIONS.load(deps.storage, &key)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(PendingCodeRegistration {
+            code: pending.code,
+            proposer: pending.proposer.humanize(deps.api)?,
+            available_at: Timestamp::from_nanos(pending.available_at),
+            confirmed: pending.confirmed
+        }))
+    }
+
+    #[inline]
+    pub fn pending_confirmer_change(deps: Deps) -> StdResult<Option<ConfirmerChange>> {
+        PENDING_CONFIRMER_CHANGE.load(deps.storage)
+    }
+
+    pub fn pending_delay_decrease(deps: Deps) -> StdResult<Option<PendingDelayDecrease>> {
+        let Some(pending) = PENDING_DELAY_DECREASE.load(deps.storage)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(PendingDelayDecrease {
+            delay: pending.delay,
+            available_at: Timestamp::from_nanos(pending.available_at)
+        }))
+    }
+
+    /// Loads the config, first applying the pending decrease
+    /// of [`Config::code_change_delay`] if it's available.
+    pub(crate) fn load_config_with_delay(deps: DepsMut, env: &Env) -> StdResult<Config> {
+        let mut config = Config::load(deps.storage)?;
+
+        let Some(pending) = PENDING_DELAY_DECREASE.load(deps.storage)? else {
+            return Ok(config);
+        };
+
+        if env.block.time.nanos() >= pending.available_at {
+            config.code_change_delay = pending.delay;
+            config.save(deps.storage)?;
+
+            PENDING_DELAY_DECREASE.remove(deps.storage);
+        }
+
+        Ok(config)
+    }
+
+    /// Cancels the pending decrease of [`Config::code_change_delay`].
+    #[admin::require_admin]
+    pub fn cancel_delay_decrease(
+        deps: DepsMut,
+        info: MessageInfo
+    ) -> StdResult<Response> {
+        if PENDING_DELAY_DECREASE.load(deps.storage)?.is_none() {
+            return Err(StdError::generic_err("No delay decrease is pending."));
+        }
+
+        PENDING_DELAY_DECREASE.remove(deps.storage);
+
+        Ok(Response::default())
+    }
+
+    /// Keeps the `current` delay in the `new` config if it's lower and
+    /// schedules the decrease to take effect after the current delay.
+    /// Submitting the current or the pending delay again leaves a pending
+    /// decrease as is while a higher delay cancels it.
+    pub(crate) fn schedule_delay_decrease(
+        deps: DepsMut,
+        env: &Env,
+        current: &Config,
+        new: &mut Config
+    ) -> StdResult<()> {
+        if new.code_change_delay == current.code_change_delay {
+            return Ok(());
+        }
+
+        if new.code_change_delay > current.code_change_delay {
+            PENDING_DELAY_DECREASE.remove(deps.storage);
+
+            return Ok(());
+        }
+
+        let pending = PENDING_DELAY_DECREASE.load(deps.storage)?;
+
+        if matches!(pending, Some(pending) if pending.delay == new.code_change_delay) {
+            new.code_change_delay = current.code_change_delay;
+
+            return Ok(());
+        }
+
+        PENDING_DELAY_DECREASE.save(deps.storage, &StoredDelayDecrease {
+            delay: new.code_change_delay,
+            available_at: env.block.time.plus_seconds(current.code_change_delay).nanos()
+        })?;
+
+        new.code_change_delay = current.code_change_delay;
+
+        Ok(())
+    }
+
+    /// If code changes must be confirmed by a confirmer, keeps the `current`
+    /// confirmation settings in the `new` config and waits for the current
+    /// confirmer to accept the new ones. Otherwise they are applied as is.
+    /// A pending change is kept if the settings are left unchanged.
+    pub(crate) fn schedule_confirmer_change(
+        deps: DepsMut,
+        current: &Config,
+        new: &mut Config
+    ) -> StdResult<()> {
+        let change = ConfirmerChange {
+            confirm_code_changes: new.confirm_code_changes,
+            code_change_confirmer: new.code_change_confirmer.clone()
+        };
+
+        let unchanged = change.confirm_code_changes == current.confirm_code_changes &&
+            change.code_change_confirmer == current.code_change_confirmer;
+
+        // Leave a pending change alone when the settings are submitted
+        // again as they are, e.g. while changing another part of the config.
+        if unchanged {
+            return Ok(());
+        }
+
+        if !current.confirm_code_changes || current.code_change_confirmer.is_none() {
+            PENDING_CONFIRMER_CHANGE.remove(deps.storage);
+
+            return Ok(());
+        }
+
+        PENDING_CONFIRMER_CHANGE.save(deps.storage, &change)?;
+
+        new.confirm_code_changes = current.confirm_code_changes;
+        new.code_change_confirmer = current.code_change_confirmer.clone();
+
+        Ok(())
+    }
+
+    /// Schedules the code change to be applied after `delay` seconds,
+    /// replacing any code change that is already pending.
+    pub(crate) fn schedule_code_change(
+        deps: DepsMut,
+        env: &Env,
+        proposer: &Addr,
+        code: &ContractCode,
+        config: &Config
+    ) -> StdResult<()> {
+        PENDING_CODE_CHANGE.save(deps.storage, &StoredCodeChange {
+            code: code.clone(),
+            proposer: proposer.canonize(deps.api)?,
+            proposed_at_height: env.block.height,
+            available_at: env.block.time.plus_seconds(config.code_change_delay).nanos(),
+            confirmed: !config.confirm_code_changes
+        })
+    }
+
+    /// Schedules the code to be registered after [`Config::code_change_delay`] and
+    /// once confirmed if required, replacing any registration pending under the same key.
+    pub(crate) fn schedule_code_registration(
+        deps: DepsMut,
+        env: &Env,
+        proposer: &Addr,
+        code: RegisteredCode,
+        config: &Config
+    ) -> StdResult<()> {
+        let key = code.key.clone();
+
+        PENDING_CODE_REGISTRATIONS.save(deps.storage, &key, &StoredCodeRegistration {
+            code,
+            proposer: proposer.canonize(deps.api)?,
+            proposed_at_height: env.block.height,
+            available_at: env.block.time.plus_seconds(config.code_change_delay).nanos(),
+            confirmed: !config.confirm_code_changes
+        })
+    }
+
+    /// Returns whether a registration was pending under `key`.
+    pub(crate) fn cancel_code_registration(deps: DepsMut, key: &String) -> StdResult<bool> {
+        let is_pending = PENDING_CODE_REGISTRATIONS.load(deps.storage, key)?.is_some();
+        PENDING_CODE_REGISTRATIONS.remove(deps.storage, key);
+
+        Ok(is_pending)
+    }
+
+    #[inline]
+    pub(crate) fn clear_pending_code_change(deps: DepsMut) {
+        PENDING_CODE_CHANGE.remove(deps.storage);
+    }
+
+    /// Code changes and registrations can be confirmed by
+    /// [`Config::code_change_confirmer`] or by the admin if not set.
+    fn assert_code_change_confirmer(deps: Deps, info: &MessageInfo) -> StdResult<()> {
+        let config = Config::load(deps.storage)?;
+
+        match config.code_change_confirmer {
+            Some(confirmer) if confirmer != info.sender =>
+                Err(StdError::generic_err("Unauthorized")),
+            Some(_) => Ok(()),
+            None => admin::assert(deps, info)
+        }
+    }
+
+    #[inline]
+    fn no_pending_code_change() -> StdError {
+        StdError::generic_err("No code change is pending.")
+    }
+
+    #[inline]
+    fn no_pending_code_registration(key: &str) -> StdError {
+        StdError::generic_err(format!("No registration of code \"{key}\" is pending."))
+    }
+}
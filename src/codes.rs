@@ -0,0 +1,543 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        self, StdResult, StdError, Response, Deps, DepsMut,
+        MessageInfo, Env, Addr, CanonicalAddr, Binary, Timestamp, Event
+    },
+    bin_serde::{
+        self, FadromaSerialize, FadromaDeserialize,
+        Serializer, Deserializer
+    },
+    storage::{
+        ItemSpace, TypedKey, StaticKey,
+        map::Map, iterable::IterableStorage
+    },
+    core::{ContractCode, Canonize, Humanize},
+    admin,
+    namespace
+};
+
+use crate::{
+    GenericFactory, ExtraData, FactoryHooks, Config, Pagination,
+    PaginatedResponse, FactoryError, CODE_CHANGE_EVENT
+};
+
+/// A contract code stored in the code registry under a string key.
+/// Pass the key in [`crate::InstanceConfig::code_key`] to create
+/// instances from this code instead of the default one.
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct RegisteredCode {
+    pub key: String,
+    pub code: ContractCode,
+    pub metadata: Option<CodeMetadata>
+}
+
+/// Human-readable information about a registered code for UIs to display.
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct CodeMetadata {
+    pub name: String,
+    /// A semantic version, e.g. `1.2.0`.
+    pub version: Option<String>,
+    pub description: Option<String>
+}
+
+/// An entry in the history of the default contract code.
+#[derive(Serialize, Deserialize, JsonSchema, Canonize, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct CodeChange<A> {
+    pub code: ContractCode,
+    /// The address that set the code.
+    pub admin: A,
+    pub block_height: u64,
+    /// The hash of the code that was replaced, if any.
+    pub previous_code_hash: Option<String>
+}
+
+/// Marks a code as deprecated. Instances can still be created from
+/// it until the `sunset` time, if any, after which it is refused.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub struct Deprecation {
+    pub sunset: Option<Timestamp>
+}
+
+impl CodeMetadata {
+    pub fn validate(&self) -> StdResult<()> {
+        if self.name.is_empty() {
+            return Err(StdError::generic_err("Code name cannot be empty."));
+        }
+
+        if let Some(version) = &self.version {
+            // Ignore any pre-release or build suffix.
+            let core = version.split(['-', '+']).next().unwrap_or_default();
+            let parts = core.split('.').collect::<Vec<_>>();
+
+            let is_valid = parts.len() == 3 && parts.iter().all(|x|
+                !x.is_empty() && x.bytes().all(|x| x.is_ascii_digit())
+            );
+
+            if !is_valid {
+                return Err(StdError::generic_err(format!(
+                    "Invalid semantic version: \"{version}\"."
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+namespace!(CodesNs, b"codes");
+const CODE_HISTORY: StaticKey = StaticKey(b"code_history");
+
+// Paused codes keyed by their registry key. The default
+// code is stored under an empty key which is not a valid
+// registry key.
+namespace!(PausedCodesNs, b"paused_codes");
+const PAUSED_CODES: ItemSpace<
+    bool,
+    PausedCodesNs,
+    TypedKey<String>
+> = ItemSpace::new();
+
+// Keyed the same way as PAUSED_CODES.
+namespace!(CodeAllowlistsNs, b"code_allowlists");
+const CODE_ALLOWLISTS: ItemSpace<
+    Vec<CanonicalAddr>,
+    CodeAllowlistsNs,
+    TypedKey<String>
+> = ItemSpace::new();
+
+// Keyed the same way as PAUSED_CODES.
+namespace!(CodeCallbacksNs, b"code_callbacks");
+const CODE_CALLBACKS: ItemSpace<
+    Binary,
+    CodeCallbacksNs,
+    TypedKey<String>
+> = ItemSpace::new();
+
+namespace!(DeprecationsNs, b"code_deprecations");
+const DEPRECATIONS: ItemSpace<
+    Deprecation,
+    DeprecationsNs,
+    TypedKey<u64>
+> = ItemSpace::new();
+
+// Timestamp doesn't implement the Fadroma binary serialization
+// traits so we store it as nanoseconds instead.
+impl FadromaSerialize for Deprecation {
+    #[inline]
+    fn size_hint(&self) -> usize {
+        self.sunset.map(|x| x.nanos()).size_hint()
+    }
+
+    #[inline]
+    fn to_bytes(&self, ser: &mut Serializer) -> bin_serde::Result<()> {
+        self.sunset.map(|x| x.nanos()).to_bytes(ser)
+    }
+}
+
+impl FadromaDeserialize for Deprecation {
+    #[inline]
+    fn from_bytes(de: &mut Deserializer) -> bin_serde::Result<Self> {
+        let sunset = Option::<u64>::from_bytes(de)?;
+
+        Ok(Self { sunset: sunset.map(Timestamp::from_nanos) })
+    }
+}
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Registers the code under `key`, replacing any code already registered
+    /// under it. Scheduled instead while [`Config::code_change_delay`] or
+    /// [`Config::confirm_code_changes`] is set, the same as
+    /// [`GenericFactory::change_contract_code`], so that the codes used by the
+    /// registry cannot be swapped without the timelock or the confirmer either.
+    /// See [`GenericFactory::apply_code_registration`].
+    #[admin::require_admin]
+    pub fn register_code(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        key: String,
+        code: ContractCode,
+        metadata: Option<CodeMetadata>
+    ) -> StdResult<Response> {
+        if key.is_empty() {
+            return Err(StdError::generic_err("Code key cannot be empty."));
+        }
+
+        Self::verify_code(deps.as_ref(), &code)?;
+
+        if let Some(metadata) = &metadata {
+            metadata.validate()?;
+        }
+
+        let config = Self::load_config_with_delay(deps.branch(), &env)?;
+        let entry = RegisteredCode { key: key.clone(), code, metadata };
+
+        if config.code_change_delay > 0 || config.confirm_code_changes {
+            Self::schedule_code_registration(deps, &env, &info.sender, entry, &config)?;
+
+            return Ok(Response::default());
+        }
+
+        Self::cancel_code_registration(deps.branch(), &key)?;
+        Self::save_registered_code(deps, &entry)?;
+
+        Ok(Response::default())
+    }
+
+    #[inline]
+    pub(crate) fn save_registered_code(deps: DepsMut, entry: &RegisteredCode) -> StdResult<()> {
+        Self::codes().insert(deps.storage, &entry.key, entry)?;
+
+        Ok(())
+    }
+
+    #[admin::require_admin]
+    pub fn set_code_metadata(
+        deps: DepsMut,
+        info: MessageInfo,
+        key: String,
+        metadata: Option<CodeMetadata>
+    ) -> StdResult<Response> {
+        if let Some(metadata) = &metadata {
+            metadata.validate()?;
+        }
+
+        let mut codes = Self::codes();
+        let Some(mut entry) = codes.get(deps.storage, &key)? else {
+            return Err(Self::code_not_found(&key));
+        };
+
+        entry.metadata = metadata;
+        codes.insert(deps.storage, &key, &entry)?;
+
+        Ok(Response::default())
+    }
+
+    /// Removes the code registered under `key` and
+    /// cancels any registration pending under it.
+    #[admin::require_admin]
+    pub fn remove_code(
+        mut deps: DepsMut,
+        info: MessageInfo,
+        key: String
+    ) -> StdResult<Response> {
+        let was_pending = Self::cancel_code_registration(deps.branch(), &key)?;
+
+        if !Self::codes().remove(deps.storage, &key)? && !was_pending {
+            return Err(Self::code_not_found(&key));
+        }
+
+        PAUSED_CODES.remove(deps.storage, &key);
+        CODE_ALLOWLISTS.remove(deps.storage, &key);
+        CODE_CALLBACKS.remove(deps.storage, &key);
+
+        Ok(Response::default())
+    }
+
+    /// Deprecates the code stored in the registry under `code_key` or the
+    /// default code if [`None`]. Deprecation applies to the code ID, so every
+    /// registry entry pointing to the same code is affected.
+    #[admin::require_admin]
+    pub fn deprecate_code(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        code_key: Option<String>,
+        sunset: Option<Timestamp>
+    ) -> StdResult<Response> {
+        if matches!(sunset, Some(sunset) if sunset <= env.block.time) {
+            return Err(StdError::generic_err("Sunset time must be in the future."));
+        }
+
+        let code = Self::load_code(deps.as_ref(), code_key.as_ref())?;
+        DEPRECATIONS.save(deps.storage, &code.id, &Deprecation { sunset })?;
+
+        Ok(Response::default())
+    }
+
+    #[admin::require_admin]
+    pub fn undeprecate_code(
+        deps: DepsMut,
+        info: MessageInfo,
+        code_key: Option<String>
+    ) -> StdResult<Response> {
+        let code = Self::load_code(deps.as_ref(), code_key.as_ref())?;
+        DEPRECATIONS.remove(deps.storage, &code.id);
+
+        Ok(Response::default())
+    }
+
+    /// Pauses or resumes creating instances from the code stored in the
+    /// registry under `code_key` or the default code if [`None`].
+    #[admin::require_admin]
+    pub fn set_code_paused(
+        deps: DepsMut,
+        info: MessageInfo,
+        code_key: Option<String>,
+        paused: bool
+    ) -> StdResult<Response> {
+        // Make sure that the code exists.
+        Self::load_code(deps.as_ref(), code_key.as_ref())?;
+
+        let key = code_key.unwrap_or_default();
+
+        if paused {
+            PAUSED_CODES.save(deps.storage, &key, &true)?;
+        } else {
+            PAUSED_CODES.remove(deps.storage, &key);
+        }
+
+        Ok(Response::default())
+    }
+
+    #[inline]
+    pub fn is_code_paused(deps: Deps, code_key: Option<&String>) -> StdResult<bool> {
+        let key = code_key.cloned().unwrap_or_default();
+
+        Ok(PAUSED_CODES.load(deps.storage, &key)?.unwrap_or_default())
+    }
+
+    /// Fails with [`FactoryError::CodePaused`] if creating instances
+    /// from the given code was paused by the admin.
+    pub fn assert_code_not_paused(deps: Deps, code_key: Option<&String>) -> StdResult<()> {
+        if Self::is_code_paused(deps, code_key)? {
+            return Err(FactoryError::CodePaused { code_key: code_key.cloned() }.into());
+        }
+
+        Ok(())
+    }
+
+    #[admin::require_admin]
+    pub fn set_code_allowlist(
+        deps: DepsMut,
+        info: MessageInfo,
+        code_key: Option<String>,
+        addresses: Option<Vec<String>>
+    ) -> StdResult<Response> {
+        Self::load_code(deps.as_ref(), code_key.as_ref())?;
+
+        let key = code_key.unwrap_or_default();
+
+        match addresses {
+            Some(addresses) => {
+                let addresses = addresses.iter()
+                    .map(|x| x.as_str().canonize(deps.api))
+                    .collect::<StdResult<Vec<_>>>()?;
+
+                CODE_ALLOWLISTS.save(deps.storage, &key, &addresses)?;
+            },
+            None => CODE_ALLOWLISTS.remove(deps.storage, &key)
+        }
+
+        Ok(Response::default())
+    }
+
+    pub fn code_allowlist(deps: Deps, code_key: Option<&String>) -> StdResult<Option<Vec<Addr>>> {
+        let key = code_key.cloned().unwrap_or_default();
+
+        CODE_ALLOWLISTS.load_humanize(deps, &key)
+    }
+
+    /// Sets the message that the factory sends to every instance of the code
+    /// once it is registered, e.g. to finish initializing it with its own
+    /// address known to the factory. The factory is the sender of the message,
+    /// which is why only the admin can set it.
+    #[admin::require_admin]
+    pub fn set_code_callback(
+        deps: DepsMut,
+        info: MessageInfo,
+        code_key: Option<String>,
+        callback: Option<Binary>
+    ) -> StdResult<Response> {
+        Self::load_code(deps.as_ref(), code_key.as_ref())?;
+
+        let key = code_key.unwrap_or_default();
+
+        match callback {
+            Some(callback) => CODE_CALLBACKS.save(deps.storage, &key, &callback)?,
+            None => CODE_CALLBACKS.remove(deps.storage, &key)
+        }
+
+        Ok(Response::default())
+    }
+
+    #[inline]
+    pub fn code_callback(deps: Deps, code_key: Option<&String>) -> StdResult<Option<Binary>> {
+        let key = code_key.cloned().unwrap_or_default();
+
+        CODE_CALLBACKS.load(deps.storage, &key)
+    }
+
+    /// Fails with [`FactoryError::NotAllowedForCode`] if the code has
+    /// an allowlist and `sender` is not in it.
+    pub fn assert_can_create_from(
+        deps: Deps,
+        code_key: Option<&String>,
+        sender: &Addr
+    ) -> StdResult<()> {
+        let key = code_key.cloned().unwrap_or_default();
+
+        let Some(allowlist) = CODE_ALLOWLISTS.load(deps.storage, &key)? else {
+            return Ok(());
+        };
+
+        if !allowlist.contains(&sender.canonize(deps.api)?) {
+            return Err(FactoryError::NotAllowedForCode { code_key: code_key.cloned() }.into());
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn code_deprecation(deps: Deps, code_id: u64) -> StdResult<Option<Deprecation>> {
+        DEPRECATIONS.load(deps.storage, &code_id)
+    }
+
+    /// Fails with [`FactoryError::CodeSunset`] if the code
+    /// is deprecated and its sunset time has passed.
+    pub fn assert_not_sunset(deps: Deps, env: &Env, code: &ContractCode) -> StdResult<()> {
+        let Some(Deprecation { sunset: Some(sunset) }) =
+            DEPRECATIONS.load(deps.storage, &code.id)? else {
+            return Ok(());
+        };
+
+        if env.block.time >= sunset {
+            return Err(FactoryError::CodeSunset { code_id: code.id, sunset }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the code hash is well-formed if [`Config::verify_code_hashes`]
+    /// is set. The code hash cannot be compared against the one stored on chain
+    /// because contracts have no way to query it on Secret Network.
+    pub fn verify_code(deps: Deps, code: &ContractCode) -> StdResult<()> {
+        if !Config::load(deps.storage)?.verify_code_hashes {
+            return Ok(());
+        }
+
+        let hash = &code.code_hash;
+
+        if hash.len() != 64 || !hash.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(StdError::generic_err(format!(
+                "Invalid code hash: \"{hash}\". Expecting 64 hexadecimal characters."
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn list_codes(deps: Deps, pagination: Pagination) ->
+        StdResult<PaginatedResponse<RegisteredCode>>
+    {
+        let codes = Self::codes();
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate(&config, codes.values(deps.storage)?, Ok)
+    }
+
+    #[inline]
+    pub fn code_by_key(deps: Deps, key: String) -> StdResult<Option<RegisteredCode>> {
+        Self::codes().get(deps.storage, &key)
+    }
+
+    /// Loads the code that instances should be created from. This is
+    /// the code stored in the registry under `key` or the default
+    /// code (see [`crate::ExecuteMsg::ChangeContractCode`]) if [`None`].
+    pub fn load_code(deps: Deps, key: Option<&String>) -> StdResult<ContractCode> {
+        match key {
+            Some(key) => {
+                let Some(entry) = Self::codes().get(deps.storage, key)? else {
+                    return Err(Self::code_not_found(key));
+                };
+
+                Ok(entry.code)
+            },
+            None => crate::CONTRACT.load_or_error(deps.storage)
+        }
+    }
+
+    /// Finds the default code or a code in the registry with the given ID.
+    pub fn find_code_by_id(deps: Deps, code_id: u64) -> StdResult<Option<ContractCode>> {
+        if let Some(code) = crate::CONTRACT.load(deps.storage)? {
+            if code.id == code_id {
+                return Ok(Some(code));
+            }
+        }
+
+        for entry in Self::codes().values(deps.storage)? {
+            let entry = entry?;
+
+            if entry.code.id == code_id {
+                return Ok(Some(entry.code));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn code_history(deps: Deps, pagination: Pagination) ->
+        StdResult<PaginatedResponse<CodeChange<Addr>>>
+    {
+        let history = Self::code_history_storage();
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate(&config, history.iter(deps.storage)?, |x| x.humanize(deps.api))
+    }
+
+    /// Appends an entry to the code history and returns the event to emit
+    /// for it. Must be called *before* the new default code is saved.
+    pub(crate) fn record_code_change(
+        deps: DepsMut,
+        env: &Env,
+        admin: &Addr,
+        code: &ContractCode
+    ) -> StdResult<Event> {
+        let previous = crate::CONTRACT.load(deps.storage)?;
+
+        let mut event = Event::new(CODE_CHANGE_EVENT);
+
+        if let Some(previous) = &previous {
+            event = event
+                .add_attribute_plaintext("old_code_id", previous.id.to_string())
+                .add_attribute_plaintext("old_code_hash", &previous.code_hash);
+        }
+
+        let event = event
+            .add_attribute_plaintext("new_code_id", code.id.to_string())
+            .add_attribute_plaintext("new_code_hash", &code.code_hash)
+            .add_attribute_plaintext("admin", admin);
+
+        Self::code_history_storage().push(deps.storage, &CodeChange {
+            code: code.clone(),
+            admin: admin.canonize(deps.api)?,
+            block_height: env.block.height,
+            previous_code_hash: previous.map(|x| x.code_hash)
+        })?;
+
+        Ok(event)
+    }
+
+    #[inline]
+    fn code_not_found(key: &str) -> StdError {
+        StdError::generic_err(format!("No code registered under key: {key}."))
+    }
+
+    #[inline]
+    fn code_history_storage() -> IterableStorage<CodeChange<CanonicalAddr>, StaticKey> {
+        IterableStorage::new(CODE_HISTORY)
+    }
+
+    #[inline]
+    fn codes<'a>() -> Map<TypedKey<'a, String>, RegisteredCode, CodesNs> {
+        Map::new()
+    }
+}
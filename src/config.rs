@@ -0,0 +1,265 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, Env,
+        MessageInfo, Storage, Addr, Api
+    },
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::SingleItem,
+    admin,
+    killswitch,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, RateLimit, AllowedDenom};
+
+/// Runtime settings of the factory which the admin can change
+/// using [`crate::ExecuteMsg::UpdateConfig`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct Config {
+    /// If `true`, instance data queries also fail when the
+    /// contract is not operational (see [`killswitch`]).
+    #[serde(default)]
+    pub pause_queries: bool,
+    /// The number of seconds that must pass before a code change
+    /// made with [`crate::ExecuteMsg::ChangeContractCode`] can be
+    /// applied. If zero, the change is applied immediately. Lowering
+    /// it only takes effect after the current delay has passed. See
+    /// [`crate::QueryMsg::PendingDelayDecrease`].
+    #[serde(default)]
+    pub code_change_delay: u64,
+    /// If `true`, code changes must be confirmed using
+    /// [`crate::ExecuteMsg::ConfirmCodeChange`] before they are applied.
+    /// While a confirmer is set, changing this or the confirmer must be
+    /// accepted by the confirmer (see [`crate::ConfirmerChange`]).
+    #[serde(default)]
+    pub confirm_code_changes: bool,
+    /// The address that confirms code changes. If [`None`], the admin
+    /// confirms them but not earlier than the block after the proposal.
+    #[serde(default)]
+    pub code_change_confirmer: Option<Addr>,
+    /// If `true`, code hashes are checked to be well-formed (64 hex
+    /// characters) before a code is saved. See [`GenericFactory::verify_code`].
+    #[serde(default)]
+    pub verify_code_hashes: bool,
+    /// If `true`, the [`crate::InstanceConfig`] that each instance is
+    /// created with is stored and can be queried with
+    /// [`crate::QueryMsg::InstanceInitMsg`].
+    #[serde(default)]
+    pub store_init_msgs: bool,
+    /// Limits how many instances an address can create in a given
+    /// time. Not limited if [`None`].
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// The maximum number of instances that the factory holds. Removed
+    /// instances don't count towards it. Not limited if [`None`].
+    #[serde(default)]
+    pub max_instances: Option<u64>,
+    /// The maximum number of instances that the factory holds
+    /// from a single creator. Not limited if [`None`].
+    #[serde(default)]
+    pub max_instances_per_creator: Option<u64>,
+    /// If set, the labels of all instances are generated from this
+    /// template and creators cannot choose their own. The placeholders
+    /// `{creator}`, `{seq}` (see [`crate::Instance::seq`]), `{time}` (in
+    /// seconds) and `{code_id}` are replaced with the values of the instance.
+    /// Must contain `{seq}` so that the labels are unique.
+    #[serde(default)]
+    pub label_template: Option<String>,
+    /// The denoms that instances can be sent on creation and the
+    /// amounts allowed of each. Any funds can be sent if [`None`].
+    #[serde(default)]
+    pub allowed_denoms: Option<Vec<AllowedDenom>>,
+    /// The number of seconds after which instance proposals expire
+    /// and can no longer be approved. They never expire if [`None`].
+    #[serde(default)]
+    pub proposal_ttl: Option<u64>,
+    /// If `true`, instantiations are replied to even if they fail, in which
+    /// case the failure is recorded instead of failing the transaction
+    /// that created the instance. See [`crate::QueryMsg::FailedInstantiations`].
+    #[serde(default)]
+    pub track_failures: bool,
+    /// If `true`, pausing the factory or setting it to migrate using its
+    /// killswitch also does so for every registered instance. See
+    /// [`crate::ExecuteMsg::ContinueKillswitchPropagation`].
+    #[serde(default)]
+    pub propagate_killswitch: bool,
+    /// If `true`, new instances are given a one-time token as the
+    /// `registration_token` field of their instantiate message and stay
+    /// [`crate::InstanceStatus::Pending`] until they send it back with
+    /// [`crate::ExecuteMsg::RegisterSelf`].
+    #[serde(default)]
+    pub require_self_registration: bool,
+    /// If `true`, the address that a child sets in [`crate::InstantiateReplyData`]
+    /// is accepted even if the chain doesn't report the address of the new
+    /// instance in the reply so that it can't be checked. Such replies
+    /// fail otherwise.
+    #[serde(default)]
+    pub allow_unverified_addresses: bool,
+    /// The maximum number of entries returned in a single page.
+    #[serde(default = "Config::default_max_page_limit")]
+    pub max_page_limit: u8,
+    /// The number of entries returned when a page is requested with
+    /// a `limit` of zero. It never exceeds [`Config::max_page_limit`].
+    #[serde(default = "Config::default_default_page_limit")]
+    pub default_page_limit: u8
+}
+
+namespace!(ConfigNs, b"config");
+const CONFIG: SingleItem<Config, ConfigNs> = SingleItem::new();
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pause_queries: false,
+            code_change_delay: 0,
+            confirm_code_changes: false,
+            code_change_confirmer: None,
+            verify_code_hashes: false,
+            store_init_msgs: false,
+            rate_limit: None,
+            max_instances: None,
+            max_instances_per_creator: None,
+            label_template: None,
+            allowed_denoms: None,
+            proposal_ttl: None,
+            track_failures: false,
+            propagate_killswitch: false,
+            require_self_registration: false,
+            allow_unverified_addresses: false,
+            max_page_limit: Self::default_max_page_limit(),
+            default_page_limit: Self::default_default_page_limit()
+        }
+    }
+}
+
+impl Config {
+    #[inline]
+    pub fn load(storage: &dyn Storage) -> StdResult<Self> {
+        CONFIG.load_or_default(storage)
+    }
+
+    #[inline]
+    pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
+        CONFIG.save(storage, self)
+    }
+
+    /// The page size to use for a requested `limit`.
+    pub fn page_limit(&self, limit: u8) -> u8 {
+        match limit {
+            0 => self.default_page_limit.min(self.max_page_limit),
+            limit => limit.min(self.max_page_limit)
+        }
+    }
+
+    #[inline]
+    fn default_max_page_limit() -> u8 {
+        crate::Pagination::MAX_LIMIT
+    }
+
+    #[inline]
+    fn default_default_page_limit() -> u8 {
+        crate::Pagination::DEFAULT_LIMIT
+    }
+
+    pub fn validate(&self, api: &dyn Api) -> StdResult<()> {
+        if let Some(confirmer) = &self.code_change_confirmer {
+            api.addr_validate(confirmer.as_str())?;
+        }
+
+        if let Some(limit) = &self.rate_limit {
+            if limit.max_creations == 0 || limit.window == 0 {
+                return Err(StdError::generic_err(
+                    "Rate limit must allow at least one creation in a non-empty window."
+                ));
+            }
+        }
+
+        if let Some(template) = &self.label_template {
+            let rest = expand_label_template(template, &Addr::unchecked(""), 0, 0, 0);
+
+            if rest.contains(['{', '}']) {
+                return Err(StdError::generic_err("Unknown placeholder in label template."));
+            }
+
+            // Labels must be unique and the sequence number
+            // is the only placeholder that never repeats.
+            if !template.contains("{seq}") {
+                return Err(StdError::generic_err("Label template must contain {seq}."));
+            }
+        }
+
+        self.validate_allowed_denoms()?;
+
+        if self.max_page_limit == 0 {
+            return Err(StdError::generic_err("Maximum page limit must be greater than zero."));
+        }
+
+        if self.default_page_limit == 0 {
+            return Err(StdError::generic_err("Default page limit must be greater than zero."));
+        }
+
+        Ok(())
+    }
+}
+
+/// Replaces the placeholders of [`Config::label_template`].
+pub(crate) fn expand_label_template(
+    template: &str,
+    creator: &Addr,
+    seq: u64,
+    time: u64,
+    code_id: u64
+) -> String {
+    template
+        .replace("{creator}", creator.as_str())
+        .replace("{seq}", &seq.to_string())
+        .replace("{time}", &time.to_string())
+        .replace("{code_id}", &code_id.to_string())
+}
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Updates the config. A lower [`Config::code_change_delay`]
+    /// is scheduled instead of being applied right away and changes
+    /// to the code change confirmation wait for the confirmer.
+    #[admin::require_admin]
+    pub fn update_config(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        mut config: Config
+    ) -> StdResult<Response> {
+        config.validate(deps.api)?;
+
+        let current = Self::load_config_with_delay(deps.branch(), &env)?;
+        Self::schedule_delay_decrease(deps.branch(), &env, &current, &mut config)?;
+        Self::schedule_confirmer_change(deps.branch(), &current, &mut config)?;
+
+        config.save(deps.storage)?;
+
+        Ok(Response::default())
+    }
+
+    #[inline]
+    pub fn config(deps: Deps) -> StdResult<Config> {
+        Config::load(deps.storage)
+    }
+
+    /// Fails if [`Config::pause_queries`] is set and the
+    /// contract is not operational. Call this before
+    /// serving any instance data.
+    pub fn assert_can_query(deps: Deps) -> StdResult<()> {
+        if Config::load(deps.storage)?.pause_queries {
+            killswitch::assert_is_operational(deps)?;
+        }
+
+        Ok(())
+    }
+}
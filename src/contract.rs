@@ -0,0 +1,48 @@
+//! A ready-to-deploy factory contract. The child instantiate message
+//! is accepted as [`Binary`] and passed through to the child as is,
+//! so the same wasm can be used to create instances of any contract.
+//! No extra data is stored for instances.
+//! *Feature flag: `entry-points`*
+
+use fadroma::cosmwasm_std::{
+    StdResult, Response, Deps, DepsMut, MessageInfo, Env, Reply, Binary, Empty
+};
+
+use crate::{GenericFactory, QueryMsg};
+
+pub type Factory = GenericFactory<Binary, Empty>;
+pub type InstantiateMsg = crate::InstantiateMsg<Binary>;
+pub type ExecuteMsg = crate::ExecuteMsg<Binary>;
+
+pub fn instantiate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg
+) -> StdResult<Response> {
+    Factory::instantiate(deps, env, info, msg)
+}
+
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg
+) -> StdResult<Response> {
+    Factory::execute(deps, env, info, msg)
+}
+
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    Factory::query(deps, env, msg)
+}
+
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> StdResult<Response> {
+    Factory::reply(deps, env, reply)
+}
+
+fadroma::entrypoint! {
+    init: instantiate,
+    execute: execute,
+    query: query,
+    reply: reply
+}
@@ -0,0 +1,79 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{StdResult, Deps, Storage, CanonicalAddr, Timestamp},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{ItemSpace, TypedKey},
+    core::Canonize,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks};
+
+/// Statistics about the instances created by an address.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct CreatorStats {
+    /// The number of instances created.
+    pub instances: u64,
+    pub first_created_at: Timestamp,
+    pub last_created_at: Timestamp
+}
+
+/// Timestamps are in nanoseconds since Timestamp doesn't
+/// implement the Fadroma binary serialization.
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct StoredCreatorStats {
+    instances: u64,
+    first_created_at: u64,
+    last_created_at: u64
+}
+
+namespace!(CreatorStatsNs, b"creator_stats");
+const CREATOR_STATS: ItemSpace<
+    StoredCreatorStats,
+    CreatorStatsNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Returns [`None`] if the address hasn't created any instances.
+    pub fn creator_stats(deps: Deps, creator: String) -> StdResult<Option<CreatorStats>> {
+        let creator = creator.as_str().canonize(deps.api)?;
+
+        let stats = CREATOR_STATS.load(deps.storage, &creator)?.map(|x| CreatorStats {
+            instances: x.instances,
+            first_created_at: Timestamp::from_nanos(x.first_created_at),
+            last_created_at: Timestamp::from_nanos(x.last_created_at)
+        });
+
+        Ok(stats)
+    }
+
+    /// Updates the statistics of `creator` for a newly registered instance.
+    pub(crate) fn record_creation(
+        storage: &mut dyn Storage,
+        creator: &CanonicalAddr,
+        time: Timestamp
+    ) -> StdResult<()> {
+        let stats = match CREATOR_STATS.load(storage, creator)? {
+            Some(stats) => StoredCreatorStats {
+                instances: stats.instances + 1,
+                last_created_at: time.nanos(),
+                ..stats
+            },
+            None => StoredCreatorStats {
+                instances: 1,
+                first_created_at: time.nanos(),
+                last_created_at: time.nanos()
+            }
+        };
+
+        CREATOR_STATS.save(storage, creator, &stats)
+    }
+}
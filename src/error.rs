@@ -0,0 +1,142 @@
+//! Typed errors returned by the factory. They are converted into
+//! [`StdError::GenericErr`] so that they can be returned from any
+//! function that returns [`fadroma::cosmwasm_std::StdResult`].
+
+use std::fmt;
+
+use fadroma::cosmwasm_std::{StdError, Timestamp, Coin, Uint128};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum FactoryError {
+    /// The code was deprecated and its sunset time has passed.
+    CodeSunset {
+        code_id: u64,
+        sunset: Timestamp
+    },
+    /// Creating instances from the code was paused by the admin.
+    CodePaused {
+        code_key: Option<String>
+    },
+    /// The sender is not in the allowlist of the code.
+    NotAllowedForCode {
+        code_key: Option<String>
+    },
+    /// The auth mode is [`crate::AuthMode::Allowlist`] and
+    /// the sender is not in the creator allowlist.
+    NotAllowedToCreate,
+    /// The sender was blocked from creating instances by the admin.
+    CreatorBlocked,
+    /// The sender hasn't set a viewing key for the gate token.
+    TokenGateKeyMissing,
+    /// The sender doesn't hold the gate token.
+    NotTokenHolder,
+    /// Less than the creation fee and the funds of the instance was sent.
+    InsufficientFunds {
+        expected: Coin
+    },
+    /// The sender created too many instances recently.
+    /// See [`crate::Config::rate_limit`].
+    RateLimited {
+        /// The time in seconds after which the sender can create again.
+        retry_after: u64
+    },
+    /// The factory holds [`crate::Config::max_instances`] instances.
+    InstanceCapReached {
+        max: u64
+    },
+    /// The sender created [`crate::Config::max_instances_per_creator`] instances.
+    CreatorCapReached {
+        max: u64
+    },
+    /// Less than the creation fee was paid in the fee token.
+    InsufficientTokenPayment {
+        expected: Uint128
+    },
+    /// The instance was sent a denom that is not in [`crate::Config::allowed_denoms`].
+    DenomNotAllowed {
+        denom: String
+    },
+    /// The instance was sent less than the minimum amount of a denom.
+    FundsBelowMinimum {
+        min: Coin
+    },
+    /// The instance was sent more than the maximum amount of a denom.
+    FundsAboveMaximum {
+        max: Coin
+    },
+    /// A reply that doesn't belong to an instance pending creation.
+    UnsolicitedReply {
+        id: u64
+    },
+    /// The address set by the child in [`crate::InstantiateReplyData`]
+    /// is not the one that the chain reported for it.
+    AddressMismatch {
+        claimed: String,
+        actual: String
+    },
+    /// The chain didn't report the address of the instance so the one set by
+    /// the child in [`crate::InstantiateReplyData`] couldn't be checked.
+    /// See [`crate::Config::allow_unverified_addresses`].
+    UnverifiedAddress {
+        claimed: String
+    }
+}
+
+impl fmt::Display for FactoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CodeSunset { code_id, sunset } => write!(
+                f,
+                "Code with ID {code_id} is deprecated and cannot be instantiated after {}.",
+                sunset.seconds()
+            ),
+            Self::CodePaused { code_key: Some(key) } =>
+                write!(f, "Creating instances from code \"{key}\" is paused."),
+            Self::CodePaused { code_key: None } =>
+                write!(f, "Creating instances from the default code is paused."),
+            Self::NotAllowedForCode { code_key: Some(key) } =>
+                write!(f, "Not allowed to create instances from code \"{key}\"."),
+            Self::NotAllowedForCode { code_key: None } =>
+                write!(f, "Not allowed to create instances from the default code."),
+            Self::NotAllowedToCreate =>
+                write!(f, "Not allowed to create instances."),
+            Self::CreatorBlocked =>
+                write!(f, "The sender is blocked from creating instances."),
+            Self::TokenGateKeyMissing =>
+                write!(f, "Set a viewing key for the gate token with SetTokenGateKey first."),
+            Self::NotTokenHolder =>
+                write!(f, "The sender doesn't hold the token required to create instances."),
+            Self::InsufficientFunds { expected } =>
+                write!(f, "Expected at least {expected} to be sent."),
+            Self::RateLimited { retry_after } =>
+                write!(f, "Too many instances created, try again after {retry_after}."),
+            Self::InstanceCapReached { max } =>
+                write!(f, "The maximum of {max} instances has been reached."),
+            Self::CreatorCapReached { max } =>
+                write!(f, "The maximum of {max} instances per creator has been reached."),
+            Self::InsufficientTokenPayment { expected } =>
+                write!(f, "Expected at least {expected} of the fee token to be sent."),
+            Self::DenomNotAllowed { denom } =>
+                write!(f, "Instances cannot be sent \"{denom}\"."),
+            Self::FundsBelowMinimum { min } =>
+                write!(f, "Instances must be sent at least {min}."),
+            Self::FundsAboveMaximum { max } =>
+                write!(f, "Instances cannot be sent more than {max}."),
+            Self::UnsolicitedReply { id } =>
+                write!(f, "Reply {id} doesn't belong to an instance pending creation."),
+            Self::AddressMismatch { claimed, actual } =>
+                write!(f, "The instance claims to be at {claimed} but was instantiated at {actual}."),
+            Self::UnverifiedAddress { claimed } =>
+                write!(f, "The instance claims to be at {claimed} but the chain didn't report its address.")
+        }
+    }
+}
+
+impl std::error::Error for FactoryError { }
+
+impl From<FactoryError> for StdError {
+    #[inline]
+    fn from(err: FactoryError) -> Self {
+        StdError::generic_err(err.to_string())
+    }
+}
@@ -0,0 +1,212 @@
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, Env, MessageInfo,
+        Addr, CanonicalAddr, Binary, Timestamp, BankMsg, to_binary
+    },
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{StaticKey, iterable::IterableStorage},
+    core::{Humanize, Canonize},
+    crypto::sha_256,
+    admin
+};
+
+use crate::{
+    GenericFactory, ExtraData, FactoryHooks, Config, InstanceConfig,
+    PendingInstance, Pagination, PaginatedResponse, pending
+};
+
+/// The attribute of the reply response that holds the ID
+/// of the [`FailedInstantiation`] that was recorded.
+pub const FAILURE_ID_ATTR: &str = "fadroma_failure_id";
+
+/// An instantiation that failed while [`Config::track_failures`] was set.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct FailedInstantiation {
+    pub id: u64,
+    pub creator: Addr,
+    pub code_id: u64,
+    pub label: String,
+    /// The SHA-256 hash of the JSON serialized [`crate::InstanceConfig`]
+    /// that the instance was created with, its message encoded as [`Binary`].
+    pub config_hash: Binary,
+    /// The error returned by the child.
+    pub error: String,
+    pub failed_at: Timestamp,
+    /// The config that the instance was created with.
+    pub config: InstanceConfig<Binary>,
+    /// Whether [`crate::ExecuteMsg::RetryInstantiation`]
+    /// was called for this failure.
+    pub retried: bool
+}
+
+/// The failure time is in nanoseconds since Timestamp
+/// doesn't implement the Fadroma binary serialization.
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct StoredFailure {
+    creator: CanonicalAddr,
+    code_id: u64,
+    label: String,
+    config_hash: Binary,
+    error: String,
+    failed_at: u64,
+    config: InstanceConfig<Binary>,
+    retried: bool
+}
+
+// Failures are never removed so that their index is their ID.
+const FAILURES: StaticKey = StaticKey(b"failed_instantiations");
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Records the failed instantiation of the instance that the reply with `id` belongs to,
+    /// forfeits its deposit, if any, and sends the funds that were received
+    /// for the instance back to its creator. Nothing else is refunded.
+    pub(crate) fn handle_failure(deps: DepsMut, id: u64, error: String) -> StdResult<Response> {
+        let pending: PendingInstance = pending::take(deps.storage, id)?;
+
+        if let Some(deposit) = &pending.deposit {
+            Self::forfeit_deposit(deps.storage, deposit)?;
+        }
+
+        let Some(config) = pending.config else {
+            return Err(StdError::generic_err(
+                "The config of the failed instance wasn't kept."
+            ));
+        };
+
+        let refund = if pending.funds.is_empty() {
+            None
+        } else {
+            Some(BankMsg::Send {
+                to_address: deps.api.addr_humanize(&pending.created.creator)?.into_string(),
+                amount: pending.funds
+            })
+        };
+
+        let id = Self::failures().push(deps.storage, &StoredFailure {
+            creator: pending.created.creator,
+            code_id: pending.code.id,
+            label: pending.label,
+            config_hash: Binary::from(sha_256(to_binary(&config)?.as_slice())),
+            error,
+            failed_at: pending.created.block_time.nanos(),
+            config,
+            retried: false
+        })?;
+
+        Ok(Response::default()
+            .add_messages(refund)
+            .add_attribute_plaintext(FAILURE_ID_ATTR, id.to_string())
+        )
+    }
+
+    /// Lists the failed instantiations from oldest to newest.
+    pub fn failed_instantiations(
+        deps: Deps,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<FailedInstantiation>> {
+        let config = Config::load(deps.storage)?;
+        let iter = Self::failures().iter(deps.storage)?.enumerate();
+
+        pagination.paginate(
+            &config,
+            iter.map(|(id, x)| x.map(|x| (id as u64, x))),
+            |(id, x)| Ok(FailedInstantiation {
+                id,
+                creator: x.creator.humanize(deps.api)?,
+                code_id: x.code_id,
+                label: x.label,
+                config_hash: x.config_hash,
+                error: x.error,
+                failed_at: Timestamp::from_nanos(x.failed_at),
+                config: x.config,
+                retried: x.retried
+            })
+        )
+    }
+
+    #[inline]
+    fn failures() -> IterableStorage<StoredFailure, StaticKey> {
+        IterableStorage::new(FAILURES)
+    }
+}
+
+impl<
+    MSG: Serialize + DeserializeOwned + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Creates the instance of a failed instantiation again with the same
+    /// config on behalf of its original creator, who must still be allowed to
+    /// create instances. Can only be called by the admin or the creator and
+    /// only once for each failure. No fee is charged
+    /// but the funds of the instance must be sent again since they were
+    /// refunded when it failed.
+    pub fn retry_instantiation(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        failure_id: u64
+    ) -> StdResult<Response> {
+        let mut failures = Self::failures();
+
+        let Some(mut failure) = failures.get(deps.storage, failure_id)? else {
+            return Err(StdError::generic_err(
+                format!("No failed instantiation with ID: {failure_id}.")
+            ));
+        };
+
+        if admin::assert(deps.as_ref(), &info).is_err() &&
+            failure.creator != info.sender.as_str().canonize(deps.api)?
+        {
+            return Err(StdError::generic_err("Unauthorized"));
+        }
+
+        if failure.retried {
+            return Err(StdError::generic_err(
+                format!("Failed instantiation {failure_id} has already been retried.")
+            ));
+        }
+
+        let creator = MessageInfo {
+            sender: failure.creator.clone().humanize(deps.api)?,
+            funds: info.funds.clone()
+        };
+
+        // The creator may have been blocked or lost the right to create since.
+        Self::assert_can_create(deps.as_ref(), &creator)?;
+
+        // The fee was paid for the first attempt but its deposit was forfeited.
+        let refund = Self::charge_fee(deps.branch(), &info, &failure.config.funds, 0, 1)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
+
+        failure.retried = true;
+        failures.set(deps.storage, failure_id, &failure)?;
+
+        let config = failure.config;
+
+        let resp = match Self::decode_msg(&config.msg) {
+            Ok(msg) => Self::instantiate_child(deps, env, creator, InstanceConfig {
+                msg,
+                funds: config.funds,
+                code_key: config.code_key,
+                label: config.label,
+                extra: config.extra
+            }, deposit)?,
+            // Created from a code slot or a raw message, which
+            // was checked by the hooks or the admin respectively.
+            Err(_) => Self::dispatch_child(deps, env, creator, config, true, deposit)?
+        };
+
+        Ok(resp.add_messages(refund))
+    }
+}
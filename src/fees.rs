@@ -0,0 +1,417 @@
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, MessageInfo, Env,
+        Storage, Addr, CanonicalAddr, Coin, BankMsg, WasmMsg, Uint128, Binary,
+        to_binary, from_binary
+    },
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{SingleItem, ItemSpace, TypedKey},
+    core::{ContractLink, Canonize, Humanize},
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, FactoryError, InstanceConfig};
+
+/// The fee charged for creating an instance, which must be sent
+/// along with the funds that the instance is created with.
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Default, Debug)]
+pub struct FeeConfig {
+    /// No fee is charged if [`None`].
+    pub fee: Option<Coin>,
+    /// The address that can withdraw the collected fees.
+    /// The admin if [`None`].
+    pub collector: Option<Addr>,
+    /// Allows paying the fee in a SNIP-20 token instead by sending it
+    /// to the factory along with the instance config.
+    /// See [`crate::ExecuteMsg::Receive`].
+    #[serde(default)]
+    pub token_fee: Option<TokenFee>,
+    /// A deposit that creators must send along with the fee. It is
+    /// refunded once the instance is created and is forfeited to the
+    /// fee collector if it isn't. No deposit is required if [`None`].
+    #[serde(default)]
+    pub deposit: Option<Coin>
+}
+
+/// The creation fee when it is paid in a SNIP-20 token.
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct TokenFee {
+    pub token: ContractLink<Addr>,
+    pub amount: Uint128
+}
+
+/// A deposit locked by `payer` until the instance it was paid for is created.
+#[derive(FadromaSerialize, FadromaDeserialize, Clone, Debug)]
+pub(crate) struct Deposit {
+    pub payer: CanonicalAddr,
+    pub amount: Coin
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Snip20Msg {
+    RegisterReceive { code_hash: String },
+    Transfer { recipient: String, amount: Uint128 }
+}
+
+namespace!(FeeConfigNs, b"fee_config");
+const FEE_CONFIG: SingleItem<FeeConfig, FeeConfigNs> = SingleItem::new();
+
+namespace!(CollectedFeesNs, b"collected_fees");
+const COLLECTED_FEES: SingleItem<Vec<Coin>, CollectedFeesNs> = SingleItem::new();
+
+// The discount in percent granted to each address. 100 exempts it from the fee.
+namespace!(FeeDiscountsNs, b"fee_discounts");
+const FEE_DISCOUNTS: ItemSpace<
+    u8,
+    FeeDiscountsNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+namespace!(CollectedTokenFeesNs, b"collected_token_fees");
+const COLLECTED_TOKEN_FEES: SingleItem<Uint128, CollectedTokenFeesNs> = SingleItem::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Registers the factory as a receiver of the fee token, if any. The
+    /// fee token cannot be changed while there are uncollected token fees.
+    #[admin::require_admin]
+    pub fn set_fee_config(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: FeeConfig
+    ) -> StdResult<Response> {
+        if let Some(collector) = &config.collector {
+            deps.api.addr_validate(collector.as_str())?;
+        }
+
+        let current = FEE_CONFIG.load_or_default(deps.storage)?.token_fee.map(|x| x.token);
+        let token = config.token_fee.as_ref().map(|x| &x.token);
+
+        if current.as_ref() != token &&
+            !COLLECTED_TOKEN_FEES.load_or_default(deps.storage)?.is_zero()
+        {
+            return Err(StdError::generic_err(
+                "Withdraw the collected token fees before changing the fee token."
+            ));
+        }
+
+        let mut resp = Response::default();
+
+        if let Some(token) = token {
+            deps.api.addr_validate(token.address.as_str())?;
+
+            resp = resp.add_message(WasmMsg::Execute {
+                contract_addr: token.address.to_string(),
+                code_hash: token.code_hash.clone(),
+                msg: to_binary(&Snip20Msg::RegisterReceive {
+                    code_hash: env.contract.code_hash
+                })?,
+                funds: vec![]
+            });
+        }
+
+        FEE_CONFIG.save(deps.storage, &config)?;
+
+        Ok(resp)
+    }
+
+    #[inline]
+    pub fn fee_config(deps: Deps) -> StdResult<FeeConfig> {
+        FEE_CONFIG.load_or_default(deps.storage)
+    }
+
+    #[inline]
+    pub fn collected_fees(deps: Deps) -> StdResult<Vec<Coin>> {
+        COLLECTED_FEES.load_or_default(deps.storage)
+    }
+
+    #[inline]
+    pub fn collected_token_fees(deps: Deps) -> StdResult<Uint128> {
+        COLLECTED_TOKEN_FEES.load_or_default(deps.storage)
+    }
+
+    /// Grants `addr` a discount of `percent` on the creation fee, both
+    /// in the native coin and in the fee token. A discount of 100 exempts
+    /// it from the fee and a discount of 0 removes it.
+    #[admin::require_admin]
+    pub fn set_fee_discount(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+        percent: u8
+    ) -> StdResult<Response> {
+        if percent > 100 {
+            return Err(StdError::generic_err("Discount cannot be more than 100 percent."));
+        }
+
+        let addr = addr.as_str().canonize(deps.api)?;
+
+        if percent == 0 {
+            FEE_DISCOUNTS.remove(deps.storage, &addr);
+        } else {
+            FEE_DISCOUNTS.save(deps.storage, &addr, &percent)?;
+        }
+
+        Ok(Response::default())
+    }
+
+    pub fn fee_discount(deps: Deps, addr: String) -> StdResult<u8> {
+        let addr = addr.as_str().canonize(deps.api)?;
+
+        Ok(FEE_DISCOUNTS.load(deps.storage, &addr)?.unwrap_or_default())
+    }
+
+    /// Sends the collected fees to the fee collector. Can be called
+    /// by the collector or by the admin if there is none.
+    pub fn withdraw_fees(deps: DepsMut, info: MessageInfo) -> StdResult<Response> {
+        let config = FEE_CONFIG.load_or_default(deps.storage)?;
+
+        let recipient = match config.collector {
+            Some(collector) if collector == info.sender => collector,
+            Some(_) => return Err(StdError::generic_err("Unauthorized")),
+            None => {
+                admin::assert(deps.as_ref(), &info)?;
+
+                info.sender
+            }
+        };
+
+        let fees = COLLECTED_FEES.load_or_default(deps.storage)?;
+        COLLECTED_FEES.remove(deps.storage);
+
+        let token_fees = COLLECTED_TOKEN_FEES.load_or_default(deps.storage)?;
+        COLLECTED_TOKEN_FEES.remove(deps.storage);
+
+        let mut resp = Response::default();
+
+        if let (Some(fee), false) = (config.token_fee, token_fees.is_zero()) {
+            resp = resp.add_message(WasmMsg::Execute {
+                contract_addr: fee.token.address.into_string(),
+                code_hash: fee.token.code_hash,
+                msg: to_binary(&Snip20Msg::Transfer {
+                    recipient: recipient.to_string(),
+                    amount: token_fees
+                })?,
+                funds: vec![]
+            });
+        }
+
+        if !fees.is_empty() {
+            resp = resp.add_message(BankMsg::Send {
+                to_address: recipient.into_string(),
+                amount: fees
+            });
+        }
+
+        Ok(resp)
+    }
+
+    /// Checks that the sent funds cover the creation fee for the given
+    /// number of `creations`, the number of `deposits` to lock and the
+    /// `funds` that the instances are created with, and records the fee
+    /// as collected. Returns a refund to the sender of anything sent above that.
+    pub(crate) fn charge_fee(
+        deps: DepsMut,
+        info: &MessageInfo,
+        funds: &[Coin],
+        creations: u32,
+        deposits: u32
+    ) -> StdResult<Option<BankMsg>> {
+        let config = FEE_CONFIG.load_or_default(deps.storage)?;
+        let mut required = funds.to_vec();
+
+        let fee = config.fee.map(|mut fee| -> StdResult<Coin> {
+            fee.amount = Self::discounted_fee(deps.as_ref(), &info.sender, fee.amount)? *
+                Uint128::from(creations);
+
+            Ok(fee)
+        }).transpose()?;
+
+        if let Some(fee) = &fee {
+            add_coin(&mut required, fee);
+        }
+
+        let deposit = config.deposit.filter(|_| deposits > 0);
+
+        if let Some(mut deposit) = deposit.clone() {
+            deposit.amount *= Uint128::from(deposits);
+            add_coin(&mut required, &deposit);
+        }
+
+        // The instance funds must always be sent, or they would
+        // be paid out of the balance of the factory.
+        for coin in &required {
+            if sent_amount(&info.funds, &coin.denom) < coin.amount {
+                return Err(FactoryError::InsufficientFunds { expected: coin.clone() }.into());
+            }
+        }
+
+        if let Some(fee) = fee.filter(|x| !x.amount.is_zero()) {
+            Self::collect_fee(deps.storage, &fee)?;
+        }
+
+        let refund: Vec<Coin> = info.funds.iter()
+            .map(|x| Coin {
+                denom: x.denom.clone(),
+                amount: x.amount.saturating_sub(sent_amount(&required, &x.denom))
+            })
+            .filter(|x| !x.amount.is_zero())
+            .collect();
+
+        Ok((!refund.is_empty()).then(|| BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: refund
+        }))
+    }
+
+    /// The deposit that `info.sender` locks for each instance
+    /// when calling [`GenericFactory::charge_fee`] with `deposits`.
+    pub(crate) fn deposit_of(deps: Deps, info: &MessageInfo) -> StdResult<Option<Deposit>> {
+        let Some(amount) = FEE_CONFIG.load_or_default(deps.storage)?.deposit else {
+            return Ok(None);
+        };
+
+        Ok(Some(Deposit {
+            payer: info.sender.as_str().canonize(deps.api)?,
+            amount
+        }))
+    }
+
+    pub(crate) fn refund_deposit(deps: Deps, deposit: Deposit) -> StdResult<BankMsg> {
+        Ok(BankMsg::Send {
+            to_address: deposit.payer.humanize(deps.api)?.into_string(),
+            amount: vec![deposit.amount]
+        })
+    }
+
+    /// Adds the deposit to the collected fees.
+    #[inline]
+    pub(crate) fn forfeit_deposit(storage: &mut dyn Storage, deposit: &Deposit) -> StdResult<()> {
+        Self::collect_fee(storage, &deposit.amount)
+    }
+
+    /// Applies the discount of `creator`, if any, to the fee `amount`.
+    fn discounted_fee(deps: Deps, creator: &Addr, amount: Uint128) -> StdResult<Uint128> {
+        let discount = Self::fee_discount(deps, creator.to_string())?;
+
+        Ok(amount.multiply_ratio(100 - discount as u128, 100u128))
+    }
+
+    fn collect_fee(storage: &mut dyn Storage, fee: &Coin) -> StdResult<()> {
+        let mut fees = COLLECTED_FEES.load_or_default(storage)?;
+        add_coin(&mut fees, fee);
+
+        COLLECTED_FEES.save(storage, &fees)
+    }
+}
+
+impl<
+    MSG: Serialize + DeserializeOwned + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// The SNIP-20 receiver interface. Creates an instance from the
+    /// [`InstanceConfig`] sent in `msg` on behalf of `from`, paying the
+    /// creation fee with the received tokens. Anything above the fee is sent
+    /// back to `from`. Not accepted while [`FeeConfig::deposit`] is set since
+    /// the native deposit cannot be sent along with the tokens.
+    pub fn receive(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        from: String,
+        amount: Uint128,
+        msg: Option<Binary>
+    ) -> StdResult<Response> {
+        let fee_config = FEE_CONFIG.load_or_default(deps.storage)?;
+
+        let Some(fee) = fee_config.token_fee else {
+            return Err(StdError::generic_err("Token payments are not accepted."));
+        };
+
+        if fee_config.deposit.is_some() {
+            return Err(StdError::generic_err(
+                "Token payments are not accepted while a deposit is required."
+            ));
+        }
+
+        if info.sender != fee.token.address {
+            return Err(StdError::generic_err(format!(
+                "Unexpected token: {}.",
+                info.sender
+            )));
+        }
+
+        let info = MessageInfo {
+            sender: deps.api.addr_validate(&from)?,
+            funds: vec![]
+        };
+
+        let expected = Self::discounted_fee(deps.as_ref(), &info.sender, fee.amount)?;
+
+        if amount < expected {
+            return Err(FactoryError::InsufficientTokenPayment { expected }.into());
+        }
+
+        let Some(msg) = msg else {
+            return Err(StdError::generic_err("Expecting an instance config to be sent along."));
+        };
+
+        let config: InstanceConfig<MSG> = from_binary(&msg)?;
+
+        if !config.funds.is_empty() {
+            return Err(StdError::generic_err(
+                "Instances paid for in tokens cannot be sent funds."
+            ));
+        }
+
+        Self::assert_can_create(deps.as_ref(), &info)?;
+        Self::apply_rate_limit(deps.branch(), &env, &info)?;
+
+        let collected = COLLECTED_TOKEN_FEES.load_or_default(deps.storage)?;
+        COLLECTED_TOKEN_FEES.save(deps.storage, &(collected + expected))?;
+
+        let refund = amount - expected;
+        let recipient = info.sender.to_string();
+
+        let resp = Self::instantiate_child(deps, env, info, config, None)?;
+
+        if refund.is_zero() {
+            return Ok(resp);
+        }
+
+        Ok(resp.add_message(WasmMsg::Execute {
+            contract_addr: fee.token.address.into_string(),
+            code_hash: fee.token.code_hash,
+            msg: to_binary(&Snip20Msg::Transfer { recipient, amount: refund })?,
+            funds: vec![]
+        }))
+    }
+}
+
+pub(crate) fn add_coin(coins: &mut Vec<Coin>, coin: &Coin) {
+    match coins.iter_mut().find(|x| x.denom == coin.denom) {
+        Some(existing) => existing.amount += coin.amount,
+        None => coins.push(coin.clone())
+    }
+}
+
+#[inline]
+pub(crate) fn sent_amount(funds: &[Coin], denom: &str) -> Uint128 {
+    funds.iter()
+        .filter(|x| x.denom == denom)
+        .map(|x| x.amount)
+        .sum()
+}
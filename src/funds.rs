@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{StdResult, StdError, Coin, Uint128},
+    bin_serde::{FadromaSerialize, FadromaDeserialize}
+};
+
+use crate::{Config, FactoryError};
+
+/// A denom that instances can be sent on creation.
+/// Set with [`Config::allowed_denoms`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct AllowedDenom {
+    pub denom: String,
+    /// The minimum amount that an instance can be sent, if any is sent.
+    #[serde(default)]
+    pub min: Option<Uint128>,
+    /// The maximum amount that an instance can be sent.
+    #[serde(default)]
+    pub max: Option<Uint128>
+}
+
+impl Config {
+    /// Fails if `funds` don't comply with [`Config::allowed_denoms`].
+    pub(crate) fn assert_funds_allowed(&self, funds: &[Coin]) -> StdResult<()> {
+        let Some(allowed) = &self.allowed_denoms else {
+            return Ok(());
+        };
+
+        for coin in funds {
+            let Some(policy) = allowed.iter().find(|x| x.denom == coin.denom) else {
+                return Err(FactoryError::DenomNotAllowed { denom: coin.denom.clone() }.into());
+            };
+
+            if let Some(min) = policy.min {
+                if coin.amount < min {
+                    return Err(FactoryError::FundsBelowMinimum {
+                        min: Coin::new(min.u128(), &coin.denom)
+                    }.into());
+                }
+            }
+
+            if let Some(max) = policy.max {
+                if coin.amount > max {
+                    return Err(FactoryError::FundsAboveMaximum {
+                        max: Coin::new(max.u128(), &coin.denom)
+                    }.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn validate_allowed_denoms(&self) -> StdResult<()> {
+        let Some(allowed) = &self.allowed_denoms else {
+            return Ok(());
+        };
+
+        for (i, policy) in allowed.iter().enumerate() {
+            if allowed[..i].iter().any(|x| x.denom == policy.denom) {
+                return Err(StdError::generic_err(
+                    format!("Denom \"{}\" is allowed more than once.", policy.denom)
+                ));
+            }
+
+            if let (Some(min), Some(max)) = (policy.min, policy.max) {
+                if min > max {
+                    return Err(StdError::generic_err(
+                        format!("Minimum amount of \"{}\" is greater than the maximum.", policy.denom)
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, Env, MessageInfo,
+        Storage, Addr, CanonicalAddr, Binary, Timestamp
+    },
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{SingleItem, ItemSpace, TypedKey},
+    core::Humanize,
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, Config, Pagination, PaginatedResponse, AddrList};
+
+/// The result of the latest health checks of an instance.
+/// See [`crate::ExecuteMsg::StartHealthCheck`].
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct InstanceHealth {
+    pub address: Addr,
+    /// When the instance last answered the health check query.
+    pub last_success: Option<Timestamp>,
+    /// When the health check query to the instance last failed.
+    pub last_failure: Option<Timestamp>,
+    /// The error of the last failed health check query.
+    pub error: Option<String>
+}
+
+/// Times are in nanoseconds since Timestamp doesn't
+/// implement the Fadroma binary serialization.
+#[derive(FadromaSerialize, FadromaDeserialize, Default)]
+struct HealthRecord {
+    last_success: Option<u64>,
+    last_failure: Option<u64>,
+    error: Option<String>
+}
+
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct HealthCheck {
+    query: Binary,
+    next_id: u64
+}
+
+namespace!(HealthCheckNs, b"health_check");
+const HEALTH_CHECK: SingleItem<HealthCheck, HealthCheckNs> = SingleItem::new();
+
+namespace!(InstanceHealthNs, b"instance_health");
+const INSTANCE_HEALTH: ItemSpace<
+    HealthRecord,
+    InstanceHealthNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Starts checking the health of every registered instance by sending
+    /// it `query`. Instances are checked in batches by
+    /// [`GenericFactory::continue_health_check`]. Starting a health check
+    /// while one is in progress starts over from the first instance.
+    #[admin::require_admin]
+    pub fn start_health_check(
+        deps: DepsMut,
+        info: MessageInfo,
+        query: Binary
+    ) -> StdResult<Response> {
+        HEALTH_CHECK.save(deps.storage, &HealthCheck { query, next_id: 0 })?;
+
+        Ok(Response::default())
+    }
+
+    /// Queries the next `batch_size` instances and records whether they
+    /// answered. An instance is unhealthy if its last query failed.
+    #[admin::require_admin]
+    pub fn continue_health_check(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        batch_size: u32
+    ) -> StdResult<Response> {
+        let Some(mut check) = HEALTH_CHECK.load(deps.storage)? else {
+            return Err(StdError::generic_err("No health check is in progress."));
+        };
+
+        let end = Self::instance_times().len(deps.storage)?;
+        let batch_end = end.min(check.next_id.saturating_add(batch_size as u64));
+        let now = env.block.time.nanos();
+
+        for id in check.next_id..batch_end {
+            // Removed instances are skipped.
+            let Some(instance) = Self::load_by_id(deps.as_ref(), id)? else {
+                continue;
+            };
+
+            let address = instance.contract.address.clone().humanize(deps.api)?;
+            let result = Self::smart_query(
+                deps.as_ref(),
+                address.into_string(),
+                instance.contract.code_hash,
+                check.query.clone()
+            );
+
+            let mut record = INSTANCE_HEALTH
+                .load_or_default(deps.storage, &instance.contract.address)?;
+
+            match result {
+                Ok(_) => {
+                    record.last_success = Some(now);
+                    record.error = None;
+
+                    Self::unhealthy().remove(deps.storage, &instance.contract.address)?;
+                }
+                Err(err) => {
+                    record.last_failure = Some(now);
+                    record.error = Some(err.to_string());
+
+                    if !Self::unhealthy().contains(deps.storage, &instance.contract.address)? {
+                        Self::unhealthy().push(deps.storage, &instance.contract.address)?;
+                    }
+                }
+            }
+
+            INSTANCE_HEALTH.save(deps.storage, &instance.contract.address, &record)?;
+        }
+
+        check.next_id = batch_end;
+
+        let done = check.next_id >= end;
+
+        if done {
+            HEALTH_CHECK.remove(deps.storage);
+        } else {
+            HEALTH_CHECK.save(deps.storage, &check)?;
+        }
+
+        Ok(Response::default().add_attribute_plaintext("health_check_done", done.to_string()))
+    }
+
+    /// Lists the instances whose latest health check failed.
+    pub fn unhealthy_instances(
+        deps: Deps,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<InstanceHealth>> {
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate(&config, Self::unhealthy().iter(deps.storage)?, |addr| {
+            let record = INSTANCE_HEALTH.load_or_default(deps.storage, &addr)?;
+
+            Ok(InstanceHealth {
+                address: addr.humanize(deps.api)?,
+                last_success: record.last_success.map(Timestamp::from_nanos),
+                last_failure: record.last_failure.map(Timestamp::from_nanos),
+                error: record.error
+            })
+        })
+    }
+
+    /// Forgets the health of a removed instance.
+    pub(crate) fn remove_health(storage: &mut dyn Storage, addr: &CanonicalAddr) -> StdResult<()> {
+        INSTANCE_HEALTH.remove(storage, addr);
+        Self::unhealthy().remove(storage, addr)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn unhealthy() -> AddrList {
+        AddrList::new(&[b"unhealthy_instances"])
+    }
+}
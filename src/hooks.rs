@@ -0,0 +1,107 @@
+use serde::{Serialize, de::DeserializeOwned};
+use fadroma::{
+    cosmwasm_std::{
+        StdResult, Response, Deps, DepsMut, Env, MessageInfo,
+        Reply, SubMsg, Addr
+    }
+};
+
+use crate::{GenericFactory, ExtraData, ExecuteMsg, InstanceConfig, Instance};
+
+/// Callbacks for customizing the creation of instances without having
+/// to reimplement [`GenericFactory::create_instance`] or
+/// [`GenericFactory::handle_reply`]. All methods do nothing by default.
+/// Set them as the `HOOKS` parameter of [`GenericFactory`] for them to
+/// be called by all of its entry points.
+///
+/// # Examples
+///
+/// ```
+/// use generic_factory::{FactoryHooks, Instance};
+/// use fadroma::cosmwasm_std::{StdResult, DepsMut, Addr, Empty, SubMsg, WasmMsg, to_binary};
+///
+/// struct NotifyRegistry;
+///
+/// impl FactoryHooks<Empty, Empty> for NotifyRegistry {
+///     fn after_registered(
+///         _deps: DepsMut,
+///         instance: &Instance<Addr, Empty>
+///     ) -> StdResult<Vec<SubMsg>> {
+///         let msg = WasmMsg::Execute {
+///             contract_addr: "registry".into(),
+///             code_hash: "registry_hash".into(),
+///             msg: to_binary(&instance.contract)?,
+///             funds: vec![]
+///         };
+///
+///         Ok(vec![SubMsg::new(msg)])
+///     }
+/// }
+/// ```
+pub trait FactoryHooks<MSG, EXTRA: ExtraData> {
+    /// Called before an instance is created from `config`, however it
+    /// is created, and when it is proposed. Returning an error fails the
+    /// creation. Instances created with [`crate::ExecuteMsg::CreateInstanceRaw`]
+    /// are not checked.
+    #[inline]
+    fn before_create(
+        _deps: Deps,
+        _info: &MessageInfo,
+        _config: &InstanceConfig<MSG>
+    ) -> StdResult<()> {
+        Ok(())
+    }
+
+    /// Called after `instance` has been registered. The returned
+    /// messages are added to the response of the reply.
+    #[inline]
+    fn after_registered(
+        _deps: DepsMut,
+        _instance: &Instance<Addr, EXTRA>
+    ) -> StdResult<Vec<SubMsg>> {
+        Ok(vec![])
+    }
+}
+
+/// No hooks.
+impl<MSG, EXTRA: ExtraData> FactoryHooks<MSG, EXTRA> for () { }
+
+impl<
+    MSG: Serialize + DeserializeOwned + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Same as [`GenericFactory::execute`] but with `H` as the hooks of the
+    /// factory instead of `HOOKS`. Prefer setting the `HOOKS` parameter,
+    /// which also covers [`crate::InstantiateMsg::initial_instance`].
+    #[inline]
+    pub fn execute_hooked<H: FactoryHooks<MSG, EXTRA>>(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: ExecuteMsg<MSG>
+    ) -> StdResult<Response> {
+        GenericFactory::<MSG, EXTRA, AUTH, REPLY, H>::execute(deps, env, info, msg)
+    }
+}
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Same as [`GenericFactory::reply`] but with `H` as the
+    /// hooks of the factory instead of `HOOKS`.
+    #[inline]
+    pub fn reply_hooked<H: FactoryHooks<MSG, EXTRA>>(
+        deps: DepsMut,
+        env: Env,
+        reply: Reply
+    ) -> StdResult<Response> {
+        GenericFactory::<MSG, EXTRA, AUTH, REPLY, H>::reply(deps, env, reply)
+    }
+}
@@ -0,0 +1,338 @@
+use fadroma::{
+    cosmwasm_std::{StdResult, StdError, Storage},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage
+};
+
+use crate::Order;
+
+const HEADS: &[u8] = b"id_list_heads";
+const NODES: &[u8] = b"id_list_nodes";
+
+#[derive(FadromaSerialize, FadromaDeserialize, Default)]
+struct Head {
+    first: Option<u64>,
+    last: Option<u64>,
+    len: u64
+}
+
+#[derive(FadromaSerialize, FadromaDeserialize, Default)]
+struct Node {
+    prev: Option<u64>,
+    next: Option<u64>
+}
+
+/// A list of instance IDs kept in ascending order from which an ID can
+/// be removed without moving the others. Unlike `AddrList`
+/// the order in which the instances were created is preserved.
+pub(crate) struct IdList {
+    key: Vec<u8>
+}
+
+/// Iterates an [`IdList`] in either order from a given ID.
+pub(crate) struct IdIter<'a> {
+    list: &'a IdList,
+    storage: &'a dyn Storage,
+    current: Option<u64>,
+    order: Order
+}
+
+impl IdList {
+    /// Each segment is length prefixed so that different
+    /// segments can't produce the same storage key.
+    pub fn new(segments: &[&[u8]]) -> Self {
+        let mut key = Vec::with_capacity(
+            segments.iter().map(|x| 4 + x.len()).sum()
+        );
+
+        for segment in segments {
+            key.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            key.extend_from_slice(segment);
+        }
+
+        Self { key }
+    }
+
+    #[inline]
+    pub fn len(&self, storage: &dyn Storage) -> StdResult<u64> {
+        Ok(self.head(storage)?.len)
+    }
+
+    /// The first ID in the given `order`.
+    pub fn first(&self, storage: &dyn Storage, order: Order) -> StdResult<Option<u64>> {
+        let head = self.head(storage)?;
+
+        Ok(match order {
+            Order::Asc => head.first,
+            Order::Desc => head.last
+        })
+    }
+
+    /// The ID that follows `id` in the given `order`. This is read from the node
+    /// of `id` if it's in the list. Otherwise, the list is walked from the other
+    /// end towards it, which only happens when `id` was removed in the meantime.
+    pub fn next_after(
+        &self,
+        storage: &dyn Storage,
+        id: u64,
+        order: Order
+    ) -> StdResult<Option<u64>> {
+        if let Some(node) = self.node(storage, id)? {
+            return Ok(node.step(order));
+        }
+
+        let mut found = None;
+        let mut current = self.first(storage, order.reverse())?;
+
+        while let Some(x) = current {
+            let is_past = match order {
+                Order::Asc => x < id,
+                Order::Desc => x > id
+            };
+
+            if is_past {
+                break;
+            }
+
+            found = Some(x);
+            current = self.load_node(storage, x)?.step(order.reverse());
+        }
+
+        Ok(found)
+    }
+
+    /// Iterates the list in the given `order` starting at `start`, which
+    /// is expected to be in the list. Nothing is returned if [`None`].
+    pub fn iter_from<'a>(
+        &'a self,
+        storage: &'a dyn Storage,
+        start: Option<u64>,
+        order: Order
+    ) -> IdIter<'a> {
+        IdIter {
+            list: self,
+            storage,
+            current: start,
+            order
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, storage: &dyn Storage, id: u64) -> StdResult<bool> {
+        Ok(self.node(storage, id)?.is_some())
+    }
+
+    /// Inserts `id` after the last ID lower than it. Since IDs are sequential,
+    /// newly registered instances are simply appended. Does nothing if the ID
+    /// is already in the list.
+    pub fn insert(&self, storage: &mut dyn Storage, id: u64) -> StdResult<()> {
+        if self.contains(storage, id)? {
+            return Ok(());
+        }
+
+        let mut head = self.head(storage)?;
+
+        let mut prev = head.last;
+
+        while let Some(current) = prev {
+            if current < id {
+                break;
+            }
+
+            prev = self.load_node(storage, current)?.prev;
+        }
+
+        let next = match prev {
+            Some(prev) => self.load_node(storage, prev)?.next,
+            None => head.first
+        };
+
+        match prev {
+            Some(prev) => self.update_node(storage, prev, |x| x.next = Some(id))?,
+            None => head.first = Some(id)
+        }
+
+        match next {
+            Some(next) => self.update_node(storage, next, |x| x.prev = Some(id))?,
+            None => head.last = Some(id)
+        }
+
+        head.len += 1;
+
+        storage::save(storage, self.node_key(id), &Node { prev, next })?;
+        storage::save(storage, self.head_key(), &head)
+    }
+
+    /// Returns `false` if the ID isn't in the list.
+    pub fn remove(&self, storage: &mut dyn Storage, id: u64) -> StdResult<bool> {
+        let Some(node) = self.node(storage, id)? else {
+            return Ok(false);
+        };
+
+        let mut head = self.head(storage)?;
+
+        match node.prev {
+            Some(prev) => self.update_node(storage, prev, |x| x.next = node.next)?,
+            None => head.first = node.next
+        }
+
+        match node.next {
+            Some(next) => self.update_node(storage, next, |x| x.prev = node.prev)?,
+            None => head.last = node.prev
+        }
+
+        head.len -= 1;
+
+        storage::remove(storage, self.node_key(id));
+        storage::save(storage, self.head_key(), &head)?;
+
+        Ok(true)
+    }
+
+    #[inline]
+    fn head(&self, storage: &dyn Storage) -> StdResult<Head> {
+        Ok(storage::load(storage, self.head_key())?.unwrap_or_default())
+    }
+
+    #[inline]
+    fn node(&self, storage: &dyn Storage, id: u64) -> StdResult<Option<Node>> {
+        storage::load(storage, self.node_key(id))
+    }
+
+    #[inline]
+    fn load_node(&self, storage: &dyn Storage, id: u64) -> StdResult<Node> {
+        self.node(storage, id)?
+            .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))
+    }
+
+    fn update_node(
+        &self,
+        storage: &mut dyn Storage,
+        id: u64,
+        update: impl FnOnce(&mut Node)
+    ) -> StdResult<()> {
+        let mut node = self.load_node(storage, id)?;
+        update(&mut node);
+
+        storage::save(storage, self.node_key(id), &node)
+    }
+
+    fn head_key(&self) -> Vec<u8> {
+        [HEADS, self.key.as_slice()].concat()
+    }
+
+    fn node_key(&self, id: u64) -> Vec<u8> {
+        [NODES, self.key.as_slice(), &id.to_be_bytes()].concat()
+    }
+}
+
+impl Node {
+    #[inline]
+    fn step(&self, order: Order) -> Option<u64> {
+        match order {
+            Order::Asc => self.next,
+            Order::Desc => self.prev
+        }
+    }
+}
+
+impl<'a> Iterator for IdIter<'a> {
+    type Item = StdResult<u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.current?;
+
+        match self.list.load_node(self.storage, id) {
+            Ok(node) => {
+                self.current = node.step(self.order);
+
+                Some(Ok(id))
+            }
+            Err(err) => {
+                self.current = None;
+
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fadroma::cosmwasm_std::testing::MockStorage;
+
+    #[test]
+    fn order_is_kept() {
+        let mut storage = MockStorage::new();
+        let list = IdList::new(&[b"test"]);
+
+        let iter = |storage: &MockStorage, start: Option<u64>, order: Order| list
+            .iter_from(storage, start, order)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+
+        let ids = |storage: &MockStorage| iter(
+            storage,
+            list.first(storage, Order::Asc).unwrap(),
+            Order::Asc
+        );
+
+        for id in [0, 2, 4, 1, 3] {
+            list.insert(&mut storage, id).unwrap();
+        }
+
+        assert_eq!(ids(&storage), [0, 1, 2, 3, 4]);
+        assert_eq!(list.len(&storage).unwrap(), 5);
+
+        assert!(list.remove(&mut storage, 2).unwrap());
+        assert!(!list.remove(&mut storage, 2).unwrap());
+        assert!(list.remove(&mut storage, 0).unwrap());
+        assert!(list.remove(&mut storage, 4).unwrap());
+
+        assert_eq!(ids(&storage), [1, 3]);
+        assert_eq!(iter(&storage, list.first(&storage, Order::Desc).unwrap(), Order::Desc), [3, 1]);
+        assert_eq!(list.len(&storage).unwrap(), 2);
+
+        list.insert(&mut storage, 2).unwrap();
+        list.insert(&mut storage, 2).unwrap();
+
+        assert_eq!(ids(&storage), [1, 2, 3]);
+        assert_eq!(list.len(&storage).unwrap(), 3);
+    }
+
+    #[test]
+    fn iteration_resumes_after_id() {
+        let mut storage = MockStorage::new();
+        let list = IdList::new(&[b"test"]);
+
+        for id in [1, 3, 5, 7] {
+            list.insert(&mut storage, id).unwrap();
+        }
+
+        let next = |storage: &MockStorage, id: u64, order: Order|
+            list.next_after(storage, id, order).unwrap();
+
+        assert_eq!(next(&storage, 3, Order::Asc), Some(5));
+        assert_eq!(next(&storage, 3, Order::Desc), Some(1));
+        assert_eq!(next(&storage, 7, Order::Asc), None);
+        assert_eq!(next(&storage, 1, Order::Desc), None);
+
+        // IDs that aren't in the list anymore.
+        list.remove(&mut storage, 5).unwrap();
+
+        assert_eq!(next(&storage, 5, Order::Asc), Some(7));
+        assert_eq!(next(&storage, 5, Order::Desc), Some(3));
+        assert_eq!(next(&storage, 4, Order::Asc), Some(7));
+        assert_eq!(next(&storage, 0, Order::Asc), Some(1));
+        assert_eq!(next(&storage, 0, Order::Desc), None);
+        assert_eq!(next(&storage, 9, Order::Asc), None);
+        assert_eq!(next(&storage, 9, Order::Desc), Some(7));
+
+        let ids = list.iter_from(&storage, Some(3), Order::Asc)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(ids, [3, 7]);
+    }
+}
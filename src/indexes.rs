@@ -0,0 +1,407 @@
+use serde::{Serialize, de::DeserializeOwned};
+use fadroma::{
+    cosmwasm_std::{
+        StdResult, Response, Deps, DepsMut, Env, MessageInfo, Reply,
+        SubMsgResponse, Storage, Addr, CanonicalAddr, Binary, from_binary
+    },
+    storage::{ItemSpace, TypedKey},
+    core::Canonize,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, ExecuteMsg, Instance, Pagination, PaginatedResponse, AddrList};
+
+/// The name of a secondary index of instances.
+pub type IndexName = &'static str;
+
+/// Implement this on `EXTRA` to index instances by values derived from
+/// their extra data, for example the token pair of an AMM pair contract.
+/// The indexes are maintained when using [`GenericFactory::reply_indexed`]
+/// or [`GenericFactory::handle_reply_indexed`] together with
+/// [`GenericFactory::execute_indexed`] and can be queried with
+/// [`crate::QueryMsg::InstancesByIndex`].
+///
+/// # Examples
+///
+/// ```
+/// use generic_factory::{IndexedExtra, IndexName};
+///
+/// struct PairExtra {
+///     token_0: String,
+///     token_1: String
+/// }
+///
+/// impl IndexedExtra for PairExtra {
+///     fn index_keys(&self) -> Vec<(IndexName, Vec<u8>)> {
+///         let key = format!("{}/{}", self.token_0, self.token_1);
+///
+///         vec![("pair", key.into_bytes())]
+///     }
+/// }
+/// ```
+pub trait IndexedExtra {
+    /// The index names and keys under which the instance is indexed.
+    fn index_keys(&self) -> Vec<(IndexName, Vec<u8>)>;
+}
+
+const EXTRA_INDEXES: &[u8] = b"extra_indexes";
+
+// The extra index keys of each instance so that it can be
+// removed from them even if its extra data changed since.
+namespace!(InstanceIndexKeysNs, b"instance_index_keys");
+const INSTANCE_INDEX_KEYS: ItemSpace<
+    Vec<Vec<u8>>,
+    InstanceIndexKeysNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData + IndexedExtra,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Same as [`GenericFactory::reply`] but also indexes the new instance.
+    #[inline]
+    pub fn reply_indexed(
+        deps: DepsMut,
+        _env: Env,
+        reply: Reply
+    ) -> StdResult<Response> {
+        Self::reply_with(deps, reply, Self::handle_reply_indexed)
+    }
+
+    /// Same as [`GenericFactory::reply_indexed`] but with `H` as
+    /// the hooks of the factory instead of `HOOKS`.
+    #[inline]
+    pub fn reply_indexed_hooked<H: FactoryHooks<MSG, EXTRA>>(
+        deps: DepsMut,
+        env: Env,
+        reply: Reply
+    ) -> StdResult<Response> {
+        GenericFactory::<MSG, EXTRA, AUTH, REPLY, H>::reply_indexed(deps, env, reply)
+    }
+
+    /// Same as [`GenericFactory::handle_reply`] but also indexes the new instance.
+    pub fn handle_reply_indexed(
+        mut deps: DepsMut,
+        id: u64,
+        resp: SubMsgResponse
+    ) -> StdResult<Instance<Addr, EXTRA>> {
+        let instance = Self::handle_reply(deps.branch(), id, resp)?;
+        let canonical = instance.contract.address.as_str().canonize(deps.api)?;
+
+        Self::add_to_extra_indexes(deps.storage, &canonical, &instance.extra)?;
+
+        Ok(instance)
+    }
+
+    /// Replaces the extra data of an instance and moves
+    /// it from its old extra indexes to the new ones.
+    fn set_instance_extra_indexed(
+        deps: DepsMut,
+        addr: &CanonicalAddr,
+        extra: &Binary
+    ) -> StdResult<bool> {
+        let instances = Self::instances();
+        let Some(mut instance) = instances.load(deps.storage, addr)? else {
+            return Ok(false);
+        };
+
+        instance.extra = from_binary(extra)?;
+        instances.save(deps.storage, addr, &instance)?;
+
+        Self::remove_from_extra_indexes(deps.storage, addr)?;
+        Self::add_to_extra_indexes(deps.storage, addr, &instance.extra)?;
+
+        Ok(true)
+    }
+
+    fn add_to_extra_indexes(
+        storage: &mut dyn Storage,
+        addr: &CanonicalAddr,
+        extra: &EXTRA
+    ) -> StdResult<()> {
+        let keys = extra.index_keys()
+            .into_iter()
+            .map(|(index, key)| extra_index_key(index, &key))
+            .collect::<Vec<_>>();
+
+        for key in &keys {
+            Self::extra_index(key).push(storage, addr)?;
+        }
+
+        INSTANCE_INDEX_KEYS.save(storage, addr, &keys)
+    }
+}
+
+impl<
+    MSG: Serialize + DeserializeOwned + 'static,
+    EXTRA: ExtraData + IndexedExtra,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Same as [`GenericFactory::execute`] but also re-indexes instances
+    /// whose extra data is replaced. Use it along with [`GenericFactory::reply_indexed`].
+    #[inline]
+    pub fn execute_indexed(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: ExecuteMsg<MSG>
+    ) -> StdResult<Response> {
+        Self::execute_with(deps, env, info, msg, Self::set_instance_extra_indexed)
+    }
+}
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Lists the instances whose extra data produced `key` for `index`
+    /// in the order they were created. Always empty if `EXTRA`
+    /// doesn't implement [`IndexedExtra`].
+    pub fn instances_by_index(
+        deps: Deps,
+        index: &str,
+        key: &[u8],
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<Instance<Addr, EXTRA>>> {
+        let key = extra_index_key(index, key);
+
+        Self::paginate_index(deps, Self::extra_index(&key), pagination)
+    }
+
+    /// Whether the instance was indexed by its extra data.
+    #[inline]
+    pub(crate) fn has_extra_index_keys(
+        storage: &dyn Storage,
+        addr: &CanonicalAddr
+    ) -> StdResult<bool> {
+        Ok(INSTANCE_INDEX_KEYS.load(storage, addr)?.is_some())
+    }
+
+    /// Removes an instance that is being removed from the extra indexes.
+    pub(crate) fn remove_from_extra_indexes(
+        storage: &mut dyn Storage,
+        addr: &CanonicalAddr
+    ) -> StdResult<()> {
+        let Some(keys) = INSTANCE_INDEX_KEYS.load(storage, addr)? else {
+            return Ok(());
+        };
+
+        for key in keys {
+            Self::extra_index(&key).remove(storage, addr)?;
+        }
+
+        INSTANCE_INDEX_KEYS.remove(storage, addr);
+
+        Ok(())
+    }
+
+    #[inline]
+    fn extra_index(key: &[u8]) -> AddrList {
+        AddrList::new(&[key])
+    }
+}
+
+/// The index name is length prefixed so that different
+/// names and keys can't produce the same storage key.
+fn extra_index_key(index: &str, key: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(EXTRA_INDEXES.len() + 4 + index.len() + key.len());
+    result.extend_from_slice(EXTRA_INDEXES);
+    result.extend_from_slice(&(index.len() as u32).to_be_bytes());
+    result.extend_from_slice(index.as_bytes());
+    result.extend_from_slice(key);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use fadroma::{
+        schemars::{self, JsonSchema},
+        cosmwasm_std::{
+            Binary, Event, OwnedDeps, ContractInfoResponse, SystemResult, ContractResult, to_binary,
+            testing::{mock_dependencies, mock_env, mock_info, MockStorage, MockApi, MockQuerier}
+        },
+        bin_serde::{FadromaSerialize, FadromaDeserialize},
+        core::ContractCode
+    };
+    use crate::{InstantiateMsg, InstanceConfig, InstantiateReplyData};
+
+    #[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Debug)]
+    struct PairExtra {
+        token_0: String,
+        token_1: String
+    }
+
+    impl IndexedExtra for PairExtra {
+        fn index_keys(&self) -> Vec<(IndexName, Vec<u8>)> {
+            vec![
+                ("token", self.token_0.clone().into_bytes()),
+                ("token", self.token_1.clone().into_bytes())
+            ]
+        }
+    }
+
+    type Factory = GenericFactory<Binary, PairExtra, false>;
+
+    fn with_pairs() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        let info = mock_info("admin", &[]);
+
+        Factory::instantiate(deps.as_mut(), mock_env(), info.clone(), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "pair".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        for (addr, token_0, token_1) in [("pair_0", "A", "B"), ("pair_1", "B", "C")] {
+            let resp = Factory::create_instance(deps.as_mut(), mock_env(), info.clone(), InstanceConfig {
+                msg: Binary::default(),
+                funds: vec![],
+                code_key: None,
+                label: None,
+                extra: None
+            }).unwrap();
+
+            let data = InstantiateReplyData::with_extra(
+                Addr::unchecked(addr),
+                PairExtra { token_0: token_0.into(), token_1: token_1.into() }
+            );
+
+            let instance = Factory::handle_reply_indexed(deps.as_mut(), resp.messages[0].id, SubMsgResponse {
+                events: vec![Event::new("instantiate").add_attribute("contract_address", addr)],
+                data: Some(to_binary(&data).unwrap())
+            }).unwrap();
+
+            assert_eq!(instance.contract.address, addr);
+            assert_eq!(instance.extra.token_1, token_1);
+        }
+
+        deps
+    }
+
+    #[test]
+    fn instances_are_indexed_by_extra() {
+        let mut deps = with_pairs();
+        let info = mock_info("admin", &[]);
+
+        let with_b = Factory::instances_by_index(
+            deps.as_ref(),
+            "token",
+            b"B",
+            Pagination::new(0, 10)
+        ).unwrap();
+
+        assert_eq!(
+            with_b.entries.iter().map(|x| x.contract.address.as_str()).collect::<Vec<_>>(),
+            ["pair_0", "pair_1"]
+        );
+
+        let with_c = Factory::instances_by_index(
+            deps.as_ref(),
+            "token",
+            b"C",
+            Pagination::new(0, 10)
+        ).unwrap();
+
+        assert_eq!(with_c.total, 1);
+        assert_eq!(with_c.entries[0].extra.token_0, "B");
+
+        Factory::remove_instance(deps.as_mut(), info, "pair_0".into()).unwrap();
+
+        let with_b = Factory::instances_by_index(
+            deps.as_ref(),
+            "token",
+            b"B",
+            Pagination::new(0, 10)
+        ).unwrap();
+
+        assert_eq!(with_b.total, 1);
+        assert_eq!(with_b.entries[0].contract.address.as_str(), "pair_1");
+    }
+
+    #[test]
+    fn updating_extra_reindexes() {
+        let mut deps = with_pairs();
+        let info = mock_info("admin", &[]);
+
+        let update = || ExecuteMsg::<Binary>::UpdateInstanceExtra {
+            addr: "pair_0".into(),
+            extra: to_binary(&PairExtra { token_0: "C".into(), token_1: "D".into() }).unwrap()
+        };
+
+        let err = Factory::execute(deps.as_mut(), mock_env(), info.clone(), update())
+            .unwrap_err();
+        assert!(err.to_string().contains("execute_indexed"));
+
+        Factory::execute_indexed(deps.as_mut(), mock_env(), info, update()).unwrap();
+
+        let keys = |deps: Deps, key: &[u8]| Factory::instances_by_index(
+            deps,
+            "token",
+            key,
+            Pagination::new(0, 10)
+        ).unwrap().entries.into_iter().map(|x| x.contract.address.into_string()).collect::<Vec<_>>();
+
+        assert!(keys(deps.as_ref(), b"A").is_empty());
+        assert_eq!(keys(deps.as_ref(), b"B"), ["pair_1"]);
+        assert_eq!(keys(deps.as_ref(), b"C"), ["pair_1", "pair_0"]);
+        assert_eq!(keys(deps.as_ref(), b"D"), ["pair_0"]);
+
+        let set_my_extra = || ExecuteMsg::<Binary>::SetMyExtra {
+            extra: to_binary(&PairExtra { token_0: "E".into(), token_1: "B".into() }).unwrap()
+        };
+
+        let info = mock_info("pair_1", &[]);
+
+        let err = Factory::execute(deps.as_mut(), mock_env(), info.clone(), set_my_extra())
+            .unwrap_err();
+        assert!(err.to_string().contains("execute_indexed"));
+
+        Factory::execute_indexed(deps.as_mut(), mock_env(), info, set_my_extra()).unwrap();
+
+        assert_eq!(keys(deps.as_ref(), b"B"), ["pair_1"]);
+        assert_eq!(keys(deps.as_ref(), b"C"), ["pair_0"]);
+        assert_eq!(keys(deps.as_ref(), b"E"), ["pair_1"]);
+    }
+
+    #[test]
+    fn existing_contracts_are_indexed() {
+        let mut deps = with_pairs();
+
+        deps.querier.update_wasm(|_| {
+            let resp = ContractInfoResponse::new(1, "deployer");
+
+            SystemResult::Ok(ContractResult::Ok(to_binary(&resp).unwrap()))
+        });
+
+        Factory::execute_indexed(deps.as_mut(), mock_env(), mock_info("admin", &[]), ExecuteMsg::RegisterExisting {
+            address: "pair_2".into(),
+            label: "pair_2".into(),
+            extra: to_binary(&PairExtra { token_0: "A".into(), token_1: "D".into() }).unwrap()
+        }).unwrap();
+
+        let with_a = Factory::instances_by_index(
+            deps.as_ref(),
+            "token",
+            b"A",
+            Pagination::new(0, 10)
+        ).unwrap();
+
+        assert_eq!(
+            with_a.entries.iter().map(|x| x.contract.address.as_str()).collect::<Vec<_>>(),
+            ["pair_0", "pair_2"]
+        );
+    }
+}
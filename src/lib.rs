@@ -1,18 +1,25 @@
 use std::{
     marker::PhantomData,
-    any::type_name
+    any::{Any, type_name},
+    collections::HashSet
 };
 
 use fadroma::{
     schemars::{self, JsonSchema},
     cosmwasm_std::{
-        StdResult, Response, Deps, DepsMut, MessageInfo, Env,
+        StdResult, Response, Deps, DepsMut, MessageInfo, Env, Api, Storage,
         SubMsg, WasmMsg, Coin, Reply, StdError, Empty, Addr,
         CanonicalAddr, SubMsgResponse, SubMsgResult, Binary,
-        to_binary, from_binary
+        Timestamp, Uint128, to_binary, from_binary, from_slice
+    },
+    bin_serde::{
+        self, FadromaSerialize, FadromaDeserialize,
+        Serializer, Deserializer
+    },
+    storage::{
+        SingleItem, ItemSpace, StaticKey, TypedKey,
+        iterable::IterableStorage
     },
-    bin_serde::{FadromaSerialize, FadromaDeserialize},
-    storage::{SingleItem, TypedKey, map::InsertOnlyMap},
     core::{ContractCode, ContractLink, Humanize, Canonize},
     admin::{self, Admin},
     killswitch::{self, Killswitch},
@@ -20,41 +27,351 @@ use fadroma::{
 };
 use serde::{Serialize, Deserialize, de::DeserializeOwned};
 
+pub mod reply;
+mod addr_list;
+mod id_list;
+mod config;
+mod codes;
+mod code_change;
+mod error;
+mod slots;
+mod creators;
+mod tags;
+mod indexes;
+mod status;
+mod register;
+mod auth;
+mod token_gate;
+mod fees;
+mod rate_limit;
+mod pending;
+mod proposals;
+mod permits;
+mod validate;
+mod funds;
+mod failures;
+mod hooks;
+mod proxy;
+mod broadcast;
+mod health;
+mod propagation;
+
+pub use config::Config;
+pub use codes::{RegisteredCode, CodeMetadata, CodeChange, Deprecation};
+pub use code_change::{
+    PendingCodeChange, PendingCodeRegistration,
+    PendingDelayDecrease, ConfirmerChange
+};
+pub use error::FactoryError;
+pub use slots::CodeSlot;
+pub use creators::CreatorStats;
+pub use indexes::{IndexedExtra, IndexName};
+pub use status::InstanceStatus;
+pub use auth::AuthMode;
+pub use token_gate::TokenGate;
+pub use fees::{FeeConfig, TokenFee};
+pub use rate_limit::RateLimit;
+pub use proposals::{Proposal, ProposalStatus};
+pub use permits::{CreationPermit, SignedPermit};
+pub use validate::{ValidateConfig, Validated};
+pub use funds::AllowedDenom;
+pub use failures::{FailedInstantiation, FAILURE_ID_ATTR};
+pub use hooks::FactoryHooks;
+pub use broadcast::Broadcast;
+pub use health::InstanceHealth;
+pub use propagation::KillswitchPropagation;
+pub use register::REGISTRATION_TOKEN_FIELD;
+use addr_list::AddrList;
+use id_list::IdList;
+use fees::Deposit;
+
+#[cfg(feature = "cw-ownable")]
+pub mod ownable;
+
+#[cfg(feature = "entry-points")]
+pub mod contract;
+
+/// The default start of the reply IDs of the instantiate
+/// submessages sent by [`GenericFactory`]. Set its `REPLY`
+/// parameter to use another one.
 pub const REPLY_ID: u64 = 78024480;
 pub const INSTANCE_ADDR_ATTR: &str = "fadroma_instance_address";
+/// The maximum length of a label set in [`InstanceConfig::label`].
+pub const MAX_LABEL_LEN: usize = 128;
+// The start of the labels generated for instances created without one.
+const GENERATED_LABEL_PREFIX: &str = "Fadroma factory child instance #";
+/// The type of the event emitted whenever the default contract code changes.
+pub const CODE_CHANGE_EVENT: &str = "fadroma_factory_code_change";
 
 pub trait ExtraData: JsonSchema +
     Serialize + DeserializeOwned +
     FadromaSerialize + FadromaDeserialize { }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
-pub struct InstantiateMsg {
+pub struct InstantiateMsg<MSG> {
     pub admin: Option<String>,
-    pub code: ContractCode
+    pub code: ContractCode,
+    pub config: Option<Config>,
+    /// An instance to create in the same transaction as the factory.
+    /// Created on behalf of the sender, who must send its funds.
+    pub initial_instance: Option<InstanceConfig<MSG>>
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg<MSG> {
     CreateInstance(InstanceConfig<MSG>),
+    /// Creates several instances in a single transaction.
+    CreateInstances(Vec<InstanceConfig<MSG>>),
+    /// Creates an instance that the factory doesn't keep track of.
+    CreateInstanceUnregistered(InstanceConfig<MSG>),
+    /// Proposes an instance to be created once the admin approves it.
+    ProposeInstance(InstanceConfig<MSG>),
+    /// Can only be called by the admin.
+    ApproveInstance { id: u64 },
+    /// Can only be called by the admin.
+    RejectInstance { id: u64 },
+    /// Marks the proposals that expired before being approved as such,
+    /// checking at most `limit` of them.
+    PruneExpired { limit: u32 },
+    /// Creates the instance of a [`FailedInstantiation`] again. Can only
+    /// be called by the admin or the creator of the failed instance.
+    RetryInstantiation { failure_id: u64 },
+    /// Creates an instance authorized by a permit signed off-chain. `config`
+    /// is the serialized `InstanceConfig<MSG>` that the permit was signed for.
+    CreateInstanceWithPermit {
+        config: Binary,
+        permit: CreationPermit,
+        signature: Binary
+    },
+    /// Sets the public key that signs creation permits.
+    /// Can only be called by the admin.
+    SetPermitSigner { pubkey: Option<Binary> },
+    /// Creates an instance with an instantiate message that is passed
+    /// through as is instead of `MSG`. Can only be called by the admin.
+    CreateInstanceRaw(InstanceConfig<Binary>),
     ChangeContractCode(ContractCode),
+    ConfirmCodeChange(ContractCode),
+    ApplyCodeChange { },
+    /// Accepts the pending change of the code change confirmation settings.
+    /// Can only be called by the current confirmer.
+    ConfirmConfirmerChange(ConfirmerChange),
+    /// Cancels the pending decrease of [`Config::code_change_delay`].
+    /// Can only be called by the admin.
+    CancelDelayDecrease { },
+    /// Scheduled while [`Config::code_change_delay`]
+    /// or [`Config::confirm_code_changes`] is set.
+    RegisterCode { key: String, code: ContractCode, metadata: Option<CodeMetadata> },
+    ConfirmCodeRegistration { key: String, code: ContractCode },
+    /// Registers the code scheduled under `key` once the delay has passed
+    /// and it has been confirmed if required. Can be called by anyone.
+    ApplyCodeRegistration { key: String },
+    SetCodeMetadata { key: String, metadata: Option<CodeMetadata> },
+    RemoveCode { key: String },
+    DeprecateCode { code_key: Option<String>, sunset: Option<Timestamp> },
+    UndeprecateCode { code_key: Option<String> },
+    SetCodePaused { code_key: Option<String>, paused: bool },
+    /// Restricts creating instances from the code to the given
+    /// addresses. Anyone (subject to `AUTH`) can if [`None`].
+    SetCodeAllowlist { code_key: Option<String>, addresses: Option<Vec<String>> },
+    /// Sets the message sent to each new instance of the code once it
+    /// is registered. Not sent if [`None`].
+    SetCodeCallback { code_key: Option<String>, callback: Option<Binary> },
+    /// Replaces the extra data of an instance with `extra`, which
+    /// is the JSON encoded `EXTRA`. Can be called by the admin or an operator.
+    UpdateInstanceExtra { addr: String, extra: Binary },
+    /// Replaces the extra data of the calling instance with `extra`,
+    /// which is the JSON encoded `EXTRA`. Can only be called by instances.
+    SetMyExtra { extra: Binary },
+    /// Can be called by the admin or the creator of the instance.
+    TagInstance { addr: String, tag: String },
+    /// Can be called by the admin or the creator of the instance.
+    UntagInstance { addr: String, tag: String },
+    /// Removes the instance from the factory. The contract itself is
+    /// not affected. Can only be called by the admin.
+    RemoveInstance { addr: String },
+    /// Can only be called by the admin.
+    SetInstanceStatus { addr: String, status: InstanceStatus },
+    /// Sends `msg` to an instance using its stored code hash. `funds`
+    /// are sent from the balance of the factory. Can only be called by the admin.
+    ExecuteOnInstance { addr: String, msg: Binary, funds: Vec<Coin> },
+    /// Starts sending `msg` to every registered instance. Can only be called by the admin.
+    StartBroadcast { msg: Binary },
+    /// Sends the broadcast message to the next `batch_size` instances.
+    /// Can only be called by the admin.
+    ContinueBroadcast { batch_size: u32 },
+    /// Can only be called by the admin.
+    CancelBroadcast { },
+    /// Starts checking the health of every registered instance by sending
+    /// it the `query`. Can only be called by the admin.
+    StartHealthCheck { query: Binary },
+    /// Queries the next `batch_size` instances for the health check
+    /// in progress. Can only be called by the admin.
+    ContinueHealthCheck { batch_size: u32 },
+    /// Sends the killswitch status of the factory to the next `batch_size`
+    /// instances when [`Config::propagate_killswitch`] is set. Allowed while
+    /// the factory is not operational. Can only be called by the admin.
+    ContinueKillswitchPropagation { batch_size: u32 },
+    /// Adds a contract that wasn't created by the factory to its instances.
+    /// `label` is the one it was instantiated with and `extra` is the JSON
+    /// encoded `EXTRA`. Can only be called by the admin.
+    RegisterExisting { address: String, label: String, extra: Binary },
+    /// Updates the stored code of an instance that was migrated to
+    /// the one it runs on chain. Can be called by anyone.
+    SyncInstanceCodeHash { addr: String },
+    /// Sent by a pending instance with the token from its instantiate message to
+    /// become active. See [`Config::require_self_registration`].
+    RegisterSelf { token: Binary },
+    /// Changes who can create instances. Can only be called by the admin.
+    SetAuthMode { mode: AuthMode },
+    /// Adds addresses to the creator allowlist. Can only be called by the admin.
+    AddCreators { addrs: Vec<String> },
+    /// Removes addresses from the creator allowlist. Can only be called by the admin.
+    RemoveCreators { addrs: Vec<String> },
+    /// Blocks addresses from creating instances. Can only be called by the admin.
+    BlockCreators { addrs: Vec<String> },
+    /// Can only be called by the admin.
+    UnblockCreators { addrs: Vec<String> },
+    /// Grants addresses the operator role, which allows creating instances
+    /// and updating their extra data. Can only be called by the admin.
+    AddOperators { addrs: Vec<String> },
+    /// Can only be called by the admin.
+    RemoveOperators { addrs: Vec<String> },
+    /// Gives the factory a viewing key for the token
+    /// required by [`AuthMode::TokenGated`].
+    SetTokenGateKey { key: String },
+    /// Can only be called by the admin.
+    SetFeeConfig(FeeConfig),
+    /// Grants an address a discount in percent on the creation fee.
+    /// Can only be called by the admin.
+    SetFeeDiscount { addr: String, percent: u8 },
+    /// Sends the collected creation fees to the fee collector.
+    WithdrawFees { },
+    /// The SNIP-20 receiver interface. Creates an instance from the
+    /// [`InstanceConfig`] in `msg`, paying the creation fee in the token
+    /// of [`FeeConfig::token_fee`] in the same transaction.
+    Receive {
+        sender: Addr,
+        from: Addr,
+        amount: Uint128,
+        msg: Option<Binary>
+    },
+    UpdateConfig(Config),
     Admin(admin::ExecuteMsg),
-    Killswitch(killswitch::ExecuteMsg)
+    Killswitch(killswitch::ExecuteMsg),
+    #[cfg(feature = "cw-ownable")]
+    UpdateOwnership(ownable::Action)
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    ListInstances { pagination: Pagination },
+    ListInstances {
+        pagination: Pagination,
+        /// If set, the page starts after the instance with this
+        /// address and [`Pagination::start`] is ignored.
+        #[serde(default)]
+        start_after: Option<String>,
+        /// Only list the instances that match the filter.
+        /// Cannot be combined with `start_after`.
+        #[serde(default)]
+        filter: Option<InstanceFilter>
+    },
+    /// Lists only the addresses of the instances of any status in the order
+    /// they were created, which is cheaper than [`QueryMsg::ListInstances`].
+    ListInstanceAddresses { pagination: Pagination },
     InstanceByAddr { addr: String },
+    /// Whether the address belongs to an instance created by the factory.
+    IsInstance { addr: String },
+    InstanceByLabel { label: String },
+    /// Returns only the extra data of the instance.
+    InstanceExtraByAddr { addr: String },
+    /// The configuration that the instance was created with if
+    /// [`Config::store_init_msgs`] was set at the time.
+    InstanceInitMsg { addr: String },
+    /// Returns the instances in the same order as `addrs`. At most
+    /// [`Config::max_page_limit`] addresses can be given.
+    InstancesByAddrs { addrs: Vec<String> },
+    InstanceById { id: u64 },
+    InstancesByCodeId { code_id: u64, pagination: Pagination },
+    ListInstancesByCreator { creator: String, pagination: Pagination },
+    CreatorStats { creator: String },
+    InstancesByTag { tag: String, pagination: Pagination },
+    InstanceTags { addr: String },
+    /// Lists the instances whose extra data produced `key` for `index`.
+    /// See [`IndexedExtra`].
+    InstancesByIndex { index: String, key: Binary, pagination: Pagination },
+    /// Lists the instances created at or after `from` and before `to`.
+    InstancesCreatedBetween { from: Timestamp, to: Timestamp, pagination: Pagination },
+    /// Lists the instances registered since the given ID or block height.
+    ListInstancesSince { id_or_height: IdOrHeight, pagination: Pagination },
+    /// The number of instances created by the factory.
+    TotalInstances { },
+    /// The default code that instances are created from. Use
+    /// [`QueryMsg::ListCodes`] for the codes in the registry.
+    ContractCode { },
+    ListCodes { pagination: Pagination },
+    CodeByKey { key: String },
+    CodeHistory { pagination: Pagination },
+    PendingCodeChange { },
+    PendingCodeRegistration { key: String },
+    /// The lower [`Config::code_change_delay`] that takes effect once
+    /// the current delay has passed since it was requested.
+    PendingDelayDecrease { },
+    PendingConfirmerChange { },
+    CodeDeprecation { code_id: u64 },
+    CodePaused { code_key: Option<String> },
+    CodeAllowlist { code_key: Option<String> },
+    CodeCallback { code_key: Option<String> },
+    Config { },
+    AuthMode { },
+    /// Lists the creator allowlist used with [`AuthMode::Allowlist`].
+    ListCreators { pagination: Pagination },
+    ListBlockedCreators { pagination: Pagination },
+    ListOperators { pagination: Pagination },
+    Proposal { id: u64 },
+    ListProposals { pagination: Pagination },
+    PermitSigner { },
+    FeeConfig { },
+    FeeDiscount { addr: String },
+    CollectedFees { },
+    CollectedTokenFees { },
+    /// Lists the instantiations that failed while
+    /// [`Config::track_failures`] was set.
+    FailedInstantiations { pagination: Pagination },
+    /// The broadcast in progress, if any.
+    Broadcast { },
+    /// Queries an instance with `msg` using its stored code hash
+    /// and returns the raw result of the query.
+    QueryInstance { addr: String, msg: Binary },
+    /// Lists the instances that failed their latest health check.
+    UnhealthyInstances { pagination: Pagination },
+    /// The killswitch status being sent to the instances, if any.
+    KillswitchPropagation { },
     Admin(admin::QueryMsg),
-    Killswitch(killswitch::QueryMsg)
+    Killswitch(killswitch::QueryMsg),
+    #[cfg(feature = "cw-ownable")]
+    Ownership { }
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, Debug)]
 pub struct InstanceConfig<MSG> {
     pub msg: MSG,
-    pub funds: Vec<Coin>
+    pub funds: Vec<Coin>,
+    /// The key of the code in the code registry to instantiate.
+    /// Uses the default contract code if [`None`].
+    pub code_key: Option<String>,
+    /// The label of the instance. Must be unique and at most
+    /// [`MAX_LABEL_LEN`] characters of letters, digits, spaces and
+    /// `-_.:/#`. A label is generated if [`None`] and custom labels
+    /// can't start like the generated ones.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// The JSON encoded `EXTRA` of the instance, for children that only
+    /// set their address in their [`InstantiateReplyData`] or no reply
+    /// data at all. Ignored if the child sets its own extra data.
+    #[serde(default)]
+    pub extra: Option<Binary>
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
@@ -66,35 +383,117 @@ pub struct InstantiateReplyData<
     extra: EXTRA
 }
 
+/// The address part of [`InstantiateReplyData`], which is all that's
+/// needed when the extra data is given in [`InstanceConfig::extra`].
+#[derive(Deserialize)]
+struct ReplyAddress {
+    address: Addr
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, Debug)]
 pub struct Instance<
     A,
     EXTRA: ExtraData
 > {
+    /// Sequential ID assigned to the instance when it is registered.
+    pub id: u64,
     pub contract: ContractLink<A>,
+    /// The ID of the code that the instance was created from.
+    pub code_id: u64,
+    /// The label that the instance was instantiated with. If the
+    /// chain reports it in the reply, the reported one is stored.
+    pub label: String,
+    /// The funds that were sent to the instance when instantiating it.
+    pub funds: Vec<Coin>,
+    pub created: InstanceCreation<A>,
+    /// The creation sequence number of the instance which is part of
+    /// its generated label. [`None`] if the instance wasn't created by
+    /// the factory but registered with [`ExecuteMsg::RegisterExisting`].
+    pub seq: Option<u64>,
+    pub status: InstanceStatus,
     #[serde(bound = "")] // See https://github.com/serde-rs/serde/issues/1296
     pub extra: EXTRA
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug)]
+/// Who created an instance and when.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct InstanceCreation<A> {
+    pub creator: A,
+    pub block_height: u64,
+    pub block_time: Timestamp
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Pagination {
     pub start: u64,
-    pub limit: u8
+    pub limit: u8,
+    #[serde(default)]
+    pub order: Order,
+    /// The [`PaginatedResponse::next`] cursor of the previous
+    /// page. If set, `start` is ignored and the page continues
+    /// from where the previous one ended. When listing instances
+    /// in creation order, it holds the ID of the last one listed.
+    #[serde(default)]
+    pub cursor: Option<Binary>
+}
+
+/// The order in which paginated entries are returned. For instances,
+/// ascending is the order in which they were created.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc
+}
+
+/// Restricts [`QueryMsg::ListInstances`] to instances created
+/// from a particular code. If both code fields are set, they must
+/// refer to the same code. Filtering by hash alone also lists the
+/// instances of every code ID that the same code was stored under.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug)]
+pub struct InstanceFilter {
+    #[serde(default)]
+    pub code_id: Option<u64>,
+    #[serde(default)]
+    pub code_hash: Option<String>,
+    /// Only list instances with this status. Defaults to
+    /// [`InstanceStatus::Active`].
+    #[serde(default)]
+    pub status: Option<InstanceStatus>
+}
+
+/// Where [`QueryMsg::ListInstancesSince`] starts from. Both are inclusive.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum IdOrHeight {
+    Id(u64),
+    Height(u64)
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct PaginatedResponse<T: Serialize> {
     pub entries: Vec<T>,
-    pub total: u64
+    pub total: u64,
+    /// Whether there are more entries after this page.
+    pub has_more: bool,
+    /// An opaque cursor to pass in [`Pagination::cursor`]
+    /// to get the next page. [`None`] if there are no more entries.
+    pub next: Option<Binary>
 }
 
+/// `HOOKS` are called by every entry point that creates or registers
+/// instances. See [`FactoryHooks`].
 pub struct GenericFactory<
     MSG: Serialize,
     EXTRA: ExtraData = Empty,
-    const AUTH: bool = true
+    const AUTH: bool = true,
+    const REPLY: u64 = REPLY_ID,
+    HOOKS = ()
 >{
     msg_phantom: PhantomData<MSG>,
-    extra_phantom: PhantomData<EXTRA>
+    extra_phantom: PhantomData<EXTRA>,
+    hooks_phantom: PhantomData<HOOKS>
 }
 
 namespace!(ContractNs, b"contract");
@@ -105,38 +504,266 @@ const CONTRACT: SingleItem<
 
 namespace!(InstancesNs, b"instances");
 
+// The creation time of each instance in nanoseconds, by ID. Removed
+// instances are kept so that the IDs are also the indexes.
+const INSTANCE_TIMES: StaticKey = StaticKey(b"instance_times");
+// The block height at which each instance was created, by ID.
+const INSTANCE_HEIGHTS: StaticKey = StaticKey(b"instance_heights");
+
+// The address of each instance keyed by its ID.
+namespace!(InstanceAddrsByIdNs, b"instance_addrs_by_id");
+const INSTANCE_ADDRS_BY_ID: ItemSpace<
+    CanonicalAddr,
+    InstanceAddrsByIdNs,
+    TypedKey<u64>
+> = ItemSpace::new();
+
+// The address of each instance keyed by its label.
+namespace!(InstanceLabelsNs, b"instance_labels");
+const INSTANCE_LABELS: ItemSpace<
+    CanonicalAddr,
+    InstanceLabelsNs,
+    TypedKey<String>
+> = ItemSpace::new();
+
+namespace!(InstanceCountNs, b"instance_count");
+const INSTANCE_COUNT: SingleItem<u64, InstanceCountNs> = SingleItem::new();
+
+// The configuration that each instance was created with if
+// Config::store_init_msgs was set. The message is serialized.
+namespace!(InstanceInitMsgsNs, b"instance_init_msgs");
+const INSTANCE_INIT_MSGS: ItemSpace<
+    InstanceConfig<Binary>,
+    InstanceInitMsgsNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+// The ID of each instance keyed by its address. Allows checking
+// whether an address is an instance without loading it.
+namespace!(InstanceIdsNs, b"instance_ids");
+const INSTANCE_IDS: ItemSpace<
+    u64,
+    InstanceIdsNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+/// Context saved by [`GenericFactory::create_instance`] that is
+/// needed in order to register the instance when the reply arrives.
+/// See [`pending`].
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct PendingInstance {
+    code: ContractCode,
+    label: String,
+    /// Received along with the message that created the instance.
+    funds: Vec<Coin>,
+    created: InstanceCreation<CanonicalAddr>,
+    init: Option<InstanceConfig<Binary>>,
+    seq: Option<u64>,
+    deposit: Option<Deposit>,
+    extra: Option<Binary>,
+    /// Set if [`Config::track_failures`] is so
+    /// that failed instantiations can be retried.
+    config: Option<InstanceConfig<Binary>>,
+    callback: Option<Binary>,
+    /// The hash of the registration token if
+    /// [`Config::require_self_registration`] is set.
+    registration: Option<Binary>
+}
+
+// The number of instantiate messages dispatched. Never decreases so that
+// generated labels are unique even for instances created in the same block.
+namespace!(CreationSeqNs, b"creation_seq");
+const CREATION_SEQ: SingleItem<u64, CreationSeqNs> = SingleItem::new();
+
 impl<
-    MSG: Serialize,
+    MSG: Serialize + 'static,
     EXTRA: ExtraData,
-    const AUTH: bool
-> GenericFactory<MSG, EXTRA, AUTH> {
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
     pub fn instantiate(
         mut deps: DepsMut,
-        _env: Env,
+        env: Env,
         info: MessageInfo,
-        msg: InstantiateMsg
+        msg: InstantiateMsg<MSG>
     ) -> StdResult<Response> {
         admin::init(deps.branch(), msg.admin.as_deref(), &info)?;
+
+        if let Some(config) = msg.config {
+            config.validate(deps.api)?;
+            config.save(deps.storage)?;
+        }
+
+        Self::verify_code(deps.as_ref(), &msg.code)?;
+        let event = Self::record_code_change(deps.branch(), &env, &info.sender, &msg.code)?;
         CONTRACT.save(deps.storage, &msg.code)?;
 
-        Ok(Response::default())
+        let resp = Response::default().add_event(event);
+
+        let Some(config) = msg.initial_instance else {
+            return Ok(resp);
+        };
+
+        let refund = Self::charge_fee(deps.branch(), &info, &config.funds, 1, 1)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
+
+        let child = Self::instantiate_child(deps, env, info, config, deposit)?;
+
+        Ok(resp
+            .add_submessages(child.messages)
+            .add_messages(refund)
+        )
     }
+}
 
+impl<
+    MSG: Serialize + DeserializeOwned + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Requires `MSG` to be deserializable in order to
+    /// decode the instance config sent with [`ExecuteMsg::Receive`].
+    #[inline]
     pub fn execute(
         deps: DepsMut,
         env: Env,
         info: MessageInfo,
         msg: ExecuteMsg<MSG>
     ) -> StdResult<Response> {
-        if !matches!(msg, ExecuteMsg::Killswitch(_)) {
+        Self::execute_with(deps, env, info, msg, Self::set_instance_extra)
+    }
+
+    /// `set_extra` replaces the extra data of an instance so
+    /// that [`GenericFactory::execute_indexed`] can re-index it.
+    pub(crate) fn execute_with(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: ExecuteMsg<MSG>,
+        set_extra: fn(DepsMut, &CanonicalAddr, &Binary) -> StdResult<bool>
+    ) -> StdResult<Response> {
+        if !matches!(
+            msg,
+            ExecuteMsg::Killswitch(_) |
+            ExecuteMsg::ContinueKillswitchPropagation { .. }
+        ) {
             killswitch::assert_is_operational(deps.as_ref())?;
         }
 
         match msg {
             ExecuteMsg::CreateInstance(config) =>
                 Self::create_instance(deps, env, info, config),
+            ExecuteMsg::CreateInstances(configs) =>
+                Self::create_instances(deps, env, info, configs),
+            ExecuteMsg::CreateInstanceUnregistered(config) =>
+                Self::create_instance_unregistered(deps, env, info, config),
+            ExecuteMsg::ProposeInstance(config) =>
+                Self::propose_instance(deps, env, info, config),
+            ExecuteMsg::ApproveInstance { id } =>
+                Self::approve_instance(deps, env, info, id),
+            ExecuteMsg::RejectInstance { id } =>
+                Self::reject_instance(deps, info, id),
+            ExecuteMsg::PruneExpired { limit } =>
+                Self::prune_expired(deps, env, limit),
+            ExecuteMsg::RetryInstantiation { failure_id } =>
+                Self::retry_instantiation(deps, env, info, failure_id),
+            ExecuteMsg::CreateInstanceWithPermit { config, permit, signature } =>
+                Self::create_instance_with_permit(deps, env, info, config, permit, signature),
+            ExecuteMsg::SetPermitSigner { pubkey } =>
+                Self::set_permit_signer(deps, info, pubkey),
+            ExecuteMsg::CreateInstanceRaw(config) =>
+                Self::create_instance_raw(deps, env, info, config),
             ExecuteMsg::ChangeContractCode(code) =>
-                Self::change_contract_code(deps, info, &code),
+                Self::change_contract_code(deps, env, info, &code),
+            ExecuteMsg::ConfirmCodeChange(code) =>
+                Self::confirm_code_change(deps, env, info, code),
+            ExecuteMsg::ApplyCodeChange { } =>
+                Self::apply_code_change(deps, env),
+            ExecuteMsg::ConfirmConfirmerChange(change) =>
+                Self::confirm_confirmer_change(deps, info, change),
+            ExecuteMsg::CancelDelayDecrease { } =>
+                Self::cancel_delay_decrease(deps, info),
+            ExecuteMsg::RegisterCode { key, code, metadata } =>
+                Self::register_code(deps, env, info, key, code, metadata),
+            ExecuteMsg::ConfirmCodeRegistration { key, code } =>
+                Self::confirm_code_registration(deps, env, info, key, code),
+            ExecuteMsg::ApplyCodeRegistration { key } =>
+                Self::apply_code_registration(deps, env, key),
+            ExecuteMsg::SetCodeMetadata { key, metadata } =>
+                Self::set_code_metadata(deps, info, key, metadata),
+            ExecuteMsg::RemoveCode { key } =>
+                Self::remove_code(deps, info, key),
+            ExecuteMsg::DeprecateCode { code_key, sunset } =>
+                Self::deprecate_code(deps, env, info, code_key, sunset),
+            ExecuteMsg::UndeprecateCode { code_key } =>
+                Self::undeprecate_code(deps, info, code_key),
+            ExecuteMsg::SetCodePaused { code_key, paused } =>
+                Self::set_code_paused(deps, info, code_key, paused),
+            ExecuteMsg::SetCodeAllowlist { code_key, addresses } =>
+                Self::set_code_allowlist(deps, info, code_key, addresses),
+            ExecuteMsg::SetCodeCallback { code_key, callback } =>
+                Self::set_code_callback(deps, info, code_key, callback),
+            ExecuteMsg::UpdateInstanceExtra { addr, extra } =>
+                Self::update_instance_extra_with(deps, info, addr, extra, set_extra),
+            ExecuteMsg::SetMyExtra { extra } =>
+                Self::set_my_extra_with(deps, info, extra, set_extra),
+            ExecuteMsg::TagInstance { addr, tag } =>
+                Self::tag_instance(deps, info, addr, tag),
+            ExecuteMsg::UntagInstance { addr, tag } =>
+                Self::untag_instance(deps, info, addr, tag),
+            ExecuteMsg::RemoveInstance { addr } =>
+                Self::remove_instance(deps, info, addr),
+            ExecuteMsg::SetInstanceStatus { addr, status } =>
+                Self::set_instance_status(deps, info, addr, status),
+            ExecuteMsg::ExecuteOnInstance { addr, msg, funds } =>
+                Self::execute_on_instance(deps, info, addr, msg, funds),
+            ExecuteMsg::StartBroadcast { msg } =>
+                Self::start_broadcast(deps, info, msg),
+            ExecuteMsg::ContinueBroadcast { batch_size } =>
+                Self::continue_broadcast(deps, info, batch_size),
+            ExecuteMsg::CancelBroadcast { } =>
+                Self::cancel_broadcast(deps, info),
+            ExecuteMsg::StartHealthCheck { query } =>
+                Self::start_health_check(deps, info, query),
+            ExecuteMsg::ContinueHealthCheck { batch_size } =>
+                Self::continue_health_check(deps, env, info, batch_size),
+            ExecuteMsg::ContinueKillswitchPropagation { batch_size } =>
+                Self::continue_killswitch_propagation(deps, info, batch_size),
+            ExecuteMsg::RegisterExisting { address, label, extra } =>
+                Self::register_existing(deps, env, info, address, label, extra, set_extra),
+            ExecuteMsg::SyncInstanceCodeHash { addr } =>
+                Self::sync_instance_code_hash(deps, addr),
+            ExecuteMsg::RegisterSelf { token } =>
+                Self::register_self(deps, info, token),
+            ExecuteMsg::SetAuthMode { mode } =>
+                Self::set_auth_mode(deps, info, mode),
+            ExecuteMsg::AddCreators { addrs } =>
+                Self::add_creators(deps, info, addrs),
+            ExecuteMsg::RemoveCreators { addrs } =>
+                Self::remove_creators(deps, info, addrs),
+            ExecuteMsg::BlockCreators { addrs } =>
+                Self::block_creators(deps, info, addrs),
+            ExecuteMsg::UnblockCreators { addrs } =>
+                Self::unblock_creators(deps, info, addrs),
+            ExecuteMsg::AddOperators { addrs } =>
+                Self::add_operators(deps, info, addrs),
+            ExecuteMsg::RemoveOperators { addrs } =>
+                Self::remove_operators(deps, info, addrs),
+            ExecuteMsg::SetTokenGateKey { key } =>
+                Self::set_token_gate_key(deps, info, key),
+            ExecuteMsg::SetFeeConfig(config) =>
+                Self::set_fee_config(deps, env, info, config),
+            ExecuteMsg::SetFeeDiscount { addr, percent } =>
+                Self::set_fee_discount(deps, info, addr, percent),
+            ExecuteMsg::WithdrawFees { } =>
+                Self::withdraw_fees(deps, info),
+            ExecuteMsg::Receive { from, amount, msg, .. } =>
+                Self::receive(deps, env, info, from.into_string(), amount, msg),
+            ExecuteMsg::UpdateConfig(config) =>
+                Self::update_config(deps, env, info, config),
             ExecuteMsg::Admin(msg) => match msg {
                 admin::ExecuteMsg::ChangeAdmin { mode } =>
                     admin::DefaultImpl::change_admin(
@@ -148,24 +775,60 @@ impl<
             }
             ExecuteMsg::Killswitch(msg) => match msg {
                 killswitch::ExecuteMsg::SetStatus { status } =>
-                    killswitch::DefaultImpl::set_status(
-                        deps,
-                        env,
-                        info,
-                        status
-                    )
+                    Self::set_status(deps, env, info, status)
             }
+            #[cfg(feature = "cw-ownable")]
+            ExecuteMsg::UpdateOwnership(action) =>
+                Self::update_ownership(deps, env, info, action)
         }
     }
+}
 
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
     pub fn query(
         deps: Deps,
         env: Env,
         msg: QueryMsg
     ) -> StdResult<Binary> {
+        if matches!(
+            msg,
+            QueryMsg::ListInstances { .. } |
+            QueryMsg::ListInstanceAddresses { .. } |
+            QueryMsg::InstanceByAddr { .. } |
+            QueryMsg::IsInstance { .. } |
+            QueryMsg::InstanceByLabel { .. } |
+            QueryMsg::InstanceExtraByAddr { .. } |
+            QueryMsg::InstanceInitMsg { .. } |
+            QueryMsg::InstancesByAddrs { .. } |
+            QueryMsg::InstanceById { .. } |
+            QueryMsg::InstancesByCodeId { .. } |
+            QueryMsg::ListInstancesByCreator { .. } |
+            QueryMsg::CreatorStats { .. } |
+            QueryMsg::InstancesByTag { .. } |
+            QueryMsg::InstanceTags { .. } |
+            QueryMsg::InstancesByIndex { .. } |
+            QueryMsg::InstancesCreatedBetween { .. } |
+            QueryMsg::ListInstancesSince { .. } |
+            QueryMsg::TotalInstances { } |
+            QueryMsg::QueryInstance { .. }
+        ) {
+            Self::assert_can_query(deps)?;
+        }
+
         match msg {
-            QueryMsg::ListInstances { pagination } => {
-                let result = Self::list_instances(deps, pagination)?;
+            QueryMsg::ListInstances { pagination, start_after, filter } => {
+                let result = Self::list_instances(deps, pagination, start_after, filter)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListInstanceAddresses { pagination } => {
+                let result = Self::list_instance_addresses(deps, pagination)?;
 
                 to_binary(&result)
             }
@@ -174,6 +837,223 @@ impl<
 
                 to_binary(&result)
             }
+            QueryMsg::IsInstance { addr } => {
+                let result = Self::is_instance(deps, addr)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstanceByLabel { label } => {
+                let result = Self::instance_by_label(deps, label)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstanceExtraByAddr { addr } => {
+                let result = Self::instance_extra_by_addr(deps, addr)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstanceInitMsg { addr } => {
+                let result = Self::instance_init_msg(deps, addr)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstancesByAddrs { addrs } => {
+                let result = Self::instances_by_addrs(deps, addrs)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstanceById { id } => {
+                let result = Self::instance_by_id(deps, id)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstancesByCodeId { code_id, pagination } => {
+                let result = Self::instances_by_code_id(deps, code_id, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListInstancesByCreator { creator, pagination } => {
+                let result = Self::list_instances_by_creator(deps, creator, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CreatorStats { creator } => {
+                let result = Self::creator_stats(deps, creator)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstancesByTag { tag, pagination } => {
+                let result = Self::instances_by_tag(deps, tag, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstanceTags { addr } => {
+                let result = Self::instance_tags(deps, addr)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstancesByIndex { index, key, pagination } => {
+                let result = Self::instances_by_index(deps, &index, &key, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::InstancesCreatedBetween { from, to, pagination } => {
+                let result = Self::instances_created_between(deps, from, to, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListInstancesSince { id_or_height, pagination } => {
+                let result = Self::list_instances_since(deps, id_or_height, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::TotalInstances { } => {
+                let result = Self::total_instances(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ContractCode { } => {
+                let result = Self::contract_code(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListCodes { pagination } => {
+                let result = Self::list_codes(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CodeByKey { key } => {
+                let result = Self::code_by_key(deps, key)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CodeHistory { pagination } => {
+                let result = Self::code_history(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::PendingCodeChange { } => {
+                let result = Self::pending_code_change(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::PendingCodeRegistration { key } => {
+                let result = Self::pending_code_registration(deps, key)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::PendingDelayDecrease { } => {
+                let result = Self::pending_delay_decrease(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::PendingConfirmerChange { } => {
+                let result = Self::pending_confirmer_change(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CodeDeprecation { code_id } => {
+                let result = Self::code_deprecation(deps, code_id)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CodePaused { code_key } => {
+                let result = Self::is_code_paused(deps, code_key.as_ref())?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CodeAllowlist { code_key } => {
+                let result = Self::code_allowlist(deps, code_key.as_ref())?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CodeCallback { code_key } => {
+                let result = Self::code_callback(deps, code_key.as_ref())?;
+
+                to_binary(&result)
+            }
+            QueryMsg::Config { } => {
+                let result = Self::config(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::AuthMode { } => {
+                let result = Self::auth_mode(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListCreators { pagination } => {
+                let result = Self::list_creators(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListBlockedCreators { pagination } => {
+                let result = Self::list_blocked_creators(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListOperators { pagination } => {
+                let result = Self::list_operators(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::Proposal { id } => {
+                let result = Self::proposal(deps, id)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::ListProposals { pagination } => {
+                let result = Self::list_proposals(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::PermitSigner { } => {
+                let result = Self::permit_signer(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::FeeConfig { } => {
+                let result = Self::fee_config(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::FeeDiscount { addr } => {
+                let result = Self::fee_discount(deps, addr)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CollectedFees { } => {
+                let result = Self::collected_fees(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::FailedInstantiations { pagination } => {
+                let result = Self::failed_instantiations(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::Broadcast { } => {
+                let result = Self::broadcast(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::QueryInstance { addr, msg } =>
+                Self::query_instance(deps, addr, msg),
+            QueryMsg::UnhealthyInstances { pagination } => {
+                let result = Self::unhealthy_instances(deps, pagination)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::KillswitchPropagation { } => {
+                let result = Self::killswitch_propagation(deps)?;
+
+                to_binary(&result)
+            }
+            QueryMsg::CollectedTokenFees { } => {
+                let result = Self::collected_token_fees(deps)?;
+
+                to_binary(&result)
+            }
             QueryMsg::Admin(msg) => match msg {
                 admin::QueryMsg::Admin { } => {
                     let admin = admin::DefaultImpl::admin(deps, env)?;
@@ -188,28 +1068,72 @@ impl<
                     to_binary(&result)
                 }
             }
+            #[cfg(feature = "cw-ownable")]
+            QueryMsg::Ownership { } => {
+                let result = Self::ownership(deps)?;
+
+                to_binary(&result)
+            }
         }
     }
 
     /// The reply entry point to use if you don't have any custom logic.
+    /// Sets the [`ContractLink`] of the new instance as the response data.
     /// If you do, use [`GenericFactory::handle_reply`] which leaves
-    /// matching the reply ID and result up to you.
+    /// matching the reply ID and result up to you. Each instance is
+    /// created with its own reply ID from [`GenericFactory::REPLY_IDS`].
+    #[inline]
     pub fn reply(
         deps: DepsMut,
         _env: Env,
         reply: Reply
     ) -> StdResult<Response> {
-        if reply.id != REPLY_ID {
-            return Err(StdError::generic_err(
-                format!("Expecting reply with id: {REPLY_ID}.")
-            ));
+        Self::reply_with(deps, reply, Self::handle_reply)
+    }
+
+    /// Also refunds the deposit locked for the instance, if any, sends it the
+    /// callback of its code (see [`GenericFactory::set_code_callback`]) and
+    /// calls [`FactoryHooks::after_registered`].
+    fn reply_with(
+        mut deps: DepsMut,
+        reply: Reply,
+        handle_reply: fn(DepsMut, u64, SubMsgResponse) -> StdResult<Instance<Addr, EXTRA>>
+    ) -> StdResult<Response> {
+        if !Self::REPLY_IDS.contains(reply.id) {
+            return Err(StdError::generic_err(format!(
+                "Expecting reply with id in range: {}..{}.",
+                Self::REPLY_IDS.start(),
+                Self::REPLY_IDS.end()
+            )));
         }
 
         let response = if let SubMsgResult::Ok(resp) = reply.result {
-            let addr = Self::handle_reply(deps, resp)?;
+            let (deposit, callback) = pending::peek(deps.storage, reply.id)?
+                .map(|x| (x.deposit, x.callback))
+                .unwrap_or_default();
+
+            let instance = handle_reply(deps.branch(), reply.id, resp)?;
+            let hooked = HOOKS::after_registered(deps.branch(), &instance)?;
+
+            let refund = deposit
+                .map(|x| Self::refund_deposit(deps.as_ref(), x))
+                .transpose()?;
+
+            let callback = callback.map(|msg| WasmMsg::Execute {
+                contract_addr: instance.contract.address.to_string(),
+                code_hash: instance.contract.code_hash.clone(),
+                msg,
+                funds: vec![]
+            });
 
             Response::default()
-                .add_attribute_plaintext(INSTANCE_ADDR_ATTR, addr)
+                .set_data(to_binary(&instance.contract)?)
+                .add_attribute_plaintext(INSTANCE_ADDR_ATTR, instance.contract.address)
+                .add_messages(refund)
+                .add_messages(callback)
+                .add_submessages(hooked)
+        } else if let SubMsgResult::Err(error) = reply.result {
+            Self::handle_failure(deps, reply.id, error)?
         } else {
             Response::default()
         };
@@ -219,308 +1143,6524 @@ impl<
 
     /// Lower level function to use when you have additional logic
     /// in your reply handler. Otherwise, use [`GenericFactory::reply`].
-    /// You should match the ID of the reply with [`REPLY_ID`] and then
-    /// call this function. Returns the address of the new instance.
-    pub fn handle_reply(deps: DepsMut, resp: SubMsgResponse) -> StdResult<Addr> {
-        let Some(data) = resp.data else {
-            return Err(StdError::generic_err(format!(
-                "Expecting non-empty data in reply of type {}.",
-                type_name::<InstantiateReplyData<EXTRA>>()
-            )));
+    /// You should check that the ID of the reply is in [`GenericFactory::REPLY_IDS`],
+    /// which your own submessages should avoid, and then call this function
+    /// with it. Returns the new instance.
+    ///
+    /// If the child doesn't set [`InstantiateReplyData`], its address is
+    /// taken from the `MsgInstantiateContractResponse` or the `instantiate`
+    /// event of the reply. Its extra data must then be given in
+    /// [`InstanceConfig::extra`] unless `EXTRA` can be deserialized from
+    /// an empty JSON object, like [`Empty`].
+    pub fn handle_reply(
+        mut deps: DepsMut,
+        id: u64,
+        resp: SubMsgResponse
+    ) -> StdResult<Instance<Addr, EXTRA>> {
+        let mut pending = pending::take(deps.storage, id)?;
+
+        // The data set by the child may be wrapped in a MsgInstantiateContractResponse.
+        let (chain_address, data) = match resp.data {
+            Some(data) => match reply::parse_instantiate_response(&data) {
+                Some(resp) => (Some(resp.address), resp.data),
+                None => (None, Some(data))
+            },
+            None => (None, None)
         };
 
-        let data: InstantiateReplyData<EXTRA> = from_binary(&data)?;
+        // The extra data set by the child takes precedence over the one given
+        // when creating the instance so that creators can't misrepresent it.
+        let (address, extra) = match (data, pending.extra.take()) {
+            (Some(data), extra) => match from_binary::<InstantiateReplyData<EXTRA>>(&data) {
+                Ok(data) => (Some(data.address), data.extra),
+                Err(err) => {
+                    let address = from_binary::<ReplyAddress>(&data)
+                        .ok()
+                        .map(|x| x.address);
 
-        let contract = CONTRACT.load_or_error(deps.storage)?;
-        let mut instances = Self::instances();
+                    let extra = match extra {
+                        Some(extra) => from_binary(&extra)?,
+                        None => Self::default_extra().ok_or(err)?
+                    };
 
-        let address = data.address.as_ref().canonize(deps.api)?;
-        let key = address.clone(); // it is what it is...
-        
-        instances.insert(
-            deps.storage,
-            &key,
-            &Instance {
-                contract: ContractLink {
-                    address,
-                    code_hash: contract.code_hash
-                },
-                extra: data.extra
+                    (address, extra)
+                }
+            },
+            (None, Some(extra)) => (None, from_binary(&extra)?),
+            (None, None) => {
+                let Some(extra) = Self::default_extra() else {
+                    return Err(StdError::generic_err(format!(
+                        "Expecting non-empty data in reply of type {}.",
+                        type_name::<InstantiateReplyData<EXTRA>>()
+                    )));
+                };
+
+                (None, extra)
             }
-        )?;
+        };
 
-        Ok(data.address)
-    }
+        let actual = chain_address.or_else(|| reply::instantiated_address(&resp.events));
 
-    pub fn create_instance(
-        deps: DepsMut,
-        env: Env,
-        info: MessageInfo,
-        config: InstanceConfig<MSG>
-    ) -> StdResult<Response> {
-        if AUTH {
-            admin::assert(deps.as_ref(), &info)?;
+        // Don't trust the child with its own address if the chain says otherwise
+        // or doesn't say, unless the admin explicitly allowed the latter.
+        match (&address, &actual) {
+            (Some(claimed), Some(actual)) if claimed.as_str() != actual =>
+                return Err(FactoryError::AddressMismatch {
+                    claimed: claimed.to_string(),
+                    actual: actual.clone()
+                }.into()),
+            (Some(claimed), None) if !Config::load(deps.storage)?.allow_unverified_addresses =>
+                return Err(FactoryError::UnverifiedAddress {
+                    claimed: claimed.to_string()
+                }.into()),
+            _ => { }
         }
 
-        let contract = CONTRACT.load_or_error(deps.storage)?;
-        let label = format!(
-            "Fadroma factory child instance created at: {}",
-            env.block.time.seconds()
-        );
-    
-        let msg = SubMsg::reply_on_success(
-            WasmMsg::Instantiate {
-                code_id: contract.id,
-                code_hash: contract.code_hash,
-                msg: to_binary(&config.msg)?,
-                funds: config.funds,
-                label
-            },
-            REPLY_ID
-        );
-    
-        Ok(Response::default().add_submessage(msg))
-    }
+        let address = address
+            .or_else(|| actual.map(Addr::unchecked))
+            .ok_or_else(|| StdError::generic_err(
+                "Couldn't find the address of the new instance in the reply."
+            ))?;
 
-    #[admin::require_admin]
-    pub fn change_contract_code(
-        deps: DepsMut,
-        info: MessageInfo,
-        code: &ContractCode
-    ) -> StdResult<Response> {
-        CONTRACT.save(deps.storage, code)?;
+        // The chain may have normalized the label that it was instantiated with.
+        if let Some(label) = reply::instantiated_label(&resp.events) {
+            pending.label = label;
+        }
 
-        Ok(Response::default())
+        let canonical = address.as_str().canonize(deps.api)?;
+        let instance = Self::register_instance(deps.branch(), canonical, pending, extra)?;
+
+        instance.humanized(deps.api)
     }
 
-    pub fn list_instances(deps: Deps, pagination: Pagination) ->
-        StdResult<PaginatedResponse<Instance<Addr, EXTRA>>>
-    {
-        let limit = pagination.limit.min(Pagination::MAX_LIMIT);
+    /// Same as [`GenericFactory::handle_reply`] but only
+    /// returns the address of the new instance.
+    #[inline]
+    pub fn handle_reply_address(deps: DepsMut, id: u64, resp: SubMsgResponse) -> StdResult<Addr> {
+        let instance = Self::handle_reply(deps, id, resp)?;
 
-        let instances = Self::instances();
-        let iter = instances.values(deps.storage)?;
-        let total = iter.len();
+        Ok(instance.contract.address)
+    }
 
-        let iter = iter
-            .skip(pagination.start as usize)
-            .take(limit as usize);
+    /// The extra data of instances whose child and creator didn't provide any.
+    #[inline]
+    fn default_extra() -> Option<EXTRA> {
+        from_slice(b"{}").ok()
+    }
+
+    /// Stores a new instance and adds it to all indexes.
+    fn register_instance(
+        deps: DepsMut,
+        address: CanonicalAddr,
+        pending: PendingInstance,
+        extra: EXTRA
+    ) -> StdResult<Instance<CanonicalAddr, EXTRA>> {
+        let key = address.clone(); // it is what it is...
 
-        let mut entries = Vec::with_capacity(iter.len());
-        for instance in iter {
-            let instance = instance?;
+        // IDs are sequential and never reused, so the next
+        // ID is the number of instances ever registered.
+        let id = Self::instance_times().len(deps.storage)?;
+        let count = INSTANCE_COUNT.load_or_default(deps.storage)?;
+        INSTANCE_COUNT.save(deps.storage, &(count + 1))?;
+        INSTANCE_IDS.save(deps.storage, &key, &id)?;
+        INSTANCE_ADDRS_BY_ID.save(deps.storage, &id, &key)?;
+        Self::instance_times().push(deps.storage, &pending.created.block_time.nanos())?;
+        Self::instance_heights().push(deps.storage, &pending.created.block_height)?;
+        Self::code_instances(&pending.code.id).push(deps.storage, &key)?;
+        Self::code_hash_instances(&pending.code.code_hash).push(deps.storage, &key)?;
+        Self::creator_instances(&pending.created.creator).push(deps.storage, &key)?;
+        Self::record_creation(deps.storage, &pending.created.creator, pending.created.block_time)?;
 
-            entries.push(Instance {
-                contract: instance.contract.humanize(deps.api)?,
-                extra: instance.extra
-            });
+        if !pending.label.is_empty() {
+            INSTANCE_LABELS.save(deps.storage, &pending.label, &key)?;
         }
 
-        Ok(PaginatedResponse {
-            total,
-            entries
-        })
-    }
+        if let Some(init) = &pending.init {
+            INSTANCE_INIT_MSGS.save(deps.storage, &key, init)?;
+        }
 
-    pub fn instance_by_addr(deps: Deps, addr: String) ->
-        StdResult<Option<Instance<Addr, EXTRA>>>
-    {
-        let addr = addr.as_str().canonize(deps.api)?;
+        let status = match &pending.registration {
+            Some(hash) => {
+                register::await_registration(deps.storage, &key, hash)?;
 
-        let instances = Self::instances();
-        let Some(instance) = instances.get(deps.storage, &addr)? else {
-            return Ok(None);
+                InstanceStatus::Pending
+            }
+            None => InstanceStatus::Active
         };
 
-        Ok(Some(Instance {
-            contract: instance.contract.humanize(deps.api)?,
-            extra: instance.extra
-        }))
-    }
+        Self::all_ids().insert(deps.storage, id)?;
+        Self::status_ids(status).insert(deps.storage, id)?;
 
-    #[inline]
-    fn instances<'a>() -> InsertOnlyMap<
-        TypedKey<'a, CanonicalAddr>,
-        Instance<CanonicalAddr, EXTRA>,
-        InstancesNs
-    > {
-        InsertOnlyMap::new()
+        let instance = Instance {
+            id,
+            contract: ContractLink {
+                address,
+                code_hash: pending.code.code_hash
+            },
+            code_id: pending.code.id,
+            label: pending.label,
+            funds: pending.funds,
+            created: pending.created,
+            seq: pending.seq,
+            status,
+            extra
+        };
+
+        Self::instances().save(deps.storage, &key, &instance)?;
+
+        Ok(instance)
     }
-}
 
-impl Pagination {
-    pub const MAX_LIMIT: u8 = 30;
+    pub fn create_instance(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: InstanceConfig<MSG>
+    ) -> StdResult<Response> {
+        Self::assert_can_create(deps.as_ref(), &info)?;
+        Self::apply_rate_limit(deps.branch(), &env, &info)?;
+        let refund = Self::charge_fee(deps.branch(), &info, &config.funds, 1, 1)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
 
-    #[inline]
-    pub fn new(start: u64, limit: u8) -> Self {
-        Self { start, limit}
+        let resp = Self::instantiate_child(deps, env, info, config, deposit)?;
+
+        Ok(resp.add_messages(refund))
     }
-}
 
-impl InstantiateReplyData<Empty> {
-    #[inline]
-    pub fn new(address: Addr) -> Self {
-        Self {
-            address,
-            extra: Empty { }
+    /// Creates an instance without adding it to the instances of the
+    /// factory, which saves the gas and storage of handling the reply.
+    /// It cannot be queried from the factory afterwards. Not allowed while
+    /// [`FeeConfig::deposit`] is set since there is no reply to refund it in.
+    pub fn create_instance_unregistered(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: InstanceConfig<MSG>
+    ) -> StdResult<Response> {
+        if Self::fee_config(deps.as_ref())?.deposit.is_some() {
+            return Err(StdError::generic_err(
+                "Unregistered instances cannot be created while a deposit is required."
+            ));
         }
+
+        Self::assert_can_create(deps.as_ref(), &info)?;
+        Self::apply_rate_limit(deps.branch(), &env, &info)?;
+        let refund = Self::charge_fee(deps.branch(), &info, &config.funds, 1, 0)?;
+
+        let resp = Self::create_child(deps, env, info, config, false, None)?;
+
+        Ok(resp.add_messages(refund))
     }
-}
 
-impl<EXTRA: ExtraData> InstantiateReplyData<EXTRA> {
-    #[inline]
-    pub fn with_extra(address: Addr, extra: EXTRA) -> Self {
-        Self {
-            address,
-            extra
+    /// Creates several instances in a single transaction. The creation
+    /// fee is charged for each of them and the funds that each instance is
+    /// created with must be sent in total.
+    pub fn create_instances(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        configs: Vec<InstanceConfig<MSG>>
+    ) -> StdResult<Response> {
+        if configs.is_empty() {
+            return Err(StdError::generic_err("Expecting at least one instance config."));
         }
-    }
-}
 
-impl<T: JsonSchema +
-    Serialize + DeserializeOwned +
-    FadromaSerialize + FadromaDeserialize
-> ExtraData for T { }
+        Self::assert_can_create(deps.as_ref(), &info)?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use fadroma::{
-        core::ContractLink,
-        ensemble::{
-            ContractEnsemble, ContractHarness, AnyResult, MockEnv,
-            ResponseVariants, ExecuteResponse
+        let mut funds = vec![];
+        let mut labels = HashSet::new();
+
+        for config in &configs {
+            Self::apply_rate_limit(deps.branch(), &env, &info)?;
+
+            // The labels of the other instances in the batch aren't stored yet.
+            if let Some(label) = &config.label {
+                if !labels.insert(label.as_str()) {
+                    return Err(StdError::generic_err(
+                        format!("Label \"{label}\" is used more than once.")
+                    ));
+                }
+            }
+
+            for coin in &config.funds {
+                fees::add_coin(&mut funds, coin);
+            }
         }
-    };
 
-    const ADMIN: &str = "admin";
+        let count = configs.len() as u32;
+        let refund = Self::charge_fee(deps.branch(), &info, &funds, count, count)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
+        let mut resp = Response::default();
 
-    impl<
-        MSG: Serialize + DeserializeOwned,
-        EXTRA: ExtraData,
-        const AUTH: bool
-    > ContractHarness for GenericFactory<MSG, EXTRA, AUTH> {
-        fn instantiate(
-            &self,
-            deps: DepsMut,
-            env: Env,
-            info: MessageInfo,
-            msg: Binary
-        ) -> AnyResult<Response> {
-            let result = Self::instantiate(deps, env, info, from_binary(&msg)?)?;
+        for config in configs {
+            let child = Self::instantiate_child(
+                deps.branch(),
+                env.clone(),
+                info.clone(),
+                config,
+                deposit.clone()
+            )?;
 
-            Ok(result)
+            resp = resp.add_submessages(child.messages);
         }
 
-        fn execute(
-            &self,
-            deps: DepsMut,
-            env: Env,
-            info: MessageInfo,
-            msg: Binary
-        ) -> AnyResult<Response> {
-            let result = Self::execute(deps, env, info, from_binary(&msg)?)?;
+        Ok(resp.add_messages(refund))
+    }
 
-            Ok(result)
+    /// Creates an instance with a pre-serialized instantiate message, for
+    /// when it is produced off-chain or doesn't match `MSG`. Since the
+    /// message isn't an `MSG`, [`FactoryHooks::before_create`] isn't called.
+    #[admin::require_admin]
+    pub fn create_instance_raw(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: InstanceConfig<Binary>
+    ) -> StdResult<Response> {
+        let refund = Self::charge_fee(deps.branch(), &info, &config.funds, 1, 1)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
+
+        let resp = Self::dispatch_child(deps, env, info, config, true, deposit)?;
+
+        Ok(resp.add_messages(refund))
+    }
+
+    /// Changes the default contract code or schedules the change if
+    /// [`Config::code_change_delay`] or [`Config::confirm_code_changes`]
+    /// is set. See [`GenericFactory::apply_code_change`].
+    #[admin::require_admin]
+    pub fn change_contract_code(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        code: &ContractCode
+    ) -> StdResult<Response> {
+        Self::verify_code(deps.as_ref(), code)?;
+        let config = Self::load_config_with_delay(deps.branch(), &env)?;
+
+        if config.code_change_delay > 0 || config.confirm_code_changes {
+            Self::schedule_code_change(deps, &env, &info.sender, code, &config)?;
+
+            return Ok(Response::default());
         }
 
-        fn query(&self, deps: Deps, env: Env, msg: Binary) -> AnyResult<Binary> {
-            let result = Self::query(deps, env, from_binary(&msg)?)?;
+        Self::clear_pending_code_change(deps.branch());
+        let event = Self::record_code_change(deps.branch(), &env, &info.sender, code)?;
+        CONTRACT.save(deps.storage, code)?;
 
-            Ok(result)
+        Ok(Response::default().add_event(event))
+    }
+
+    #[inline]
+    pub fn contract_code(deps: Deps) -> StdResult<ContractCode> {
+        CONTRACT.load_or_error(deps.storage)
+    }
+
+    /// Lists active instances in the order they were created (or the reverse).
+    /// Use `start_after` to resume from the last entry of the previous page
+    /// instead of an offset, which is unaffected by instances created in the
+    /// meantime and doesn't walk the list up to the entry. When filtering by code, removing an instance moves the last
+    /// one created from that code into its place and instances with another
+    /// status are skipped so a page may have fewer entries than requested.
+    pub fn list_instances(
+        deps: Deps,
+        pagination: Pagination,
+        start_after: Option<String>,
+        filter: Option<InstanceFilter>
+    ) -> StdResult<PaginatedResponse<Instance<Addr, EXTRA>>> {
+        let filter = filter.unwrap_or_default();
+        let status = filter.status.unwrap_or_default();
+
+        if filter.code_id.is_some() || filter.code_hash.is_some() {
+            if start_after.is_some() {
+                return Err(StdError::generic_err(
+                    "Cannot use start_after with a filter. Use the next cursor instead."
+                ));
+            }
+
+            return Self::list_filtered_instances(deps, filter, status, pagination);
         }
 
-        fn reply(&self, deps: DepsMut, env: Env, reply: Reply) -> AnyResult<Response> {
-            let result = Self::reply(deps, env, reply)?;
+        // The instance itself may have another status by now.
+        let after = start_after.map(|addr| -> StdResult<u64> {
+            INSTANCE_IDS.load(deps.storage, &addr.as_str().canonize(deps.api)?)?
+                .ok_or_else(|| StdError::generic_err(
+                    format!("No instance with address: {addr}.")
+                ))
+        }).transpose()?;
 
-            Ok(result)
+        let config = Config::load(deps.storage)?;
+        let ids = Self::status_ids(status);
+
+        pagination.paginate_list(&config, deps.storage, &ids, after, |id| {
+            Self::load_by_id(deps, id)?
+                .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?
+                .humanized(deps.api)
+        })
+    }
+
+    fn list_filtered_instances(
+        deps: Deps,
+        filter: InstanceFilter,
+        status: InstanceStatus,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<Instance<Addr, EXTRA>>> {
+        let instances = Self::instances();
+
+        // The same code may have been stored under several IDs so instances are
+        // also listed by hash. All instances of a code ID have the same hash.
+        let addrs = match filter.code_id {
+            Some(code_id) => {
+                let addrs = Self::code_instances(&code_id);
+
+                if let (Some(hash), Some(addr)) = (filter.code_hash, addrs.iter(deps.storage)?.next()) {
+                    let instance = instances.load(deps.storage, &addr?)?
+                        .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?;
+
+                    if instance.contract.code_hash != hash {
+                        return Ok(PaginatedResponse {
+                            entries: vec![],
+                            total: 0,
+                            has_more: false,
+                            next: None
+                        });
+                    }
+                }
+
+                addrs
+            }
+            None => Self::code_hash_instances(&filter.code_hash.unwrap_or_default())
+        };
+
+        let config = Config::load(deps.storage)?;
+
+        let page = pagination.paginate(&config, addrs.iter(deps.storage)?, |addr| {
+            let instance = instances.load(deps.storage, &addr)?
+                .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?;
+
+            if instance.status != status {
+                return Ok(None);
+            }
+
+            instance.humanized(deps.api).map(Some)
+        })?;
+
+        Ok(page.flattened())
+    }
+
+    pub fn list_instance_addresses(deps: Deps, pagination: Pagination) ->
+        StdResult<PaginatedResponse<Addr>>
+    {
+        let ids = Self::all_ids();
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate_list(&config, deps.storage, &ids, None, |id| {
+            INSTANCE_ADDRS_BY_ID.load(deps.storage, &id)?
+                .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?
+                .humanize(deps.api)
+        })
+    }
+
+    pub fn instances_by_code_id(deps: Deps, code_id: u64, pagination: Pagination) ->
+        StdResult<PaginatedResponse<Instance<Addr, EXTRA>>>
+    {
+        Self::paginate_index(deps, Self::code_instances(&code_id), pagination)
+    }
+
+    /// Lists the instances created by `creator` in the order they were created.
+    pub fn list_instances_by_creator(deps: Deps, creator: String, pagination: Pagination) ->
+        StdResult<PaginatedResponse<Instance<Addr, EXTRA>>>
+    {
+        let creator = creator.as_str().canonize(deps.api)?;
+
+        Self::paginate_index(deps, Self::creator_instances(&creator), pagination)
+    }
+
+    /// Lists the instances created at or after `from` and
+    /// before `to` in the order they were created (or the reverse).
+    /// Removed instances are skipped, so a page may have fewer entries
+    /// than requested and `total` still counts them.
+    pub fn instances_created_between(
+        deps: Deps,
+        from: Timestamp,
+        to: Timestamp,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<Instance<Addr, EXTRA>>> {
+        let times = Self::instance_times();
+        let start = Self::first_id_at_or_after(deps, &times, from.nanos())?;
+        let end = Self::first_id_at_or_after(deps, &times, to.nanos())?.max(start);
+
+        Self::paginate_ids(deps, start, end, pagination)
+    }
+
+    /// Lists the instances registered since the given ID or block height
+    /// in the order they were created (or the reverse), for indexers that
+    /// poll for new instances. Removed instances are skipped, so a page
+    /// may have fewer entries than requested and `total` still counts them.
+    pub fn list_instances_since(
+        deps: Deps,
+        id_or_height: IdOrHeight,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<Instance<Addr, EXTRA>>> {
+        let end = Self::instance_times().len(deps.storage)?;
+        let start = match id_or_height {
+            IdOrHeight::Id(id) => id.min(end),
+            IdOrHeight::Height(height) =>
+                Self::first_id_at_or_after(deps, &Self::instance_heights(), height)?
+        };
+
+        Self::paginate_ids(deps, start, end, pagination)
+    }
+
+    /// Loads the page of instances with IDs from `start` up to `end`.
+    fn paginate_ids(
+        deps: Deps,
+        start: u64,
+        end: u64,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<Instance<Addr, EXTRA>>> {
+        let ids = (start as usize..end as usize).map(Ok);
+        let config = Config::load(deps.storage)?;
+
+        let page = pagination.paginate(&config, ids, |id| {
+            Self::instance_by_id(deps, id as u64)
+        })?;
+
+        Ok(page.flattened())
+    }
+
+    /// Returns the ID of the first instance whose value in `index` is at
+    /// least `value` or the number of instances ever created if there is
+    /// none. Instances are registered in the order they were created so
+    /// their creation times and heights are sorted.
+    fn first_id_at_or_after(
+        deps: Deps,
+        index: &IterableStorage<u64, StaticKey>,
+        value: u64
+    ) -> StdResult<u64> {
+        let (mut low, mut high) = (0, index.len(deps.storage)?);
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let current = index.get(deps.storage, mid)?
+                .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?;
+
+            if current < value {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
         }
+
+        Ok(low)
     }
 
-    struct Child;
+    /// The number of instances that haven't been removed.
+    #[inline]
+    pub fn total_instances(deps: Deps) -> StdResult<u64> {
+        INSTANCE_COUNT.load_or_default(deps.storage)
+    }
 
-    #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
-    pub struct ChildInstantiateMsg {
-        text: String
+    pub fn is_instance(deps: Deps, addr: String) -> StdResult<bool> {
+        let addr = addr.as_str().canonize(deps.api)?;
+
+        Ok(INSTANCE_IDS.load(deps.storage, &addr)?.is_some())
     }
 
-    impl ContractHarness for Child {
-        fn instantiate(
-            &self,
-            _deps: DepsMut,
-            env: Env,
-            _info: MessageInfo,
-            msg: Binary
-        ) -> AnyResult<Response> {
-            let msg: ChildInstantiateMsg = from_binary(&msg)?;
+    pub fn instance_by_addr(deps: Deps, addr: String) ->
+        StdResult<Option<Instance<Addr, EXTRA>>>
+    {
+        let addr = addr.as_str().canonize(deps.api)?;
 
-            Ok(Response::new()
-                .set_data(to_binary(&InstantiateReplyData {
-                    address: env.contract.address,
-                    extra: msg.text
-                })?)
-            )
+        let Some(instance) = Self::instances().load(deps.storage, &addr)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(instance.humanized(deps.api)?))
+    }
+
+    /// Returns the instance with the given label as set
+    /// when instantiating it.
+    pub fn instance_by_label(deps: Deps, label: String) ->
+        StdResult<Option<Instance<Addr, EXTRA>>>
+    {
+        let Some(addr) = INSTANCE_LABELS.load(deps.storage, &label)? else {
+            return Ok(None);
+        };
+
+        let instance = Self::instances().load(deps.storage, &addr)?
+            .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?;
+
+        Ok(Some(instance.humanized(deps.api)?))
+    }
+
+    pub fn instance_extra_by_addr(deps: Deps, addr: String) -> StdResult<Option<EXTRA>> {
+        let addr = addr.as_str().canonize(deps.api)?;
+        let instance = Self::instances().load(deps.storage, &addr)?;
+
+        Ok(instance.map(|x| x.extra))
+    }
+
+    pub fn instance_init_msg(deps: Deps, addr: String) ->
+        StdResult<Option<InstanceConfig<Binary>>>
+    {
+        let addr = addr.as_str().canonize(deps.api)?;
+
+        INSTANCE_INIT_MSGS.load(deps.storage, &addr)
+    }
+
+    pub fn instances_by_addrs(deps: Deps, addrs: Vec<String>) ->
+        StdResult<Vec<Option<Instance<Addr, EXTRA>>>>
+    {
+        let max = Config::load(deps.storage)?.max_page_limit;
+
+        if addrs.len() > max as usize {
+            return Err(StdError::generic_err(
+                format!("Cannot query more than {max} instances at once.")
+            ));
         }
 
-        fn execute(
-            &self,
+        addrs.into_iter()
+            .map(|addr| Self::instance_by_addr(deps, addr))
+            .collect()
+    }
+
+    pub fn instance_by_id(deps: Deps, id: u64) ->
+        StdResult<Option<Instance<Addr, EXTRA>>>
+    {
+        let Some(instance) = Self::load_by_id(deps, id)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(instance.humanized(deps.api)?))
+    }
+
+    fn load_by_id(deps: Deps, id: u64) -> StdResult<Option<Instance<CanonicalAddr, EXTRA>>> {
+        let Some(addr) = INSTANCE_ADDRS_BY_ID.load(deps.storage, &id)? else {
+            return Ok(None);
+        };
+
+        let instance = Self::instances().load(deps.storage, &addr)?
+            .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?;
+
+        Ok(Some(instance))
+    }
+
+    /// Loads the page of instances whose addresses are stored in `index`.
+    fn paginate_index(
+        deps: Deps,
+        index: AddrList,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<Instance<Addr, EXTRA>>> {
+        let instances = Self::instances();
+        let config = Config::load(deps.storage)?;
+
+        pagination.paginate(&config, index.iter(deps.storage)?, |addr| {
+            let instance = instances.load(deps.storage, &addr)?
+                .ok_or_else(|| StdError::generic_err("Instance index is corrupted."))?;
+
+            instance.humanized(deps.api)
+        })
+    }
+
+    /// Replaces the extra data of an instance. `extra` is the JSON
+    /// encoded `EXTRA`. Fails for instances indexed using [`IndexedExtra`],
+    /// use [`GenericFactory::execute_indexed`] to update those.
+    /// Can be called by the admin or an operator.
+    #[inline]
+    pub fn update_instance_extra(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+        extra: Binary
+    ) -> StdResult<Response> {
+        Self::update_instance_extra_with(deps, info, addr, extra, Self::set_instance_extra)
+    }
+
+    pub(crate) fn update_instance_extra_with(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+        extra: Binary,
+        set_extra: fn(DepsMut, &CanonicalAddr, &Binary) -> StdResult<bool>
+    ) -> StdResult<Response> {
+        Self::assert_admin_or_operator(deps.as_ref(), &info)?;
+
+        let canonical = addr.as_str().canonize(deps.api)?;
+
+        if !set_extra(deps, &canonical, &extra)? {
+            return Err(StdError::generic_err(
+                format!("No instance with address: {addr}.")
+            ));
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Lets an instance replace its own extra data, for when it changes
+    /// over time. `extra` is the JSON encoded `EXTRA`. Fails for instances
+    /// indexed using [`IndexedExtra`], use [`GenericFactory::execute_indexed`]
+    /// to update those.
+    #[inline]
+    pub fn set_my_extra(
+        deps: DepsMut,
+        info: MessageInfo,
+        extra: Binary
+    ) -> StdResult<Response> {
+        Self::set_my_extra_with(deps, info, extra, Self::set_instance_extra)
+    }
+
+    pub(crate) fn set_my_extra_with(
+        deps: DepsMut,
+        info: MessageInfo,
+        extra: Binary,
+        set_extra: fn(DepsMut, &CanonicalAddr, &Binary) -> StdResult<bool>
+    ) -> StdResult<Response> {
+        let sender = info.sender.as_str().canonize(deps.api)?;
+
+        if !set_extra(deps, &sender, &extra)? {
+            return Err(StdError::generic_err(
+                "Only instances of this factory can set their extra data."
+            ));
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Returns `false` if there is no instance with the given address.
+    /// Fails if the instance was indexed by its extra data since
+    /// the indexes can't be updated without [`IndexedExtra`].
+    fn set_instance_extra(
+        deps: DepsMut,
+        addr: &CanonicalAddr,
+        extra: &Binary
+    ) -> StdResult<bool> {
+        if Self::has_extra_index_keys(deps.storage, addr)? {
+            return Err(StdError::generic_err(
+                "The extra data of indexed instances can only be replaced through execute_indexed."
+            ));
+        }
+
+        let instances = Self::instances();
+        let Some(mut instance) = instances.load(deps.storage, addr)? else {
+            return Ok(false);
+        };
+
+        instance.extra = from_binary(extra)?;
+        instances.save(deps.storage, addr, &instance)?;
+
+        Ok(true)
+    }
+
+    /// Removes the instance and all references to it from the factory.
+    /// The last instance in each index that it was in, such as by code or
+    /// creator, is moved into its place. Its ID is not reused and it is still counted in
+    /// [`CreatorStats`].
+    #[admin::require_admin]
+    pub fn remove_instance(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String
+    ) -> StdResult<Response> {
+        let canonical = addr.as_str().canonize(deps.api)?;
+
+        let instances = Self::instances();
+        let Some(instance) = instances.load(deps.storage, &canonical)? else {
+            return Err(StdError::generic_err(
+                format!("No instance with address: {addr}.")
+            ));
+        };
+
+        instances.remove(deps.storage, &canonical);
+        INSTANCE_IDS.remove(deps.storage, &canonical);
+        INSTANCE_ADDRS_BY_ID.remove(deps.storage, &instance.id);
+        Self::all_ids().remove(deps.storage, instance.id)?;
+        Self::status_ids(instance.status).remove(deps.storage, instance.id)?;
+        INSTANCE_INIT_MSGS.remove(deps.storage, &canonical);
+
+        // Labels aren't unique so a later instance may have taken it.
+        if INSTANCE_LABELS.load(deps.storage, &instance.label)?.as_ref() == Some(&canonical) {
+            INSTANCE_LABELS.remove(deps.storage, &instance.label);
+        }
+
+        Self::code_instances(&instance.code_id).remove(deps.storage, &canonical)?;
+        Self::code_hash_instances(&instance.contract.code_hash).remove(deps.storage, &canonical)?;
+        Self::creator_instances(&instance.created.creator).remove(deps.storage, &canonical)?;
+        Self::remove_tags(deps.storage, &canonical)?;
+        Self::remove_from_extra_indexes(deps.storage, &canonical)?;
+        Self::remove_health(deps.storage, &canonical)?;
+        register::remove_registration(deps.storage, &canonical);
+
+        let count = INSTANCE_COUNT.load_or_default(deps.storage)?;
+        INSTANCE_COUNT.save(deps.storage, &count.saturating_sub(1))?;
+
+        Ok(Response::default())
+    }
+
+    /// Dispatches the instantiate message of a new child. Any
+    /// authorization and fee payment must be handled by the caller.
+    /// The `deposit`, if any, is refunded once the child is created.
+    #[inline]
+    pub(crate) fn instantiate_child(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: InstanceConfig<MSG>,
+        deposit: Option<Deposit>
+    ) -> StdResult<Response> {
+        Self::create_child(deps, env, info, config, true, deposit)
+    }
+
+    /// Every instance created from an `MSG` goes through here
+    /// so that [`FactoryHooks::before_create`] is always called.
+    fn create_child(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: InstanceConfig<MSG>,
+        register: bool,
+        deposit: Option<Deposit>
+    ) -> StdResult<Response> {
+        HOOKS::before_create(deps.as_ref(), &info, &config)?;
+
+        let config = InstanceConfig {
+            msg: Self::encode_msg(&config.msg)?,
+            funds: config.funds,
+            code_key: config.code_key,
+            label: config.label,
+            extra: config.extra
+        };
+
+        Self::dispatch_child(deps, env, info, config, register, deposit)
+    }
+
+    /// Doesn't call any hooks. Use [`GenericFactory::instantiate_child`]
+    /// unless the message isn't an `MSG`. If `register` is `false`, the
+    /// instantiation is not replied to and the child is not added to the
+    /// instances of the factory.
+    fn dispatch_child(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: InstanceConfig<Binary>,
+        register: bool,
+        deposit: Option<Deposit>
+    ) -> StdResult<Response> {
+        let settings = Config::load(deps.storage)?;
+        let creator = info.sender.as_str().canonize(deps.api)?;
+
+        if register {
+            Self::assert_below_caps(deps.as_ref(), &settings, &creator)?;
+        }
+
+        if let Some(extra) = &config.extra {
+            if !register {
+                return Err(StdError::generic_err(
+                    "Unregistered instances cannot have extra data."
+                ));
+            }
+
+            // Fail now rather than when the reply is handled.
+            from_binary::<EXTRA>(extra)?;
+        }
+
+        settings.assert_funds_allowed(&config.funds)?;
+
+        // Whatever the instance is created with must have been received
+        // with this message so that it can be refunded if it fails.
+        for coin in &config.funds {
+            if fees::sent_amount(&info.funds, &coin.denom) < coin.amount {
+                return Err(FactoryError::InsufficientFunds { expected: coin.clone() }.into());
+            }
+        }
+
+        Self::assert_code_not_paused(deps.as_ref(), config.code_key.as_ref())?;
+        Self::assert_can_create_from(deps.as_ref(), config.code_key.as_ref(), &info.sender)?;
+
+        let contract = Self::load_code(deps.as_ref(), config.code_key.as_ref())?;
+        Self::assert_not_sunset(deps.as_ref(), &env, &contract)?;
+
+        let seq = CREATION_SEQ.load_or_default(deps.storage)?;
+        CREATION_SEQ.save(deps.storage, &(seq + 1))?;
+
+        let label = match (config.label.clone(), &settings.label_template) {
+            (Some(_), Some(_)) => return Err(StdError::generic_err(
+                "Labels are set by the label template of the factory."
+            )),
+            (Some(label), None) => {
+                // Otherwise it could take the label of an instance created later.
+                if label.starts_with(GENERATED_LABEL_PREFIX) {
+                    return Err(StdError::generic_err(
+                        "Label is reserved for the labels generated by the factory."
+                    ));
+                }
+
+                Self::validate_label(deps.as_ref(), &label)?;
+
+                label
+            }
+            (None, Some(template)) => {
+                let label = config::expand_label_template(
+                    template,
+                    &info.sender,
+                    seq,
+                    env.block.time.seconds(),
+                    contract.id
+                );
+
+                Self::validate_label(deps.as_ref(), &label)?;
+
+                label
+            }
+            (None, None) => format!(
+                "{GENERATED_LABEL_PREFIX}{seq} created at: {}",
+                env.block.time.seconds()
+            )
+        };
+
+        if !register {
+            let msg = WasmMsg::Instantiate {
+                code_id: contract.id,
+                code_hash: contract.code_hash,
+                msg: config.msg,
+                funds: config.funds,
+                label
+            };
+
+            return Ok(Response::default().add_message(msg));
+        }
+
+        let token = if settings.require_self_registration {
+            Some(register::registration_token(deps.storage, &env, &creator, seq, &config.msg)?)
+        } else {
+            None
+        };
+
+        let callback = Self::code_callback(deps.as_ref(), config.code_key.as_ref())?;
+
+        let reply_id = pending::push(deps.storage, &Self::REPLY_IDS, &PendingInstance {
+            code: contract.clone(),
+            label: label.clone(),
+            funds: config.funds.clone(),
+            created: InstanceCreation {
+                creator,
+                block_height: env.block.height,
+                block_time: env.block.time
+            },
+            init: settings.store_init_msgs.then(|| config.clone()),
+            seq: Some(seq),
+            deposit,
+            extra: config.extra.clone(),
+            config: settings.track_failures.then(|| config.clone()),
+            callback,
+            registration: token.as_ref().map(register::token_hash)
+        })?;
+
+        let msg = WasmMsg::Instantiate {
+            code_id: contract.id,
+            code_hash: contract.code_hash,
+            msg: match &token {
+                Some(token) => register::with_registration_token(&config.msg, token)?,
+                None => config.msg
+            },
+            funds: config.funds,
+            label
+        };
+
+        let msg = if settings.track_failures {
+            SubMsg::reply_always(msg, reply_id)
+        } else {
+            SubMsg::reply_on_success(msg, reply_id)
+        };
+    
+        Ok(Response::default().add_submessage(msg))
+    }
+
+    /// Checks a label set in [`InstanceConfig::label`]
+    /// or generated from [`Config::label_template`].
+    /// Fails if registering another instance of `creator` would exceed
+    /// [`Config::max_instances`] or [`Config::max_instances_per_creator`].
+    pub(crate) fn assert_below_caps(
+        deps: Deps,
+        settings: &Config,
+        creator: &CanonicalAddr
+    ) -> StdResult<()> {
+        // Instances created earlier in the same transaction are still pending.
+        let in_flight = pending::len(deps.storage)?;
+
+        if let Some(max) = settings.max_instances {
+            if INSTANCE_COUNT.load_or_default(deps.storage)? + in_flight >= max {
+                return Err(FactoryError::InstanceCapReached { max }.into());
+            }
+        }
+
+        if let Some(max) = settings.max_instances_per_creator {
+            let count = Self::creator_instances(creator).len(deps.storage)? +
+                pending::count_by_creator(deps.storage, creator)?;
+
+            if count >= max {
+                return Err(FactoryError::CreatorCapReached { max }.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn validate_label(deps: Deps, label: &str) -> StdResult<()> {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return Err(StdError::generic_err(format!(
+                "Label must be between 1 and {MAX_LABEL_LEN} characters long."
+            )));
+        }
+
+        let valid = label.chars().all(|x|
+            x.is_ascii_alphanumeric() || " -_.:/#".contains(x)
+        );
+
+        if !valid {
+            return Err(StdError::generic_err(
+                "Label can only contain letters, digits, spaces and -_.:/#"
+            ));
+        }
+
+        if INSTANCE_LABELS.load(deps.storage, &label.to_string())?.is_some() {
+            return Err(StdError::generic_err(format!("Label \"{label}\" is already taken.")));
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the child instantiate message. If `MSG` is [`Binary`]
+    /// it is passed through as is, which allows using the factory with
+    /// any child contract without knowing its message type at compile time.
+    #[inline]
+    fn encode_msg(msg: &MSG) -> StdResult<Binary> {
+        if let Some(msg) = (msg as &dyn Any).downcast_ref::<Binary>() {
+            return Ok(msg.clone());
+        }
+
+        to_binary(msg)
+    }
+
+    /// The reverse of [`GenericFactory::encode_msg`].
+    fn decode_msg(msg: &Binary) -> StdResult<MSG> where MSG: DeserializeOwned {
+        match (Box::new(msg.clone()) as Box<dyn Any>).downcast::<MSG>() {
+            Ok(msg) => Ok(*msg),
+            Err(_) => from_binary(msg)
+        }
+    }
+
+    #[inline]
+    fn instances<'a>() -> ItemSpace<
+        Instance<CanonicalAddr, EXTRA>,
+        InstancesNs,
+        TypedKey<'a, CanonicalAddr>
+    > {
+        ItemSpace::new()
+    }
+
+    /// The IDs of all instances.
+    #[inline]
+    fn all_ids() -> IdList {
+        IdList::new(&[b"all_ids"])
+    }
+
+    #[inline]
+    fn instance_times() -> IterableStorage<u64, StaticKey> {
+        IterableStorage::new(INSTANCE_TIMES)
+    }
+
+    #[inline]
+    fn instance_heights() -> IterableStorage<u64, StaticKey> {
+        IterableStorage::new(INSTANCE_HEIGHTS)
+    }
+
+    /// The addresses of all instances created from the given code ID.
+    #[inline]
+    fn code_instances(code_id: &u64) -> AddrList {
+        AddrList::new(&[b"code_instances", &code_id.to_be_bytes()])
+    }
+
+    /// The addresses of all instances running the given code hash.
+    #[inline]
+    fn code_hash_instances(code_hash: &str) -> AddrList {
+        AddrList::new(&[b"code_hash_instances", code_hash.as_bytes()])
+    }
+
+    /// The addresses of all instances created by the given address.
+    #[inline]
+    fn creator_instances(creator: &CanonicalAddr) -> AddrList {
+        AddrList::new(&[b"creator_instances", creator.as_slice()])
+    }
+}
+
+impl<EXTRA: ExtraData> Instance<CanonicalAddr, EXTRA> {
+    #[inline]
+    fn humanized(self, api: &dyn Api) -> StdResult<Instance<Addr, EXTRA>> {
+        Ok(Instance {
+            id: self.id,
+            contract: self.contract.humanize(api)?,
+            code_id: self.code_id,
+            label: self.label,
+            funds: self.funds,
+            created: InstanceCreation {
+                creator: self.created.creator.humanize(api)?,
+                block_height: self.created.block_height,
+                block_time: self.created.block_time
+            },
+            seq: self.seq,
+            status: self.status,
+            extra: self.extra
+        })
+    }
+}
+
+// Timestamp doesn't implement the Fadroma binary serialization
+// traits so we store it as nanoseconds instead.
+impl<A: FadromaSerialize> FadromaSerialize for InstanceCreation<A> {
+    #[inline]
+    fn size_hint(&self) -> usize {
+        self.creator.size_hint() +
+            self.block_height.size_hint() +
+            self.block_time.nanos().size_hint()
+    }
+
+    fn to_bytes(&self, ser: &mut Serializer) -> bin_serde::Result<()> {
+        self.creator.to_bytes(ser)?;
+        self.block_height.to_bytes(ser)?;
+
+        self.block_time.nanos().to_bytes(ser)
+    }
+}
+
+impl<A: FadromaDeserialize> FadromaDeserialize for InstanceCreation<A> {
+    fn from_bytes(de: &mut Deserializer) -> bin_serde::Result<Self> {
+        Ok(Self {
+            creator: A::from_bytes(de)?,
+            block_height: u64::from_bytes(de)?,
+            block_time: Timestamp::from_nanos(u64::from_bytes(de)?)
+        })
+    }
+}
+
+impl Pagination {
+    /// The default of [`Config::max_page_limit`].
+    pub const MAX_LIMIT: u8 = 30;
+
+    /// The default of [`Config::default_page_limit`].
+    pub const DEFAULT_LIMIT: u8 = 10;
+
+    #[inline]
+    pub fn new(start: u64, limit: u8) -> Self {
+        Self { start, limit, order: Order::Asc, cursor: None }
+    }
+
+    #[inline]
+    pub fn desc(self) -> Self {
+        Self { order: Order::Desc, ..self }
+    }
+
+    #[inline]
+    pub fn with_cursor(self, cursor: Option<Binary>) -> Self {
+        Self { cursor, ..self }
+    }
+
+    /// Builds the page from an iterator over all entries in storage order,
+    /// converting each entry using `f`. The page size is
+    /// determined by [`Config::page_limit`].
+    ///
+    /// The cursor holds the storage index of the next entry rather than an
+    /// offset so that it keeps pointing to the same entry in either order
+    /// when new entries are appended between pages. Lists of instance IDs
+    /// use [`Pagination::paginate_list`] instead.
+    pub fn paginate<I, T, U: Serialize>(
+        &self,
+        config: &Config,
+        iter: I,
+        mut f: impl FnMut(T) -> StdResult<U>
+    ) -> StdResult<PaginatedResponse<U>>
+        where I: DoubleEndedIterator<Item = StdResult<T>> + ExactSizeIterator
+    {
+        let total = ExactSizeIterator::len(&iter) as u64;
+        let limit = config.page_limit(self.limit) as usize;
+
+        let start = match &self.cursor {
+            Some(cursor) => {
+                let index = Self::decode_cursor(cursor)?;
+
+                match self.order {
+                    Order::Asc => index,
+                    Order::Desc => total.saturating_sub(index.saturating_add(1))
+                }
+            },
+            None => self.start
+        };
+
+        let iter: Box<dyn Iterator<Item = StdResult<T>>> = match self.order {
+            Order::Asc => Box::new(iter.skip(start as usize).take(limit)),
+            Order::Desc => Box::new(iter.rev().skip(start as usize).take(limit))
+        };
+
+        let entries = iter
+            .map(|x| x.and_then(&mut f))
+            .collect::<StdResult<Vec<_>>>()?;
+
+        let end = start.saturating_add(entries.len() as u64);
+        let has_more = end < total;
+
+        let next = has_more.then(|| {
+            let index = match self.order {
+                Order::Asc => end,
+                Order::Desc => total - end - 1
+            };
+
+            Binary::from(index.to_be_bytes().as_slice())
+        });
+
+        Ok(PaginatedResponse {
+            entries,
+            total,
+            has_more,
+            next
+        })
+    }
+
+    /// Builds the page from the instance IDs in `list`, converting each ID
+    /// using `f`. The cursor holds the last ID of the previous page and the
+    /// page continues from the node of that ID, so resuming doesn't depend on
+    /// how far into the list it is. `after` takes precedence over the cursor
+    /// and `start` is only used as an offset if neither is set.
+    pub(crate) fn paginate_list<U: Serialize>(
+        &self,
+        config: &Config,
+        storage: &dyn Storage,
+        list: &IdList,
+        after: Option<u64>,
+        f: impl FnMut(u64) -> StdResult<U>
+    ) -> StdResult<PaginatedResponse<U>> {
+        let limit = config.page_limit(self.limit) as usize;
+
+        let after = match after {
+            Some(id) => Some(id),
+            None => self.cursor.as_ref().map(Self::decode_cursor).transpose()?
+        };
+
+        let (first, offset) = match after {
+            Some(id) => (list.next_after(storage, id, self.order)?, 0),
+            None => (list.first(storage, self.order)?, self.start as usize)
+        };
+
+        // One more than the limit to know whether there are more.
+        let mut ids = list.iter_from(storage, first, self.order)
+            .skip(offset)
+            .take(limit + 1)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        let has_more = ids.len() > limit;
+        ids.truncate(limit);
+
+        let next = ids.last()
+            .filter(|_| has_more)
+            .map(|id| Binary::from(id.to_be_bytes().as_slice()));
+
+        let entries = ids.into_iter()
+            .map(f)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        Ok(PaginatedResponse {
+            entries,
+            total: list.len(storage)?,
+            has_more,
+            next
+        })
+    }
+
+    fn decode_cursor(cursor: &Binary) -> StdResult<u64> {
+        let bytes: [u8; 8] = cursor.as_slice().try_into()
+            .map_err(|_| StdError::generic_err("Invalid pagination cursor."))?;
+
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+impl Order {
+    #[inline]
+    pub fn reverse(self) -> Self {
+        match self {
+            Self::Asc => Self::Desc,
+            Self::Desc => Self::Asc
+        }
+    }
+}
+
+impl<T: Serialize> PaginatedResponse<Option<T>> {
+    /// Drops the entries that were skipped when building the page.
+    #[inline]
+    fn flattened(self) -> PaginatedResponse<T> {
+        PaginatedResponse {
+            entries: self.entries.into_iter().flatten().collect(),
+            total: self.total,
+            has_more: self.has_more,
+            next: self.next
+        }
+    }
+}
+
+impl InstantiateReplyData<Empty> {
+    #[inline]
+    pub fn new(address: Addr) -> Self {
+        Self {
+            address,
+            extra: Empty { }
+        }
+    }
+}
+
+impl<EXTRA: ExtraData> InstantiateReplyData<EXTRA> {
+    #[inline]
+    pub fn with_extra(address: Addr, extra: EXTRA) -> Self {
+        Self {
+            address,
+            extra
+        }
+    }
+}
+
+impl<T: JsonSchema +
+    Serialize + DeserializeOwned +
+    FadromaSerialize + FadromaDeserialize
+> ExtraData for T { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fadroma::{
+        cosmwasm_std::Uint128,
+        core::ContractLink,
+        crypto::sha_256,
+        ensemble::{
+            ContractEnsemble, ContractHarness, AnyResult, MockEnv,
+            ResponseVariants, ExecuteResponse
+        }
+    };
+
+    const ADMIN: &str = "admin";
+
+    impl<
+        MSG: Serialize + DeserializeOwned + 'static,
+        EXTRA: ExtraData,
+        const AUTH: bool,
+        const REPLY: u64,
+        HOOKS: FactoryHooks<MSG, EXTRA>
+    > ContractHarness for GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+        fn instantiate(
+            &self,
+            deps: DepsMut,
+            env: Env,
+            info: MessageInfo,
+            msg: Binary
+        ) -> AnyResult<Response> {
+            let result = Self::instantiate(deps, env, info, from_binary(&msg)?)?;
+
+            Ok(result)
+        }
+
+        fn execute(
+            &self,
+            deps: DepsMut,
+            env: Env,
+            info: MessageInfo,
+            msg: Binary
+        ) -> AnyResult<Response> {
+            let result = Self::execute(deps, env, info, from_binary(&msg)?)?;
+
+            Ok(result)
+        }
+
+        fn query(&self, deps: Deps, env: Env, msg: Binary) -> AnyResult<Binary> {
+            let result = Self::query(deps, env, from_binary(&msg)?)?;
+
+            Ok(result)
+        }
+
+        fn reply(&self, deps: DepsMut, env: Env, reply: Reply) -> AnyResult<Response> {
+            let result = Self::reply(deps, env, reply)?;
+
+            Ok(result)
+        }
+    }
+
+    struct Child;
+
+    #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+    pub struct ChildInstantiateMsg {
+        text: String
+    }
+
+    /// Added to [`ChildInstantiateMsg`] by the factory
+    /// if [`Config::require_self_registration`] is set.
+    #[derive(Deserialize)]
+    struct ChildRegistration {
+        registration_token: Option<Binary>
+    }
+
+    impl ContractHarness for Child {
+        fn instantiate(
+            &self,
+            deps: DepsMut,
+            env: Env,
+            _info: MessageInfo,
+            msg: Binary
+        ) -> AnyResult<Response> {
+            let registration: ChildRegistration = from_binary(&msg)?;
+            let msg: ChildInstantiateMsg = from_binary(&msg)?;
+
+            if msg.text == "fail" {
+                return Err(StdError::generic_err("Child failed.").into());
+            }
+
+            if msg.text == "bricked" {
+                deps.storage.set(b"bricked", &[1]);
+            }
+
+            if let Some(token) = registration.registration_token {
+                deps.storage.set(b"registration_token", to_binary(&token)?.as_slice());
+            }
+
+            let address = if msg.text == "impostor" {
+                Addr::unchecked("somewhere else")
+            } else {
+                env.contract.address
+            };
+
+            Ok(Response::new()
+                .set_data(to_binary(&InstantiateReplyData {
+                    address,
+                    extra: msg.text
+                })?)
+            )
+        }
+
+        fn execute(
+            &self,
+            _deps: DepsMut,
+            _env: Env,
+            _info: MessageInfo,
+            _msg: Binary
+        ) -> AnyResult<Response> {
+            Ok(Response::default())
+        }
+
+        fn query(&self, deps: Deps, _env: Env, msg: Binary) -> AnyResult<Binary> {
+            if deps.storage.get(b"bricked").is_some() {
+                return Err(StdError::generic_err("Child is bricked.").into());
+            }
+
+            let msg: String = from_binary(&msg)?;
+
+            if msg == "registration_token" {
+                return Ok(deps.storage.get(b"registration_token").unwrap_or_default().into());
+            }
+
+            Ok(to_binary(&format!("{msg} answered"))?)
+        }
+    }
+
+    /// A SNIP-20 token in which "holder" has a balance of
+    /// 100. It accepts any execute message without effect.
+    struct GateToken;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum GateTokenQuery {
+        Balance { address: String, key: String }
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum GateTokenAnswer {
+        Balance { amount: Uint128 }
+    }
+
+    impl ContractHarness for GateToken {
+        fn instantiate(
+            &self,
+            _deps: DepsMut,
+            _env: Env,
+            _info: MessageInfo,
+            _msg: Binary
+        ) -> AnyResult<Response> {
+            Ok(Response::default())
+        }
+
+        fn execute(
+            &self,
             _deps: DepsMut,
             _env: Env,
             _info: MessageInfo,
             _msg: Binary
         ) -> AnyResult<Response> {
-            todo!()
+            Ok(Response::default())
+        }
+
+        fn query(&self, _deps: Deps, _env: Env, msg: Binary) -> AnyResult<Binary> {
+            let GateTokenQuery::Balance { address, key } = from_binary(&msg)?;
+
+            if key != "key" {
+                return Err(StdError::generic_err("Wrong viewing key.").into());
+            }
+
+            let amount = if address == "holder" { 100u128 } else { 0 };
+
+            Ok(to_binary(&GateTokenAnswer::Balance { amount: amount.into() })?)
+        }
+    }
+
+    struct Suite {
+        ensemble: ContractEnsemble,
+        factory: ContractLink<Addr>
+    }
+
+    impl Suite {
+        fn new<const AUTH: bool>() -> Self {
+            let mut ensemble = ContractEnsemble::new();
+            let child = ensemble.register(Box::new(Child));
+            let factory = ensemble.register(
+                Box::new(GenericFactory::<ChildInstantiateMsg, String, AUTH> {
+                    msg_phantom: PhantomData,
+                    extra_phantom: PhantomData,
+                    hooks_phantom: PhantomData
+                })
+            );
+
+            let factory = ensemble.instantiate(
+                factory.id,
+                &InstantiateMsg::<ChildInstantiateMsg> {
+                    admin: None,
+                    code: child,
+                    config: None,
+                    initial_instance: None
+                },
+                MockEnv::new(ADMIN, "factory")
+            )
+            .unwrap()
+            .instance;
+
+            Self { ensemble, factory }
+        }
+    }
+
+    #[test]
+    fn only_admin_can_instantiate_when_auth_param_is_true() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+        
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn operators_can_create_instances() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::AddOperators { addrs: vec!["operator".into()] },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let operators: PaginatedResponse<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListOperators { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(operators.entries, [Addr::unchecked("operator")]);
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: String::from("flaming swords")
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("operator", &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateInstanceExtra {
+                addr: extract_instance_addr(&resp),
+                extra: to_binary("sharp arrows").unwrap()
+            },
+            MockEnv::new("operator", &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(ContractCode {
+                id: 2,
+                code_hash: "code_hash".into()
+            }),
+            MockEnv::new("operator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveOperators { addrs: vec!["operator".into()] },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateInstanceExtra {
+                addr: extract_instance_addr(&resp),
+                extra: to_binary("burning shields").unwrap()
+            },
+            MockEnv::new("operator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+    }
+
+    #[test]
+    fn only_admin_can_instantiate_when_auth_param_is_false() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn auth_mode_can_be_changed_at_runtime() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let mode: AuthMode = ensemble.query(
+            &factory.address,
+            &QueryMsg::AuthMode { }
+        ).unwrap();
+
+        assert_eq!(mode, AuthMode::AdminOnly);
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetAuthMode { mode: AuthMode::Anyone },
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetAuthMode { mode: AuthMode::Anyone },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetAuthMode { mode: AuthMode::Allowlist },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::NotAllowedToCreate).to_string()
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::AddCreators { addrs: vec!["not admin".into()] },
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::AddCreators {
+                addrs: vec!["not admin".into(), "other".into(), "not admin".into()]
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        let creators: PaginatedResponse<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListCreators { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(creators.entries, [Addr::unchecked("not admin"), Addr::unchecked("other")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveCreators { addrs: vec!["not admin".into()] },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        let creators: PaginatedResponse<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListCreators { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(creators.entries, [Addr::unchecked("other")]);
+    }
+
+    #[test]
+    fn token_gated_creation() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        let token = ensemble.register(Box::new(GateToken));
+        let token = ensemble.instantiate(
+            token.id,
+            &Empty { },
+            MockEnv::new(ADMIN, "gate_token")
+        ).unwrap().instance;
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetAuthMode {
+                mode: AuthMode::TokenGated(TokenGate::Snip20 {
+                    token,
+                    min_balance: Uint128::new(50)
+                })
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("holder", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::TokenGateKeyMissing).to_string()
+        );
+
+        for sender in ["holder", "not holder"] {
+            ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::SetTokenGateKey { key: "key".into() },
+                MockEnv::new(sender, &factory.address)
+            ).unwrap();
+        }
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("holder", &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("not holder", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::NotTokenHolder).to_string()
+        );
+    }
+
+    #[test]
+    fn blocked_creators_cannot_create_instances() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::BlockCreators { addrs: vec!["spammer".into()] },
+            MockEnv::new("spammer", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::BlockCreators { addrs: vec!["spammer".into()] },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("spammer", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::CreatorBlocked).to_string()
+        );
+
+        let blocked: PaginatedResponse<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListBlockedCreators { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(blocked.entries, [Addr::unchecked("spammer")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UnblockCreators { addrs: vec!["spammer".into()] },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("spammer", &factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn instances_are_stored_with_extra_data() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let (height, time) = (ensemble.block().height, ensemble.block().time);
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        let addr = extract_instance_addr(&resp);
+
+        let is_instance: bool = ensemble.query(
+            &factory.address,
+            &QueryMsg::IsInstance { addr: addr.clone() }
+        ).unwrap();
+
+        assert!(is_instance);
+
+        let is_instance: bool = ensemble.query(
+            &factory.address,
+            &QueryMsg::IsInstance { addr: "wrong addr".into() }
+        ).unwrap();
+
+        assert!(!is_instance);
+
+        let batch: Vec<Option<Instance<Addr, String>>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesByAddrs { addrs: vec!["wrong addr".into(), addr.clone()] }
+        ).unwrap();
+
+        assert_eq!(batch.len(), 2);
+        assert!(batch[0].is_none());
+        assert_eq!(batch[1].as_ref().unwrap().extra, "flaming swords");
+
+        let extra: Option<String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceExtraByAddr { addr: addr.clone() }
+        ).unwrap();
+
+        assert_eq!(extra.unwrap(), "flaming swords");
+
+        let instance: Instance<Addr, String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: addr.clone() }
+        )
+        .unwrap();
+
+        assert!(instance.contract.address.as_str().starts_with("fadroma factory child instance"));
+        assert_eq!(instance.contract.code_hash, "test_contract_0");
+        assert_eq!(instance.extra, "flaming swords");
+        assert_eq!(instance.created.creator.as_str(), "not admin");
+        assert_eq!(instance.created.block_height, height);
+        assert_eq!(instance.created.block_time.seconds(), time);
+
+        assert_eq!(instance.seq, Some(0));
+        assert_eq!(instance.label, format!("Fadroma factory child instance #0 created at: {time}"));
+
+        let by_label: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByLabel { label: instance.label.clone() }
+        ).unwrap();
+
+        assert_eq!(by_label.unwrap().contract, instance.contract);
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: "wrong addr".into() }
+        )
+        .unwrap();
+
+        assert!(instance.is_none());
+
+        let update = ExecuteMsg::<ChildInstantiateMsg>::UpdateInstanceExtra {
+            addr: addr.clone(),
+            extra: to_binary("sharp arrows").unwrap()
+        };
+
+        let err = ensemble.execute(
+            &update,
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(&update, MockEnv::new(ADMIN, &factory.address)).unwrap();
+
+        let set_my_extra = ExecuteMsg::<ChildInstantiateMsg>::SetMyExtra {
+            extra: to_binary("burning shields").unwrap()
+        };
+
+        let err = ensemble.execute(
+            &set_my_extra,
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Only instances of this factory can set their extra data."
+        );
+
+        ensemble.execute(&set_my_extra, MockEnv::new(&addr, &factory.address)).unwrap();
+
+        let extra: Option<String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceExtraByAddr { addr }
+        ).unwrap();
+
+        assert_eq!(extra.unwrap(), "burning shields");
+    }
+
+    #[test]
+    fn binary_msg_is_passed_through() {
+        let mut ensemble = ContractEnsemble::new();
+        let child = ensemble.register(Box::new(Child));
+        let factory = ensemble.register(
+            Box::new(GenericFactory::<Binary, String, false> {
+                msg_phantom: PhantomData,
+                extra_phantom: PhantomData,
+                hooks_phantom: PhantomData
+            })
+        );
+
+        let factory = ensemble.instantiate(
+            factory.id,
+            &InstantiateMsg::<Binary> {
+                admin: None,
+                code: child,
+                config: None,
+                initial_instance: None
+            },
+            MockEnv::new(ADMIN, "factory")
+        )
+        .unwrap()
+        .instance;
+
+        let config = InstanceConfig {
+            msg: to_binary(&ChildInstantiateMsg {
+                text: String::from("raw bytes")
+            }).unwrap(),
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        let addr = extract_instance_addr(&resp);
+
+        let instance: Instance<Addr, String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr }
+        )
+        .unwrap();
+
+        assert_eq!(instance.extra, "raw bytes");
+    }
+
+    #[test]
+    fn each_creation_has_its_own_reply_id() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let configs = ["a", "b", "c"].map(|text| InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: text.into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        });
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstances(configs.to_vec()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let reply_ids = resp.iter()
+            .filter_map(|x| match x {
+                ResponseVariants::Reply(resp) => Some(resp.reply.id),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(reply_ids, [REPLY_ID, REPLY_ID + 1, REPLY_ID + 2]);
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(configs[0].clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let reply_id = resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Reply(resp) => Some(resp.reply.id),
+                _ => None
+            })
+            .unwrap();
+
+        assert_eq!(reply_id, REPLY_ID + 3);
+    }
+
+    #[test]
+    fn reply_id_can_be_changed() {
+        const CUSTOM_ID: u64 = 7;
+
+        let mut ensemble = ContractEnsemble::new();
+        let child = ensemble.register(Box::new(Child));
+        let factory = ensemble.register(
+            Box::new(GenericFactory::<ChildInstantiateMsg, String, false, CUSTOM_ID> {
+                msg_phantom: PhantomData,
+                extra_phantom: PhantomData,
+                hooks_phantom: PhantomData
+            })
+        );
+
+        let factory = ensemble.instantiate(
+            factory.id,
+            &InstantiateMsg::<ChildInstantiateMsg> {
+                admin: None,
+                code: child,
+                config: None,
+                initial_instance: None
+            },
+            MockEnv::new(ADMIN, "factory")
+        )
+        .unwrap()
+        .instance;
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "custom reply".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let reply_id = resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Reply(resp) => Some(resp.reply.id),
+                _ => None
+            })
+            .unwrap();
+
+        assert_eq!(reply_id, CUSTOM_ID);
+        assert_eq!(
+            GenericFactory::<ChildInstantiateMsg, String, false, CUSTOM_ID>::REPLY_IDS.start(),
+            CUSTOM_ID
+        );
+
+        let instance: Instance<Addr, String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.extra, "custom reply");
+    }
+
+    #[test]
+    fn initial_instance_is_created_with_factory() {
+        let mut ensemble = ContractEnsemble::new();
+        let child = ensemble.register(Box::new(Child));
+        let factory = ensemble.register(
+            Box::new(GenericFactory::<ChildInstantiateMsg, String, true> {
+                msg_phantom: PhantomData,
+                extra_phantom: PhantomData,
+                hooks_phantom: PhantomData
+            })
+        );
+
+        let factory = ensemble.instantiate(
+            factory.id,
+            &InstantiateMsg {
+                admin: None,
+                code: child,
+                config: None,
+                initial_instance: Some(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: "initial".into()
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: Some("initial".into()),
+                    extra: None
+                })
+            },
+            MockEnv::new(ADMIN, "factory")
+        )
+        .unwrap()
+        .instance;
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByLabel { label: "initial".into() }
+        ).unwrap();
+
+        let instance = instance.unwrap();
+        assert_eq!(instance.extra, "initial");
+        assert_eq!(instance.created.creator, Addr::unchecked(ADMIN));
+    }
+
+    #[test]
+    fn list_instances() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        let num_instances: u8 = 10;
+
+        for i in 0..num_instances {
+            let config = InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: format!("extra data {i}")
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            };
+
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(config),
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+        }
+
+        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, num_instances / 2),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(instances.total, num_instances as u64);
+        assert_eq!(instances.entries.len(), (num_instances / 2) as usize);
+
+        for (i, instance) in instances.entries.iter().enumerate() {
+            assert!(instance.contract.address.as_str().starts_with("fadroma factory child instance"));
+            assert_eq!(instance.contract.code_hash, "test_contract_0");
+            assert_eq!(instance.extra, format!("extra data {i}"));
+        }
+
+        let first_page = instances;
+
+        let addrs: PaginatedResponse<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstanceAddresses {
+                pagination: Pagination::new(0, num_instances / 2)
+            }
+        ).unwrap();
+
+        assert_eq!(addrs.total, num_instances as u64);
+        assert_eq!(
+            addrs.entries,
+            first_page.entries.iter().map(|x| x.contract.address.clone()).collect::<Vec<_>>()
+        );
+
+        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new((num_instances / 2) as u64, num_instances),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        let last = &first_page.entries.last().unwrap().contract.address;
+        let after: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, num_instances),
+                start_after: Some(last.to_string()),
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(
+            after.entries.iter().map(|x| &x.contract).collect::<Vec<_>>(),
+            instances.entries.iter().map(|x| &x.contract).collect::<Vec<_>>()
+        );
+
+        let latest: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 3).desc(),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(
+            latest.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+            ["extra data 9", "extra data 8", "extra data 7"]
+        );
+
+        let older: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 2).desc(),
+                start_after: Some(latest.entries[2].contract.address.to_string()),
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(
+            older.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+            ["extra data 6", "extra data 5"]
+        );
+
+        // The page resumes at the same place in either order
+        // if the instance was archived since it was listed.
+        let archived = latest.entries[2].contract.address.to_string();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetInstanceStatus {
+                addr: archived.clone(),
+                status: InstanceStatus::Archived
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        for (pagination, expected) in [
+            (Pagination::new(0, 2).desc(), ["extra data 6", "extra data 5"]),
+            (Pagination::new(0, 2), ["extra data 8", "extra data 9"])
+        ] {
+            let page: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &QueryMsg::ListInstances {
+                    pagination,
+                    start_after: Some(archived.clone()),
+                    filter: None
+                }
+            ).unwrap();
+
+            assert_eq!(page.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(), expected);
+        }
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetInstanceStatus {
+                addr: archived,
+                status: InstanceStatus::Active
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let mut pagination = Pagination::new(0, 4);
+        let mut extras = vec![];
+
+        loop {
+            let page: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &QueryMsg::ListInstances {
+                    pagination: pagination.clone(),
+                    start_after: None,
+                    filter: None
+                }
+            ).unwrap();
+
+            extras.extend(page.entries.into_iter().map(|x| x.extra));
+            assert_eq!(page.has_more, page.next.is_some());
+
+            if !page.has_more {
+                break;
+            }
+
+            pagination = pagination.with_cursor(page.next);
+        }
+
+        assert_eq!(extras, (0..num_instances).map(|i| format!("extra data {i}")).collect::<Vec<_>>());
+
+        // The cursor keeps its position when instances are added between pages.
+        let pagination = Pagination::new(0, 3).desc();
+        let first: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: pagination.clone(),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "newest".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let second: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: pagination.clone().with_cursor(first.next.clone()),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(second.entries[0].extra, "extra data 6");
+
+        // The cursor is the ID of the last instance listed, so the
+        // next page is the same even if that instance is removed.
+        let last = &first.entries[2];
+        assert_eq!(first.next, Some(Binary::from(last.id.to_be_bytes().as_slice())));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveInstance {
+                addr: last.contract.address.to_string()
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let second: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: pagination.with_cursor(first.next),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(second.entries[0].extra, "extra data 6");
+
+        assert_eq!(instances.total, num_instances as u64);
+        assert_eq!(instances.entries.len(), (num_instances / 2) as usize);
+
+        for (i, instance) in instances.entries.iter().enumerate() {
+            assert!(instance.contract.address.as_str().starts_with("fadroma factory child instance"));
+            assert_eq!(instance.contract.code_hash, "test_contract_0");
+            assert_eq!(instance.extra, format!("extra data {}", i as u8 + (num_instances / 2)));
+        }
+    }
+
+    #[test]
+    fn page_limit_is_configurable() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        for i in 0..5 {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                max_page_limit: 0,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Maximum page limit must be greater than zero."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                max_page_limit: 2,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, Pagination::MAX_LIMIT),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(instances.total, 5);
+        assert_eq!(instances.entries.len(), 2);
+        assert!(instances.has_more);
+    }
+
+    #[test]
+    fn zero_page_limit_uses_default() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        for i in 0..5 {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+        }
+
+        let config: Config = ensemble.query(
+            &factory.address,
+            &QueryMsg::Config { }
+        ).unwrap();
+
+        assert_eq!(config.max_page_limit, Pagination::MAX_LIMIT);
+        assert_eq!(config.default_page_limit, Pagination::DEFAULT_LIMIT);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                default_page_limit: 3,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 0),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(instances.entries.len(), 3);
+        assert!(instances.has_more);
+    }
+
+    #[test]
+    fn only_admin_can_change_contract_code() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(
+                ContractCode {
+                    id: 2,
+                    code_hash: "code_hash".into()
+                }
+            ),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(
+                ContractCode {
+                    id: 2,
+                    code_hash: "code_hash".into()
+                }
+            ),
+            MockEnv::new(ADMIN, factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn raw_instantiate_msg_is_admin_only() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = InstanceConfig {
+            msg: to_binary(&ChildInstantiateMsg {
+                text: String::from("raw")
+            }).unwrap(),
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::CreateInstanceRaw(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::CreateInstanceRaw(config),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let instance: Instance<Addr, String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.extra, "raw");
+    }
+
+    #[test]
+    fn code_registry() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
+
+        let metadata = CodeMetadata {
+            name: "Child".into(),
+            version: Some("1.2.0".into()),
+            description: None
+        };
+
+        let register = ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+            key: "second".into(),
+            code: code.clone(),
+            metadata: Some(metadata.clone())
+        };
+
+        let err = ensemble.execute(
+            &register,
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &register,
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let codes: PaginatedResponse<RegisteredCode> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListCodes { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(codes.total, 1);
+        assert_eq!(codes.entries[0].key, "second");
+        assert_eq!(codes.entries[0].code, code);
+        assert_eq!(codes.entries[0].metadata, Some(metadata.clone()));
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetCodeMetadata {
+                key: "second".into(),
+                metadata: Some(CodeMetadata {
+                    version: Some("1.2".into()),
+                    ..metadata
+                })
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Invalid semantic version: \"1.2\"."
+        );
+
+        let mut config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: Some("second".into()),
+            label: None,
+            extra: None
+        };
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        let instance: Instance<Addr, String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.contract.code_hash, code.code_hash);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveCode { key: "second".into() },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let stored: Option<RegisteredCode> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeByKey { key: "second".into() }
+        ).unwrap();
+
+        assert!(stored.is_none());
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: No code registered under key: second."
+        );
+
+        config.code_key = None;
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        let instance: Instance<Addr, String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.contract.code_hash, "test_contract_0");
+    }
+
+    #[test]
+    fn instances_by_code_id() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let first = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        let code = ensemble.register(Box::new(Child));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(code.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        for _ in 0..2 {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(config.clone()),
+                MockEnv::new("not admin", &factory.address)
+            ).unwrap();
+        }
+
+        let old: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesByCodeId {
+                code_id: 0,
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(old.total, 1);
+        assert_eq!(old.entries[0].contract.address, extract_instance_addr(&first));
+        assert_eq!(old.entries[0].code_id, 0);
+
+        let new: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesByCodeId {
+                code_id: code.id,
+                pagination: Pagination::new(1, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(new.total, 2);
+        assert_eq!(new.entries.len(), 1);
+        assert_eq!(new.entries[0].code_id, code.id);
+        assert_eq!(new.entries[0].contract.code_hash, code.code_hash);
+        assert_eq!(new.entries[0].id, 2);
+
+        let by_id: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceById { id: 2 }
+        ).unwrap();
+
+        assert_eq!(by_id.unwrap().contract, new.entries[0].contract);
+
+        let by_id: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceById { id: 3 }
+        ).unwrap();
+
+        assert!(by_id.is_none());
+
+        let total: u64 = ensemble.query(
+            &factory.address,
+            &QueryMsg::TotalInstances { }
+        ).unwrap();
+
+        assert_eq!(total, 3);
+
+        let filtered: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: None,
+                filter: Some(InstanceFilter {
+                    code_id: None,
+                    code_hash: Some(code.code_hash.clone()),
+                    status: None
+                })
+            }
+        ).unwrap();
+
+        assert_eq!(filtered.total, 2);
+        assert!(filtered.entries.iter().all(|x| x.code_id == code.id));
+
+        let filtered: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: None,
+                filter: Some(InstanceFilter {
+                    code_id: Some(0),
+                    code_hash: Some(code.code_hash.clone()),
+                    status: None
+                })
+            }
+        ).unwrap();
+
+        assert_eq!(filtered.total, 0);
+
+        let err = ensemble.query::<_, PaginatedResponse<Instance<Addr, String>>>(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: Some(extract_instance_addr(&first)),
+                filter: Some(InstanceFilter {
+                    code_id: Some(0),
+                    code_hash: None,
+                    status: None
+                })
+            }
+        ).unwrap_err();
+
+        assert!(err.to_string().contains("Cannot use start_after with a filter."));
+    }
+
+    #[test]
+    fn instances_by_creator() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        for (i, creator) in ["alice", "bob", "alice"].into_iter().enumerate() {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new(creator, &factory.address)
+            ).unwrap();
+        }
+
+        let alice: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstancesByCreator {
+                creator: "alice".into(),
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(alice.total, 2);
+        assert_eq!(
+            alice.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+            ["extra data 0", "extra data 2"]
+        );
+
+        let carol: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstancesByCreator {
+                creator: "carol".into(),
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(carol.total, 0);
+
+        let stats: Option<CreatorStats> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CreatorStats { creator: "alice".into() }
+        ).unwrap();
+
+        let stats = stats.unwrap();
+        assert_eq!(stats.instances, 2);
+        assert!(stats.first_created_at < stats.last_created_at);
+
+        let stats: Option<CreatorStats> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CreatorStats { creator: "carol".into() }
+        ).unwrap();
+
+        assert!(stats.is_none());
+    }
+
+    #[test]
+    fn instances_created_between() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let mut times = vec![];
+
+        for i in 0..4 {
+            times.push(Timestamp::from_seconds(ensemble.block().time));
+
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+
+            ensemble.block_mut().time += 100;
+        }
+
+        let between: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesCreatedBetween {
+                from: times[1],
+                to: times[3],
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(between.total, 2);
+        assert_eq!(
+            between.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+            ["extra data 1", "extra data 2"]
+        );
+
+        let between: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesCreatedBetween {
+                from: times[3].plus_seconds(1),
+                to: times[3].plus_seconds(1000),
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(between.total, 0);
+    }
+
+    #[test]
+    fn instances_can_be_listed_since() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let mut heights = vec![];
+
+        for i in 0..4 {
+            heights.push(ensemble.block().height);
+
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+        }
+
+        for id_or_height in [IdOrHeight::Id(2), IdOrHeight::Height(heights[2])] {
+            let since: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &QueryMsg::ListInstancesSince {
+                    id_or_height,
+                    pagination: Pagination::new(0, 10)
+                }
+            ).unwrap();
+
+            assert_eq!(since.total, 2);
+            assert_eq!(
+                since.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+                ["extra data 2", "extra data 3"]
+            );
+        }
+
+        let since: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstancesSince {
+                id_or_height: IdOrHeight::Id(10),
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(since.total, 0);
+    }
+
+    #[test]
+    fn admin_can_execute_on_instances() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "proxied".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let addr = extract_instance_addr(&resp);
+        let msg = ExecuteMsg::<ChildInstantiateMsg>::ExecuteOnInstance {
+            addr: addr.clone(),
+            msg: to_binary("ping").unwrap(),
+            funds: vec![]
+        };
+
+        let err = ensemble.execute(
+            &msg,
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Unauthorized");
+
+        let resp = ensemble.execute(
+            &msg,
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let executed = resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Execute(resp) if resp.address == addr =>
+                    Some(resp.msg.clone()),
+                _ => None
+            })
+            .unwrap();
+
+        assert_eq!(executed, to_binary("ping").unwrap());
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ExecuteOnInstance {
+                addr: "unknown".into(),
+                msg: to_binary("ping").unwrap(),
+                funds: vec![]
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: No instance with address: unknown."
+        );
+    }
+
+    #[test]
+    fn instances_can_be_required_to_register_themselves() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                require_self_registration: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "self registered".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let addr = extract_instance_addr(&resp);
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: addr.clone() }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().status, InstanceStatus::Pending);
+
+        let token: Binary = ensemble.query(
+            &addr,
+            &"registration_token"
+        ).unwrap();
+
+        for (sender, token, error) in [
+            ("creator", token.clone(), "Instance creator isn't awaiting registration."),
+            (addr.as_str(), Binary::from(b"guess"), "Invalid registration token.")
+        ] {
+            let err = ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::RegisterSelf { token },
+                MockEnv::new(sender, &factory.address)
+            ).unwrap_err();
+
+            assert_eq!(
+                err.unwrap_contract_error().to_string(),
+                format!("Generic error: {error}")
+            );
+        }
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterSelf { token: token.clone() },
+            MockEnv::new(&addr, &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: addr.clone() }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().status, InstanceStatus::Active);
+
+        // The token can only be used once.
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterSelf { token },
+            MockEnv::new(&addr, &factory.address)
+        ).unwrap_err();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetInstanceStatus {
+                addr: addr.to_string(),
+                status: InstanceStatus::Pending
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Instances can't be set to pending."
+        );
+    }
+
+    #[test]
+    fn callback_is_sent_after_registration() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let callback = to_binary("finalize").unwrap();
+
+        for sender in ["creator", ADMIN] {
+            let result = ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::SetCodeCallback {
+                    code_key: None,
+                    callback: Some(callback.clone())
+                },
+                MockEnv::new(sender, &factory.address)
+            );
+
+            assert_eq!(result.is_ok(), sender == ADMIN);
+        }
+
+        let stored: Option<Binary> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeCallback { code_key: None }
+        ).unwrap();
+
+        assert_eq!(stored, Some(callback.clone()));
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "two phase".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let executed = |resp: &ExecuteResponse| resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Execute(resp) =>
+                    Some((resp.sender.clone(), resp.address.clone(), resp.msg.clone())),
+                _ => None
+            });
+
+        // Unregistered instances aren't replied to.
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstanceUnregistered(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        assert_eq!(executed(&resp), None);
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let (sender, addr, msg) = executed(&resp).unwrap();
+
+        assert_eq!(sender, factory.address.as_str());
+        assert_eq!(addr, extract_instance_addr(&resp));
+        assert_eq!(msg, callback);
+    }
+
+    #[test]
+    fn callback_is_removed_with_code() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let child = ensemble.register(Box::new(Child));
+
+        let register = |ensemble: &mut ContractEnsemble| {
+            ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                    key: "with-callback".into(),
+                    code: child.clone(),
+                    metadata: None
+                },
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+        };
+
+        register(&mut ensemble);
+
+        let callback = to_binary("finalize").unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetCodeCallback {
+                code_key: Some("with-callback".into()),
+                callback: Some(callback.clone())
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveCode { key: "with-callback".into() },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        register(&mut ensemble);
+
+        let stored: Option<Binary> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeCallback { code_key: Some("with-callback".into()) }
+        ).unwrap();
+
+        assert_eq!(stored, None);
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "no callback".into()
+                },
+                funds: Vec::new(),
+                code_key: Some("with-callback".into()),
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        assert!(!resp.iter().any(|x|
+            matches!(x, ResponseVariants::Execute(resp) if resp.msg == callback)
+        ));
+    }
+
+    #[test]
+    fn instances_can_be_queried_through_factory() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "queried".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let answer: String = ensemble.query(
+            &factory.address,
+            &QueryMsg::QueryInstance {
+                addr: extract_instance_addr(&resp),
+                msg: to_binary("ping").unwrap()
+            }
+        ).unwrap();
+
+        assert_eq!(answer, "ping answered");
+
+        let err = ensemble.query::<_, String>(
+            &factory.address,
+            &QueryMsg::QueryInstance {
+                addr: "unknown".into(),
+                msg: to_binary("ping").unwrap()
+            }
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: No instance with address: unknown."
+        );
+    }
+
+    #[test]
+    fn broadcasts_are_sent_in_batches() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let mut addrs = vec![];
+
+        for text in ["a", "b", "c", "d"] {
+            let resp = ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: text.into()
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+
+            addrs.push(extract_instance_addr(&resp));
+        }
+
+        // Removed instances count towards the batch size.
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveInstance { addr: addrs[0].clone() },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let msg = to_binary("pause").unwrap();
+
+        for sender in ["creator", ADMIN] {
+            let result = ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::StartBroadcast { msg: msg.clone() },
+                MockEnv::new(sender, &factory.address)
+            );
+
+            assert_eq!(result.is_ok(), sender == ADMIN);
+        }
+
+        let executed = |resp: &ExecuteResponse| resp.iter()
+            .filter(|x| matches!(x, ResponseVariants::Execute(resp) if resp.msg == msg))
+            .count();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueBroadcast { batch_size: 0 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Batch size must be greater than zero."
+        );
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueBroadcast { batch_size: 2 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        assert_eq!(executed(&resp), 1);
+
+        let broadcast: Option<Broadcast> = ensemble.query(
+            &factory.address,
+            &QueryMsg::Broadcast { }
+        ).unwrap();
+
+        assert_eq!(broadcast, Some(Broadcast { msg: msg.clone(), next_id: 2, sent: 1 }));
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueBroadcast { batch_size: 2 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        assert_eq!(executed(&resp), 2);
+
+        let broadcast: Option<Broadcast> = ensemble.query(
+            &factory.address,
+            &QueryMsg::Broadcast { }
+        ).unwrap();
+
+        assert_eq!(broadcast, None);
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueBroadcast { batch_size: 2 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: No broadcast is in progress."
+        );
+    }
+
+    #[test]
+    fn unhealthy_instances_are_found() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let mut addrs = vec![];
+
+        for text in ["a", "bricked", "c"] {
+            let resp = ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: text.into()
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+
+            addrs.push(extract_instance_addr(&resp));
+        }
+
+        let query = to_binary("ping").unwrap();
+
+        for sender in ["creator", ADMIN] {
+            let result = ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::StartHealthCheck { query: query.clone() },
+                MockEnv::new(sender, &factory.address)
+            );
+
+            assert_eq!(result.is_ok(), sender == ADMIN);
+        }
+
+        // The bricked instance is checked in the first batch.
+        let checked_at = Timestamp::from_seconds(ensemble.block().time);
+
+        for done in ["false", "true"] {
+            let resp = ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::ContinueHealthCheck { batch_size: 2 },
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+
+            assert!(resp.response.attributes.iter()
+                .any(|a| a.key == "health_check_done" && a.value == done));
+        }
+
+        let unhealthy: PaginatedResponse<InstanceHealth> = ensemble.query(
+            &factory.address,
+            &QueryMsg::UnhealthyInstances { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(unhealthy.total, 1);
+        assert_eq!(unhealthy.entries[0].address, addrs[1]);
+        assert_eq!(unhealthy.entries[0].last_success, None);
+        assert_eq!(unhealthy.entries[0].last_failure, Some(checked_at));
+        assert!(unhealthy.entries[0].error.as_ref().unwrap().contains("Child is bricked."));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveInstance { addr: addrs[1].clone() },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let unhealthy: PaginatedResponse<InstanceHealth> = ensemble.query(
+            &factory.address,
+            &QueryMsg::UnhealthyInstances { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(unhealthy.total, 0);
+    }
+
+    #[test]
+    fn killswitch_is_propagated_to_instances() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        for text in ["a", "b", "c"] {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: text.into()
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+        }
+
+        let paused = killswitch::ContractStatus::Paused { reason: "Emergency".into() };
+        let set_status = ExecuteMsg::<ChildInstantiateMsg>::Killswitch(
+            killswitch::ExecuteMsg::SetStatus { status: paused.clone() }
+        );
+
+        ensemble.execute(&set_status, MockEnv::new(ADMIN, &factory.address)).unwrap();
+
+        let propagation: Option<KillswitchPropagation> = ensemble.query(
+            &factory.address,
+            &QueryMsg::KillswitchPropagation { }
+        ).unwrap();
+
+        assert_eq!(propagation, None);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::Killswitch(
+                killswitch::ExecuteMsg::SetStatus {
+                    status: killswitch::ContractStatus::Operational
+                }
+            ),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                propagate_killswitch: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(&set_status, MockEnv::new(ADMIN, &factory.address)).unwrap();
+
+        let msg = to_binary(&killswitch::ExecuteMsg::SetStatus { status: paused.clone() }).unwrap();
+        let executed = |resp: &ExecuteResponse| resp.iter()
+            .filter(|x| matches!(x, ResponseVariants::Execute(resp) if resp.msg == msg))
+            .count();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueKillswitchPropagation { batch_size: 2 },
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Unauthorized");
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueKillswitchPropagation { batch_size: 2 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        assert_eq!(executed(&resp), 2);
+
+        let propagation: Option<KillswitchPropagation> = ensemble.query(
+            &factory.address,
+            &QueryMsg::KillswitchPropagation { }
+        ).unwrap();
+
+        assert_eq!(propagation, Some(KillswitchPropagation { status: paused, next_id: 2, sent: 2 }));
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueKillswitchPropagation { batch_size: 2 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        assert_eq!(executed(&resp), 1);
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ContinueKillswitchPropagation { batch_size: 2 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: No killswitch propagation is in progress."
+        );
+    }
+
+    #[test]
+    fn instances_can_be_tagged() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let mut addrs = vec![];
+
+        for i in 0..3 {
+            let resp = ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+
+            addrs.push(extract_instance_addr(&resp));
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::TagInstance {
+                addr: addrs[0].clone(),
+                tag: "beta".into()
+            },
+            MockEnv::new("stranger", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        for (addr, sender) in [(&addrs[0], "creator"), (&addrs[1], ADMIN), (&addrs[2], "creator")] {
+            ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::TagInstance {
+                    addr: addr.clone(),
+                    tag: "beta".into()
+                },
+                MockEnv::new(sender, &factory.address)
+            ).unwrap();
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::TagInstance {
+                addr: addrs[0].clone(),
+                tag: "beta".into()
+            },
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Instance is already tagged with \"beta\"."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UntagInstance {
+                addr: addrs[0].clone(),
+                tag: "beta".into()
+            },
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let tagged: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesByTag {
+                tag: "beta".into(),
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(
+            tagged.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+            ["extra data 2", "extra data 1"]
+        );
+
+        // The position of the moved instance must be kept up to date.
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UntagInstance {
+                addr: addrs[2].clone(),
+                tag: "beta".into()
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let tagged: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesByTag {
+                tag: "beta".into(),
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(tagged.total, 1);
+        assert_eq!(tagged.entries[0].extra, "extra data 1");
+
+        let tags: Vec<String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceTags { addr: addrs[1].clone() }
+        ).unwrap();
+
+        assert_eq!(tags, ["beta"]);
+    }
+
+    #[test]
+    fn funds_are_recorded() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let funds = vec![Coin::new(100, "uscrt")];
+        ensemble.add_funds("creator", funds.clone());
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "extra data".into()
+                },
+                funds: funds.clone(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address).sent_funds(funds.clone())
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().funds, funds);
+    }
+
+    #[test]
+    fn creation_fee_is_charged() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.add_funds("creator", vec![Coin::new(100, "uscrt")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: Some(Coin::new(10, "uscrt")),
+                collector: Some(Addr::unchecked("collector")),
+                token_fee: None,
+                deposit: None
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: vec![Coin::new(20, "uscrt")],
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(25, "uscrt")])
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::InsufficientFunds {
+                expected: Coin::new(30, "uscrt")
+            }).to_string()
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(30, "uscrt")])
+        ).unwrap();
+
+        let fees: Vec<Coin> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CollectedFees { }
+        ).unwrap();
+
+        assert_eq!(fees, [Coin::new(10, "uscrt")]);
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::WithdrawFees { },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::WithdrawFees { },
+            MockEnv::new("collector", &factory.address)
+        ).unwrap();
+
+        let balances = ensemble.balances("collector").unwrap();
+        assert_eq!(balances["uscrt"], Uint128::new(10));
+
+        let fees: Vec<Coin> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CollectedFees { }
+        ).unwrap();
+
+        assert!(fees.is_empty());
+    }
+
+    #[test]
+    fn extra_set_by_child_cannot_be_overridden() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "from child".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: Some(to_binary("from creator").unwrap())
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().extra, "from child");
+    }
+
+    #[test]
+    fn children_without_reply_data_are_registered() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let stock = ensemble.register(Box::new(GateToken));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                key: "stock".into(),
+                code: stock,
+                metadata: None
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "ignored".into()
+            },
+            funds: Vec::new(),
+            code_key: Some("stock".into()),
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Expecting non-empty data in reply of type generic_factory::InstantiateReplyData<alloc::string::String>."
+        );
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                extra: Some(to_binary("stock child").unwrap()),
+                ..config
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let addr = resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Instantiate(resp) => Some(resp.instance.address.to_string()),
+                _ => None
+            })
+            .unwrap();
+
+        assert_eq!(extract_instance_addr(&resp), addr);
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().extra, "stock child");
+    }
+
+    #[test]
+    fn hooks_are_called() {
+        type Factory = GenericFactory<ChildInstantiateMsg, String, false, REPLY_ID, Hooks>;
+
+        struct Hooks;
+
+        impl FactoryHooks<ChildInstantiateMsg, String> for Hooks {
+            fn before_create(
+                _deps: Deps,
+                _info: &MessageInfo,
+                config: &InstanceConfig<ChildInstantiateMsg>
+            ) -> StdResult<()> {
+                if config.msg.text == "forbidden" {
+                    return Err(StdError::generic_err("Forbidden by hook."));
+                }
+
+                Ok(())
+            }
+
+            fn after_registered(
+                _deps: DepsMut,
+                instance: &Instance<Addr, String>
+            ) -> StdResult<Vec<SubMsg>> {
+                // Let the instance know that it was registered.
+                let msg = WasmMsg::Execute {
+                    contract_addr: instance.contract.address.to_string(),
+                    code_hash: instance.contract.code_hash.clone(),
+                    msg: to_binary(&instance.id)?,
+                    funds: vec![]
+                };
+
+                Ok(vec![SubMsg::new(msg)])
+            }
+        }
+
+        struct HookedFactory;
+
+        impl ContractHarness for HookedFactory {
+            fn instantiate(
+                &self,
+                deps: DepsMut,
+                env: Env,
+                info: MessageInfo,
+                msg: Binary
+            ) -> AnyResult<Response> {
+                Ok(Factory::instantiate(deps, env, info, from_binary(&msg)?)?)
+            }
+
+            fn execute(
+                &self,
+                deps: DepsMut,
+                env: Env,
+                info: MessageInfo,
+                msg: Binary
+            ) -> AnyResult<Response> {
+                Ok(Factory::execute(deps, env, info, from_binary(&msg)?)?)
+            }
+
+            fn query(&self, deps: Deps, env: Env, msg: Binary) -> AnyResult<Binary> {
+                Ok(Factory::query(deps, env, from_binary(&msg)?)?)
+            }
+
+            fn reply(&self, deps: DepsMut, env: Env, reply: Reply) -> AnyResult<Response> {
+                Ok(Factory::reply(deps, env, reply)?)
+            }
+        }
+
+        let mut ensemble = ContractEnsemble::new();
+        let child = ensemble.register(Box::new(Child));
+        let factory = ensemble.register(Box::new(HookedFactory));
+
+        let config = |text: &str| InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: text.into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.instantiate(
+            factory.id,
+            &InstantiateMsg::<ChildInstantiateMsg> {
+                admin: None,
+                code: child.clone(),
+                config: None,
+                initial_instance: Some(config("forbidden"))
+            },
+            MockEnv::new(ADMIN, "factory")
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Forbidden by hook.");
+
+        let factory = ensemble.instantiate(
+            factory.id,
+            &InstantiateMsg::<ChildInstantiateMsg> {
+                admin: None,
+                code: child,
+                config: None,
+                initial_instance: None
+            },
+            MockEnv::new(ADMIN, "factory")
+        )
+        .unwrap()
+        .instance;
+
+        // Proposals are checked when they are submitted.
+        let err = ensemble.execute(
+            &ExecuteMsg::ProposeInstance(config("forbidden")),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Forbidden by hook.");
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstances(vec![config("allowed"), config("forbidden")]),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Forbidden by hook.");
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config("allowed")),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let notified = resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Execute(resp) => Some(resp.address.clone()),
+                _ => None
+            })
+            .unwrap();
+
+        assert_eq!(notified, extract_instance_addr(&resp));
+    }
+
+    #[test]
+    fn reply_data_is_the_instance_link() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "linked".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let link: ContractLink<Addr> = resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Reply(resp) => resp.response.data.clone(),
+                _ => None
+            })
+            .map(|x| from_binary(&x).unwrap())
+            .unwrap();
+
+        let instance: Instance<Addr, String> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(link, instance.contract);
+    }
+
+    #[test]
+    fn reply_address_must_match_the_instantiated_one() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "impostor".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: Some("impostor".into()),
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: The instance claims to be at somewhere else but was instantiated at impostor."
+        );
+    }
+
+    #[test]
+    fn unregistered_instances_are_not_tracked() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstanceUnregistered(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "extra data".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        assert!(resp.iter().any(|x| x.is_instantiate()));
+        assert!(!resp.iter().any(|x| x.is_reply()));
+
+        let total: u64 = ensemble.query(
+            &factory.address,
+            &QueryMsg::TotalInstances { }
+        ).unwrap();
+
+        assert_eq!(total, 0);
+    }
+
+    #[test]
+    fn instances_can_be_created_in_batch() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.add_funds("creator", vec![Coin::new(100, "uscrt")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: Some(Coin::new(10, "uscrt")),
+                collector: None,
+                token_fee: None,
+                deposit: None
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let configs: Vec<_> = ["one", "two", "three"].into_iter().map(|text| InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: text.into()
+            },
+            funds: vec![Coin::new(5, "uscrt")],
+            code_key: None,
+            label: None,
+            extra: None
+        }).collect();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstances(configs.clone()),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(40, "uscrt")])
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::InsufficientFunds {
+                expected: Coin::new(45, "uscrt")
+            }).to_string()
+        );
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstances(configs),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(45, "uscrt")])
+        ).unwrap();
+
+        let addrs: Vec<String> = resp.iter()
+            .filter_map(|x| match x {
+                ResponseVariants::Reply(reply) => reply.response.attributes.iter()
+                    .find(|x| x.key == INSTANCE_ADDR_ATTR)
+                    .map(|x| x.value.clone()),
+                _ => None
+            })
+            .collect();
+
+        assert_eq!(addrs.len(), 3);
+
+        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(instances.total, 3);
+
+        for (instance, (addr, text)) in instances.entries.iter().zip(addrs.iter().zip(["one", "two", "three"])) {
+            assert_eq!(instance.contract.address.as_str(), addr);
+            assert_eq!(instance.extra, text);
+        }
+    }
+
+    #[test]
+    fn instances_can_be_proposed() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        for text in ["approved", "rejected"] {
+            ensemble.execute(
+                &ExecuteMsg::ProposeInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: text.into()
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("proposer", &factory.address)
+            ).unwrap();
+        }
+
+        let proposals: PaginatedResponse<Proposal> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListProposals { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(proposals.total, 2);
+        assert_eq!(proposals.entries[1].id, 1);
+        assert_eq!(proposals.entries[1].proposer, Addr::unchecked("proposer"));
+        assert!(proposals.entries.iter().all(|x| x.status == ProposalStatus::Pending));
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApproveInstance { id: 0 },
+            MockEnv::new("proposer", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApproveInstance { id: 0 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RejectInstance { id: 1 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApproveInstance { id: 1 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Proposal 1 was already resolved."
+        );
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        let instance = instance.unwrap();
+        assert_eq!(instance.extra, "approved");
+        assert_eq!(instance.created.creator, Addr::unchecked("proposer"));
+
+        let proposal: Option<Proposal> = ensemble.query(
+            &factory.address,
+            &QueryMsg::Proposal { id: 1 }
+        ).unwrap();
+
+        assert_eq!(proposal.unwrap().status, ProposalStatus::Rejected);
+
+        let total: u64 = ensemble.query(
+            &factory.address,
+            &QueryMsg::TotalInstances { }
+        ).unwrap();
+
+        assert_eq!(total, 1);
+    }
+
+    #[test]
+    fn proposals_expire() {
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                proposal_ttl: Some(100),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        for text in ["expired", "approved", "also expired"] {
+            ensemble.execute(
+                &ExecuteMsg::ProposeInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: text.into()
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("proposer", &factory.address)
+            ).unwrap();
+        }
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApproveInstance { id: 1 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.block_mut().time += 100;
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApproveInstance { id: 0 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Proposal 0 has expired."
+        );
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::PruneExpired { limit: 0 },
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Limit must be greater than zero."
+        );
+
+        // Only the first two proposals are checked by the first call.
+        for (done, status) in [("false", ProposalStatus::Pending), ("true", ProposalStatus::Expired)] {
+            let resp = ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::PruneExpired { limit: 2 },
+                MockEnv::new("anyone", &factory.address)
+            ).unwrap();
+
+            assert!(resp.response.attributes.iter()
+                .any(|a| a.key == "pruned" && a.value == "1"));
+            assert!(resp.response.attributes.iter()
+                .any(|a| a.key == "prune_done" && a.value == done));
+
+            let proposals: PaginatedResponse<Proposal> = ensemble.query(
+                &factory.address,
+                &QueryMsg::ListProposals { pagination: Pagination::new(0, 10) }
+            ).unwrap();
+
+            assert_eq!(proposals.entries[0].status, ProposalStatus::Expired);
+            assert_eq!(proposals.entries[1].status, ProposalStatus::Approved);
+            assert_eq!(proposals.entries[2].status, status);
+        }
+
+        let proposals: PaginatedResponse<Proposal> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListProposals { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(
+            proposals.entries[0].expires_at,
+            Some(proposals.entries[0].proposed_at.plus_seconds(100))
+        );
+    }
+
+    #[test]
+    fn instances_can_be_created_with_permits() {
+        use k256::{
+            ecdsa::{SigningKey, Signature, signature::Signer},
+            elliptic_curve::sec1::ToEncodedPoint
+        };
+
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        let key = SigningKey::from_bytes(&[7; 32]).unwrap();
+        let pubkey = Binary::from(key.verifying_key().to_encoded_point(true).as_bytes());
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetPermitSigner { pubkey: Some(pubkey) },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = to_binary(&InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        }).unwrap();
+
+        let permit = CreationPermit {
+            creator: "creator".into(),
+            expiry: ensemble.block().time + 1000,
+            nonce: 1
+        };
+
+        let signed = to_binary(&SignedPermit {
+            factory: factory.address.clone(),
+            creator: permit.creator.clone(),
+            config_hash: Binary::from(fadroma::crypto::sha_256(config.as_slice())),
+            expiry: permit.expiry,
+            nonce: permit.nonce
+        }).unwrap();
+
+        let signature: Signature = key.sign(signed.as_slice());
+        let signature = Binary::from(signature.as_ref());
+
+        let create = |config: Binary| ExecuteMsg::<ChildInstantiateMsg>::CreateInstanceWithPermit {
+            config,
+            permit: permit.clone(),
+            signature: signature.clone()
+        };
+
+        let tampered = to_binary(&InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "other data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        }).unwrap();
+
+        let err = ensemble.execute(
+            &create(tampered),
+            MockEnv::new("relayer", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Invalid permit signature."
+        );
+
+        let resp = ensemble.execute(
+            &create(config.clone()),
+            MockEnv::new("relayer", &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().created.creator, Addr::unchecked("creator"));
+
+        let err = ensemble.execute(
+            &create(config),
+            MockEnv::new("relayer", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: The permit was already used."
+        );
+    }
+
+    #[test]
+    fn instances_can_have_custom_labels() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = |label: &str| InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: Some(label.into()),
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config("my pool!")),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Label can only contain letters, digits, spaces and -_.:/#"
+        );
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config("my-pool")),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByLabel { label: "my-pool".into() }
+        ).unwrap();
+
+        let instance = instance.unwrap();
+        assert_eq!(instance.label, "my-pool");
+        assert_eq!(instance.contract.address.to_string(), extract_instance_addr(&resp));
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config("my-pool")),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Label \"my-pool\" is already taken."
+        );
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstances(vec![config("twin"), config("twin")]),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Label \"twin\" is used more than once."
+        );
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config("Fadroma factory child instance #5 created at: 0")),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Label is reserved for the labels generated by the factory."
+        );
+    }
+
+    #[test]
+    fn generated_labels_are_unique_within_a_block() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        ensemble.block_mut().freeze();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        for seq in 0..2 {
+            let resp = ensemble.execute(
+                &ExecuteMsg::CreateInstance(config.clone()),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+
+            let instance: Option<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+            ).unwrap();
+
+            assert_eq!(instance.unwrap().seq, Some(seq));
+        }
+    }
+
+    #[test]
+    fn labels_can_be_generated_from_template() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                label_template: Some("pool {id}".into()),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unknown placeholder in label template."
+        );
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                label_template: Some("pool of {creator}".into()),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Label template must contain {seq}."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                label_template: Some("pool {seq} of {creator} from {code_id}".into()),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstances(vec![config.clone(), config.clone()]),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        for (id, label) in [(0, "pool 0 of creator from 0"), (1, "pool 1 of creator from 0")] {
+            let instance: Option<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &QueryMsg::InstanceById { id }
+            ).unwrap();
+
+            assert_eq!(instance.unwrap().label, label);
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                label: Some("my-pool".into()),
+                ..config.clone()
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Labels are set by the label template of the factory."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                label_template: Some("pool {seq}!".into()),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Label can only contain letters, digits, spaces and -_.:/#"
+        );
+    }
+
+    #[test]
+    fn creations_are_rate_limited() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                rate_limit: Some(RateLimit { max_creations: 2, window: 100 }),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let time = ensemble.block().time;
+
+        for _ in 0..2 {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(config.clone()),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::RateLimited { retry_after: time + 100 }).to_string()
+        );
+
+        for sender in ["other", ADMIN, ADMIN, ADMIN] {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(config.clone()),
+                MockEnv::new(sender, &factory.address)
+            ).unwrap();
+        }
+
+        ensemble.block_mut().time = time + 100;
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn instance_cap_is_enforced() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let set_cap = |max_instances| ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+            max_instances: Some(max_instances),
+            ..Config::default()
+        });
+
+        ensemble.execute(&set_cap(2), MockEnv::new(ADMIN, &factory.address)).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        for _ in 0..2 {
+            ensemble.execute(
+                &ExecuteMsg::CreateInstance(config.clone()),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::InstanceCapReached { max: 2 }).to_string()
+        );
+
+        ensemble.execute(&set_cap(3), MockEnv::new(ADMIN, &factory.address)).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn creator_cap_is_enforced() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                max_instances_per_creator: Some(1),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::CreatorCapReached { max: 1 }).to_string()
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("other", &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveInstance {
+                addr: extract_instance_addr(&resp)
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn excess_funds_are_refunded() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.add_funds("creator", vec![Coin::new(100, "uscrt"), Coin::new(5, "uatom")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: Some(Coin::new(10, "uscrt")),
+                collector: None,
+                token_fee: None,
+                deposit: None
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "extra data".into()
+                },
+                funds: vec![Coin::new(20, "uscrt")],
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(50, "uscrt"), Coin::new(5, "uatom")])
+        ).unwrap();
+
+        let balances = ensemble.balances("creator").unwrap();
+        assert_eq!(balances["uscrt"], Uint128::new(70));
+        assert_eq!(balances["uatom"], Uint128::new(5));
+    }
+
+    #[test]
+    fn instance_funds_are_restricted_to_allowed_denoms() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.add_funds("creator", vec![Coin::new(1000, "uscrt"), Coin::new(5, "uatom")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                allowed_denoms: Some(vec![AllowedDenom {
+                    denom: "uscrt".into(),
+                    min: Some(Uint128::new(10)),
+                    max: Some(Uint128::new(100))
+                }]),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let mut create = |funds: Vec<Coin>| ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "extra data".into()
+                },
+                funds: funds.clone(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address).sent_funds(funds)
+        );
+
+        let cases = [
+            (Coin::new(5, "uatom"), "Instances cannot be sent \"uatom\"."),
+            (Coin::new(9, "uscrt"), "Instances must be sent at least 10uscrt."),
+            (Coin::new(101, "uscrt"), "Instances cannot be sent more than 100uscrt.")
+        ];
+
+        for (coin, expected) in cases {
+            let err = create(vec![coin]).unwrap_err();
+
+            assert_eq!(
+                err.unwrap_contract_error().to_string(),
+                format!("Generic error: {expected}")
+            );
+        }
+
+        create(vec![Coin::new(10, "uscrt")]).unwrap();
+        create(vec![]).unwrap();
+    }
+
+    #[test]
+    fn deposits_are_refunded_or_forfeited() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.add_funds("creator", vec![Coin::new(100, "uscrt")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: None,
+                collector: None,
+                token_fee: None,
+                deposit: Some(Coin::new(10, "uscrt"))
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(5, "uscrt")])
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Expected at least 10uscrt to be sent."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(10, "uscrt")])
+        ).unwrap();
+
+        let balances = ensemble.balances("creator").unwrap();
+        assert_eq!(balances["uscrt"], Uint128::new(100));
+
+        // There is no reply to refund the deposit in.
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstanceUnregistered(config.clone()),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(10, "uscrt")])
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unregistered instances cannot be created while a deposit is required."
+        );
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::CreateInstanceRaw(InstanceConfig {
+                msg: to_binary(&config.msg).unwrap(),
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Expected at least 10uscrt to be sent."
+        );
+
+        for _ in 0..2 {
+            ensemble.execute(
+                &ExecuteMsg::ProposeInstance(config.clone()),
+                MockEnv::new("creator", &factory.address)
+                    .sent_funds(vec![Coin::new(10, "uscrt")])
+            ).unwrap();
+        }
+
+        let balances = ensemble.balances("creator").unwrap();
+        assert_eq!(balances["uscrt"], Uint128::new(80));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApproveInstance { id: 0 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RejectInstance { id: 1 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let balances = ensemble.balances("creator").unwrap();
+        assert_eq!(balances["uscrt"], Uint128::new(90));
+
+        let collected: Vec<Coin> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CollectedFees { }
+        ).unwrap();
+
+        assert_eq!(collected, [Coin::new(10, "uscrt")]);
+    }
+
+    #[test]
+    fn factory_balance_cannot_be_drained() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        // Collected fees and deposits of other creators.
+        ensemble.add_funds(&factory.address, vec![Coin::new(100, "uscrt")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                track_failures: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        for text in ["fail", "a"] {
+            let config = InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: text.into()
+                },
+                funds: vec![Coin::new(100, "uscrt")],
+                code_key: None,
+                label: None,
+                extra: None
+            };
+
+            for msg in [
+                ExecuteMsg::CreateInstance(config.clone()),
+                ExecuteMsg::CreateInstances(vec![config])
+            ] {
+                let err = ensemble.execute(
+                    &msg,
+                    MockEnv::new("creator", &factory.address)
+                ).unwrap_err();
+
+                assert_eq!(
+                    err.unwrap_contract_error().to_string(),
+                    StdError::from(FactoryError::InsufficientFunds {
+                        expected: Coin::new(100, "uscrt")
+                    }).to_string()
+                );
+            }
+        }
+
+        let balances = ensemble.balances(&factory.address).unwrap();
+        assert_eq!(balances["uscrt"], Uint128::new(100));
+    }
+
+    #[test]
+    fn failed_instantiations_are_tracked() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.add_funds("creator", vec![Coin::new(100, "uscrt")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: None,
+                collector: None,
+                token_fee: None,
+                deposit: Some(Coin::new(10, "uscrt"))
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "fail".into()
+            },
+            funds: vec![Coin::new(20, "uscrt")],
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(30, "uscrt")])
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Child failed.");
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                track_failures: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+                .sent_funds(vec![Coin::new(30, "uscrt")])
+        ).unwrap();
+
+        let balances = ensemble.balances("creator").unwrap();
+        assert_eq!(balances["uscrt"], Uint128::new(90));
+
+        let total: u64 = ensemble.query(
+            &factory.address,
+            &QueryMsg::TotalInstances { }
+        ).unwrap();
+
+        assert_eq!(total, 0);
+
+        let failures: PaginatedResponse<FailedInstantiation> = ensemble.query(
+            &factory.address,
+            &QueryMsg::FailedInstantiations {
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(failures.total, 1);
+
+        let failure = &failures.entries[0];
+        assert_eq!(failure.id, 0);
+        assert_eq!(failure.creator, Addr::unchecked("creator"));
+        assert_eq!(failure.error, "Generic error: Child failed.");
+        assert_eq!(
+            failure.config_hash,
+            Binary::from(sha_256(to_binary(&InstanceConfig {
+                msg: to_binary(&config.msg).unwrap(),
+                funds: config.funds,
+                code_key: config.code_key,
+                label: config.label,
+                extra: config.extra
+            }).unwrap().as_slice()))
+        );
+    }
+
+    #[test]
+    fn failed_instantiations_can_be_retried() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let child = ensemble.register(Box::new(Child));
+        let stock = ensemble.register(Box::new(GateToken));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                track_failures: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let register = |ensemble: &mut ContractEnsemble, code: &ContractCode| {
+            ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                    key: "flaky".into(),
+                    code: code.clone(),
+                    metadata: None
+                },
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+        };
+
+        register(&mut ensemble, &child);
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "fail".into()
+                },
+                funds: Vec::new(),
+                code_key: Some("flaky".into()),
+                label: None,
+                extra: Some(to_binary("retried").unwrap())
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        register(&mut ensemble, &stock);
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RetryInstantiation { failure_id: 0 },
+            MockEnv::new("rando", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Unauthorized");
+
+        let block = |ensemble: &mut ContractEnsemble, blocked: bool| {
+            let addrs = vec!["creator".into()];
+            let msg = if blocked {
+                ExecuteMsg::<ChildInstantiateMsg>::BlockCreators { addrs }
+            } else {
+                ExecuteMsg::<ChildInstantiateMsg>::UnblockCreators { addrs }
+            };
+
+            ensemble.execute(&msg, MockEnv::new(ADMIN, &factory.address)).unwrap();
+        };
+
+        block(&mut ensemble, true);
+
+        for sender in ["creator", ADMIN] {
+            let err = ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::RetryInstantiation { failure_id: 0 },
+                MockEnv::new(sender, &factory.address)
+            ).unwrap_err();
+
+            assert_eq!(
+                err.unwrap_contract_error().to_string(),
+                "Generic error: The sender is blocked from creating instances."
+            );
+        }
+
+        block(&mut ensemble, false);
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RetryInstantiation { failure_id: 0 },
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        let instance = instance.unwrap();
+        assert_eq!(instance.extra, "retried");
+        assert_eq!(instance.code_id, stock.id);
+        assert_eq!(instance.created.creator, Addr::unchecked("creator"));
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RetryInstantiation { failure_id: 0 },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Failed instantiation 0 has already been retried."
+        );
+    }
+
+    #[test]
+    fn fee_discounts_are_applied() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.add_funds("partner", vec![Coin::new(100, "uscrt")]);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: Some(Coin::new(10, "uscrt")),
+                collector: None,
+                token_fee: None,
+                deposit: None
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        for (addr, percent) in [("partner", 50), ("internal", 100)] {
+            ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::SetFeeDiscount {
+                    addr: addr.into(),
+                    percent
+                },
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeDiscount {
+                addr: "partner".into(),
+                percent: 101
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Discount cannot be more than 100 percent."
+        );
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("partner", &factory.address)
+                .sent_funds(vec![Coin::new(4, "uscrt")])
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::InsufficientFunds {
+                expected: Coin::new(5, "uscrt")
+            }).to_string()
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("partner", &factory.address)
+                .sent_funds(vec![Coin::new(5, "uscrt")])
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("internal", &factory.address)
+        ).unwrap();
+
+        let fees: Vec<Coin> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CollectedFees { }
+        ).unwrap();
+
+        assert_eq!(fees, [Coin::new(5, "uscrt")]);
+    }
+
+    #[test]
+    fn instances_can_be_paid_for_in_tokens() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let token = ensemble.register(Box::new(GateToken));
+        let token = ensemble.instantiate(
+            token.id,
+            &Empty { },
+            MockEnv::new(ADMIN, "fee_token")
+        ).unwrap().instance;
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: Some(Coin::new(10, "uscrt")),
+                collector: None,
+                token_fee: Some(TokenFee {
+                    token: token.clone(),
+                    amount: Uint128::new(50)
+                }),
+                deposit: None
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = to_binary(&InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        }).unwrap();
+
+        let receive = |amount: u128| ExecuteMsg::<ChildInstantiateMsg>::Receive {
+            sender: Addr::unchecked("creator"),
+            from: Addr::unchecked("creator"),
+            amount: Uint128::new(amount),
+            msg: Some(config.clone())
+        };
+
+        let err = ensemble.execute(
+            &receive(50),
+            MockEnv::new("other_token", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unexpected token: other_token."
+        );
+
+        let err = ensemble.execute(
+            &receive(40),
+            MockEnv::new(&token.address, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::InsufficientTokenPayment {
+                expected: Uint128::new(50)
+            }).to_string()
+        );
+
+        let resp = ensemble.execute(
+            &receive(60),
+            MockEnv::new(&token.address, &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().created.creator, Addr::unchecked("creator"));
+
+        // The overpayment is sent back.
+        let refund = resp.iter()
+            .find_map(|x| match x {
+                ResponseVariants::Execute(resp) if resp.address == token.address =>
+                    Some(resp.msg.clone()),
+                _ => None
+            })
+            .unwrap();
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "snake_case")]
+        enum TokenMsg {
+            Transfer { recipient: String, amount: Uint128 }
+        }
+
+        assert_eq!(refund, to_binary(&TokenMsg::Transfer {
+            recipient: "creator".into(),
+            amount: Uint128::new(10)
+        }).unwrap());
+
+        let fees: Uint128 = ensemble.query(
+            &factory.address,
+            &QueryMsg::CollectedTokenFees { }
+        ).unwrap();
+
+        assert_eq!(fees, Uint128::new(50));
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig::default()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Withdraw the collected token fees before changing the fee token."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::WithdrawFees { },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let fees: Uint128 = ensemble.query(
+            &factory.address,
+            &QueryMsg::CollectedTokenFees { }
+        ).unwrap();
+
+        assert!(fees.is_zero());
+
+        // The deposit can only be sent in the native coin.
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetFeeConfig(FeeConfig {
+                fee: None,
+                collector: None,
+                token_fee: Some(TokenFee {
+                    token: token.clone(),
+                    amount: Uint128::new(50)
+                }),
+                deposit: Some(Coin::new(10, "uscrt"))
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &receive(50),
+            MockEnv::new(&token.address, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Token payments are not accepted while a deposit is required."
+        );
+    }
+
+    #[test]
+    fn init_msgs_can_be_stored() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: "extra data".into()
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let init: Option<InstanceConfig<Binary>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceInitMsg { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert!(init.is_none());
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                store_init_msgs: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let init: Option<InstanceConfig<Binary>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceInitMsg { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        let init = init.unwrap();
+        assert_eq!(init.msg, to_binary(&config.msg).unwrap());
+        assert_eq!(init.code_key, None);
+    }
+
+    #[test]
+    fn instances_can_be_removed() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let mut addrs = vec![];
+
+        for i in 0..3 {
+            let resp = ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+
+            addrs.push(extract_instance_addr(&resp));
+        }
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::TagInstance {
+                addr: addrs[0].clone(),
+                tag: "beta".into()
+            },
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveInstance {
+                addr: addrs[0].clone()
+            },
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveInstance {
+                addr: addrs[0].clone()
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveInstance {
+                addr: addrs[0].clone()
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            format!("Generic error: No instance with address: {}.", addrs[0])
+        );
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: addrs[0].clone() }
+        ).unwrap();
+
+        assert!(instance.is_none());
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceById { id: 0 }
+        ).unwrap();
+
+        assert!(instance.is_none());
+
+        let total: u64 = ensemble.query(
+            &factory.address,
+            &QueryMsg::TotalInstances { }
+        ).unwrap();
+
+        assert_eq!(total, 2);
+
+        // Listing all instances keeps the order they were created in.
+        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(
+            instances.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+            ["extra data 1", "extra data 2"]
+        );
+
+        // The last instance is moved into the place of the removed one.
+        for query in [
+            QueryMsg::InstancesByCodeId {
+                code_id: 0,
+                pagination: Pagination::new(0, 10)
+            },
+            QueryMsg::ListInstancesByCreator {
+                creator: "creator".into(),
+                pagination: Pagination::new(0, 10)
+            }
+        ] {
+            let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &query
+            ).unwrap();
+
+            assert_eq!(instances.total, 2);
+            assert_eq!(
+                instances.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+                ["extra data 2", "extra data 1"]
+            );
+        }
+
+        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: Some(addrs[1].clone()),
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(instances.entries.len(), 1);
+        assert_eq!(instances.entries[0].extra, "extra data 2");
+
+        let tagged: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesByTag {
+                tag: "beta".into(),
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(tagged.total, 0);
+
+        // IDs are not reused.
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(InstanceConfig {
+                msg: ChildInstantiateMsg {
+                    text: "extra data 3".into()
+                },
+                funds: Vec::new(),
+                code_key: None,
+                label: None,
+                extra: None
+            }),
+            MockEnv::new("creator", &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().id, 3);
+    }
+
+    #[test]
+    fn instances_can_be_archived() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let mut addrs = vec![];
+
+        for i in 0..3 {
+            let resp = ensemble.execute(
+                &ExecuteMsg::CreateInstance(InstanceConfig {
+                    msg: ChildInstantiateMsg {
+                        text: format!("extra data {i}")
+                    },
+                    funds: Vec::new(),
+                    code_key: None,
+                    label: None,
+                    extra: None
+                }),
+                MockEnv::new("creator", &factory.address)
+            ).unwrap();
+
+            addrs.push(extract_instance_addr(&resp));
+        }
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetInstanceStatus {
+                addr: addrs[1].clone(),
+                status: InstanceStatus::Archived
+            },
+            MockEnv::new("creator", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetInstanceStatus {
+                addr: addrs[1].clone(),
+                status: InstanceStatus::Archived
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: addrs[1].clone() }
+        ).unwrap();
+
+        assert_eq!(instance.unwrap().status, InstanceStatus::Archived);
+
+        let active: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: None,
+                filter: None
+            }
+        ).unwrap();
+
+        assert_eq!(active.total, 2);
+        assert_eq!(
+            active.entries.iter().map(|x| x.extra.as_str()).collect::<Vec<_>>(),
+            ["extra data 0", "extra data 2"]
+        );
+
+        // Pages are full even though the archived instance is in between.
+        let mut pagination = Pagination::new(0, 1);
+
+        for (extra, has_more) in [("extra data 0", true), ("extra data 2", false)] {
+            let page: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &QueryMsg::ListInstances {
+                    pagination: pagination.clone(),
+                    start_after: None,
+                    filter: None
+                }
+            ).unwrap();
+
+            assert_eq!(page.total, 2);
+            assert_eq!(page.entries.len(), 1);
+            assert_eq!(page.entries[0].extra, extra);
+            assert_eq!(page.has_more, has_more);
+
+            pagination.cursor = page.next;
+        }
+
+        for code_id in [None, Some(0)] {
+            let archived: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+                &factory.address,
+                &QueryMsg::ListInstances {
+                    pagination: Pagination::new(0, 10),
+                    start_after: None,
+                    filter: Some(InstanceFilter {
+                        code_id,
+                        code_hash: None,
+                        status: Some(InstanceStatus::Archived)
+                    })
+                }
+            ).unwrap();
+
+            assert_eq!(archived.entries.len(), 1);
+            assert_eq!(archived.entries[0].extra, "extra data 1");
+        }
+
+        let retired: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::ListInstances {
+                pagination: Pagination::new(0, 10),
+                start_after: None,
+                filter: Some(InstanceFilter {
+                    status: Some(InstanceStatus::Retired),
+                    ..Default::default()
+                })
+            }
+        ).unwrap();
+
+        assert_eq!(retired.total, 0);
+
+        // Other listings are not affected.
+        let all: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstancesByCodeId {
+                code_id: 0,
+                pagination: Pagination::new(0, 10)
+            }
+        ).unwrap();
+
+        assert_eq!(all.total, 3);
+    }
+
+    #[test]
+    fn deprecated_code_is_refused_after_sunset() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let config = InstanceConfig {
+            msg: ChildInstantiateMsg {
+                text: String::from("flaming swords")
+            },
+            funds: Vec::new(),
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let sunset = Timestamp::from_seconds(ensemble.block().time + 1000);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::DeprecateCode {
+                code_key: None,
+                sunset: Some(sunset)
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let deprecation: Option<Deprecation> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeDeprecation { code_id: 0 }
+        ).unwrap();
+
+        assert_eq!(deprecation, Some(Deprecation { sunset: Some(sunset) }));
+
+        let resp = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+
+        ensemble.block_mut().time += 1000;
+
+        let err = ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::CodeSunset { code_id: 0, sunset }).to_string()
+        );
+
+        let instance: Option<Instance<Addr, String>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::InstanceByAddr { addr: extract_instance_addr(&resp) }
+        ).unwrap();
+
+        assert!(instance.is_some());
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UndeprecateCode { code_key: None },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
+    }
+
+    #[test]
+    fn code_change_is_timelocked() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                code_change_delay: 1000,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(code.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pending: Option<PendingCodeChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeChange { }
+        ).unwrap();
+
+        let pending = pending.unwrap();
+        assert_eq!(pending.code, code);
+        assert_eq!(pending.proposer, ADMIN);
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApplyCodeChange { },
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            format!(
+                "Generic error: Code change cannot be applied before {}.",
+                pending.available_at.seconds()
+            )
+        );
+
+        ensemble.block_mut().time = pending.available_at.seconds();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApplyCodeChange { },
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap();
+
+        let pending: Option<PendingCodeChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeChange { }
+        ).unwrap();
+
+        assert!(pending.is_none());
+
+        let history: PaginatedResponse<CodeChange<Addr>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeHistory { pagination: Pagination::new(1, 10) }
+        ).unwrap();
+
+        assert_eq!(history.entries[0].code, code);
+        assert_eq!(history.entries[0].admin, ADMIN);
+    }
+
+    #[test]
+    fn code_registration_is_timelocked() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                key: "child".into(),
+                code: code.clone(),
+                metadata: None
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                code_change_delay: 1000,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let mut other = code.clone();
+        other.id += 1;
+
+        // Neither overwriting the key nor removing
+        // and registering it again bypasses the delay.
+        for remove in [false, true] {
+            if remove {
+                ensemble.execute(
+                    &ExecuteMsg::<ChildInstantiateMsg>::RemoveCode { key: "child".into() },
+                    MockEnv::new(ADMIN, &factory.address)
+                ).unwrap();
+            }
+
+            ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                    key: "child".into(),
+                    code: other.clone(),
+                    metadata: None
+                },
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+
+            let registered: Option<RegisteredCode> = ensemble.query(
+                &factory.address,
+                &QueryMsg::CodeByKey { key: "child".into() }
+            ).unwrap();
+
+            assert_eq!(registered.map(|x| x.code), (!remove).then(|| code.clone()));
+        }
+
+        let pending: Option<PendingCodeRegistration> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeRegistration { key: "child".into() }
+        ).unwrap();
+
+        let pending = pending.unwrap();
+        assert_eq!(pending.code.code, other);
+        assert_eq!(pending.proposer, ADMIN);
+
+        let apply = ExecuteMsg::<ChildInstantiateMsg>::ApplyCodeRegistration {
+            key: "child".into()
+        };
+
+        let err = ensemble.execute(
+            &apply,
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            format!(
+                "Generic error: Code registration cannot be applied before {}.",
+                pending.available_at.seconds()
+            )
+        );
+
+        ensemble.block_mut().time = pending.available_at.seconds();
+
+        ensemble.execute(
+            &apply,
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap();
+
+        let registered: Option<RegisteredCode> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeByKey { key: "child".into() }
+        ).unwrap();
+
+        assert_eq!(registered.unwrap().code, other);
+
+        let err = ensemble.execute(
+            &apply,
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: No registration of code \"child\" is pending."
+        );
+
+        // Removing the key cancels a pending registration.
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                key: "new".into(),
+                code,
+                metadata: None
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RemoveCode { key: "new".into() },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pending: Option<PendingCodeRegistration> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeRegistration { key: "new".into() }
+        ).unwrap();
+
+        assert!(pending.is_none());
+    }
+
+    #[test]
+    fn code_change_delay_decrease_is_timelocked() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                code_change_delay: 1000,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config::default()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config: Config = ensemble.query(
+            &factory.address,
+            &QueryMsg::Config { }
+        ).unwrap();
+
+        assert_eq!(config.code_change_delay, 1000);
+
+        let decrease: Option<PendingDelayDecrease> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingDelayDecrease { }
+        ).unwrap();
+
+        let decrease = decrease.unwrap();
+        assert_eq!(decrease.delay, 0);
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(code.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pending: Option<PendingCodeChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeChange { }
+        ).unwrap();
+
+        assert_eq!(pending.unwrap().code, code);
+
+        ensemble.block_mut().time = decrease.available_at.seconds();
+
+        let mut other = code.clone();
+        other.id += 1;
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(other.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pending: Option<PendingCodeChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeChange { }
+        ).unwrap();
+
+        assert!(pending.is_none());
+
+        let current: ContractCode = ensemble.query(
+            &factory.address,
+            &QueryMsg::ContractCode { }
+        ).unwrap();
+
+        assert_eq!(current, other);
+
+        let decrease: Option<PendingDelayDecrease> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingDelayDecrease { }
+        ).unwrap();
+
+        assert!(decrease.is_none());
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                code_change_delay: 1000,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config::default()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let decrease: Option<PendingDelayDecrease> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingDelayDecrease { }
+        ).unwrap();
+
+        let decrease = decrease.unwrap();
+
+        // Submitting the current or the pending delay again, e.g. while
+        // changing another setting, keeps the pending decrease as is.
+        ensemble.block_mut().time += 10;
+
+        for delay in [1000, 0] {
+            ensemble.execute(
+                &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                    code_change_delay: delay,
+                    ..Config::default()
+                }),
+                MockEnv::new(ADMIN, &factory.address)
+            ).unwrap();
+
+            let pending: Option<PendingDelayDecrease> = ensemble.query(
+                &factory.address,
+                &QueryMsg::PendingDelayDecrease { }
+            ).unwrap();
+
+            assert_eq!(pending, Some(decrease.clone()));
         }
 
-        fn query(&self, _deps: Deps, _env: Env, _msg: Binary) -> AnyResult<Binary> {
-            todo!()
-        }
-    }
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::CancelDelayDecrease { },
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(err.unwrap_contract_error().to_string(), "Generic error: Unauthorized");
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::CancelDelayDecrease { },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pending: Option<PendingDelayDecrease> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingDelayDecrease { }
+        ).unwrap();
+
+        assert!(pending.is_none());
+
+        // Raising the delay cancels a pending decrease.
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config::default()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                code_change_delay: 2000,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let decrease: Option<PendingDelayDecrease> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingDelayDecrease { }
+        ).unwrap();
+
+        assert!(decrease.is_none());
+    }
+
+    #[test]
+    fn code_change_requires_confirmation() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                confirm_code_changes: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.block_mut().freeze();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(code.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApplyCodeChange { },
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Code change hasn't been confirmed."
+        );
+
+        let confirm = ExecuteMsg::<ChildInstantiateMsg>::ConfirmCodeChange(code.clone());
+
+        let err = ensemble.execute(
+            &confirm,
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: The proposer must confirm the code change in a later block."
+        );
+
+        ensemble.block_mut().unfreeze();
+        ensemble.block_mut().next();
+
+        let mut typo = code.clone();
+        typo.id += 1;
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ConfirmCodeChange(typo),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Confirmed code doesn't match the pending code change."
+        );
+
+        let err = ensemble.execute(
+            &confirm,
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &confirm,
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pending: Option<PendingCodeChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeChange { }
+        ).unwrap();
+
+        assert!(pending.is_none());
+
+        let history: PaginatedResponse<CodeChange<Addr>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeHistory { pagination: Pagination::new(0, 10) }
+        ).unwrap();
+
+        assert_eq!(history.total, 2);
+        assert_eq!(history.entries[1].code, code);
+    }
+
+    #[test]
+    fn code_registration_requires_confirmation() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
+
+        let confirmer = Addr::unchecked("confirmer");
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                confirm_code_changes: true,
+                code_change_confirmer: Some(confirmer.clone()),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                key: "child".into(),
+                code: code.clone(),
+                metadata: None
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let registered: Option<RegisteredCode> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeByKey { key: "child".into() }
+        ).unwrap();
+
+        assert!(registered.is_none());
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ApplyCodeRegistration { key: "child".into() },
+            MockEnv::new("anyone", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Code registration hasn't been confirmed."
+        );
+
+        let confirm = ExecuteMsg::<ChildInstantiateMsg>::ConfirmCodeRegistration {
+            key: "child".into(),
+            code: code.clone()
+        };
+
+        let err = ensemble.execute(
+            &confirm,
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        let mut typo = code.clone();
+        typo.id += 1;
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ConfirmCodeRegistration {
+                key: "child".into(),
+                code: typo
+            },
+            MockEnv::new(&confirmer, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Confirmed code doesn't match the pending code registration."
+        );
+
+        ensemble.execute(
+            &confirm,
+            MockEnv::new(&confirmer, &factory.address)
+        ).unwrap();
+
+        let registered: Option<RegisteredCode> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeByKey { key: "child".into() }
+        ).unwrap();
+
+        assert_eq!(registered.unwrap().code, code);
+
+        let pending: Option<PendingCodeRegistration> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingCodeRegistration { key: "child".into() }
+        ).unwrap();
+
+        assert!(pending.is_none());
+    }
+
+    #[test]
+    fn confirmer_change_requires_confirmer() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        let confirmer = Addr::unchecked("confirmer");
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                confirm_code_changes: true,
+                code_change_confirmer: Some(confirmer.clone()),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config::default()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config: Config = ensemble.query(
+            &factory.address,
+            &QueryMsg::Config { }
+        ).unwrap();
+
+        assert!(config.confirm_code_changes);
+        assert_eq!(config.code_change_confirmer, Some(confirmer.clone()));
+
+        let change: Option<ConfirmerChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingConfirmerChange { }
+        ).unwrap();
+
+        let change = change.unwrap();
+        assert_eq!(change, ConfirmerChange {
+            confirm_code_changes: false,
+            code_change_confirmer: None
+        });
+
+        // Submitting the current settings again leaves the pending change alone.
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                confirm_code_changes: true,
+                code_change_confirmer: Some(confirmer.clone()),
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pending: Option<ConfirmerChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingConfirmerChange { }
+        ).unwrap();
+
+        assert_eq!(pending, Some(change.clone()));
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ConfirmConfirmerChange(change.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ConfirmConfirmerChange(ConfirmerChange {
+                confirm_code_changes: true,
+                code_change_confirmer: Some(Addr::unchecked(ADMIN))
+            }),
+            MockEnv::new(&confirmer, &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Confirmed change doesn't match the pending confirmer change."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ConfirmConfirmerChange(change),
+            MockEnv::new(&confirmer, &factory.address)
+        ).unwrap();
+
+        let config: Config = ensemble.query(
+            &factory.address,
+            &QueryMsg::Config { }
+        ).unwrap();
+
+        assert!(!config.confirm_code_changes);
+        assert_eq!(config.code_change_confirmer, None);
+
+        let change: Option<ConfirmerChange> = ensemble.query(
+            &factory.address,
+            &QueryMsg::PendingConfirmerChange { }
+        ).unwrap();
+
+        assert!(change.is_none());
+    }
+
+    #[test]
+    fn malformed_code_hash_is_rejected() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(Config {
+                verify_code_hashes: true,
+                ..Config::default()
+            }),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let mut code = ContractCode {
+            id: 5,
+            code_hash: "a1b2c3".into()
+        };
 
-    struct Suite {
-        ensemble: ContractEnsemble,
-        factory: ContractLink<Addr>
-    }
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(code.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap_err();
 
-    impl Suite {
-        fn new<const AUTH: bool>() -> Self {
-            let mut ensemble = ContractEnsemble::new();
-            let child = ensemble.register(Box::new(Child));
-            let factory = ensemble.register(
-                Box::new(GenericFactory::<ChildInstantiateMsg, String, AUTH> {
-                    msg_phantom: PhantomData,
-                    extra_phantom: PhantomData
-                })
-            );
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Invalid code hash: \"a1b2c3\". Expecting 64 hexadecimal characters."
+        );
 
-            let factory = ensemble.instantiate(
-                factory.id,
-                &InstantiateMsg {
-                    admin: None,
-                    code: child
-                },
-                MockEnv::new(ADMIN, "factory")
-            )
-            .unwrap()
-            .instance;
+        code.code_hash = "ab".repeat(32);
 
-            Self { ensemble, factory }
-        }
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(code),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
     }
 
     #[test]
-    fn only_admin_can_instantiate_when_auth_param_is_true() {
-        let Suite { mut ensemble, factory } = Suite::new::<true>();
+    fn code_can_be_paused() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
 
-        let config = InstanceConfig {
-            msg: ChildInstantiateMsg {
-                text: String::from("flaming swords")
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                key: "second".into(),
+                code,
+                metadata: None
             },
-            funds: Vec::new()
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let pause = ExecuteMsg::<ChildInstantiateMsg>::SetCodePaused {
+            code_key: Some("second".into()),
+            paused: true
         };
-        
+
         let err = ensemble.execute(
-            &ExecuteMsg::CreateInstance(config.clone()),
+            &pause,
             MockEnv::new("not admin", &factory.address)
         ).unwrap_err();
 
@@ -530,133 +7670,205 @@ mod tests {
         );
 
         ensemble.execute(
-            &ExecuteMsg::CreateInstance(config),
+            &pause,
             MockEnv::new(ADMIN, &factory.address)
         ).unwrap();
-    }
 
-    #[test]
-    fn only_admin_can_instantiate_when_auth_param_is_false() {
-        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let paused: bool = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodePaused { code_key: Some("second".into()) }
+        ).unwrap();
 
-        let config = InstanceConfig {
+        assert!(paused);
+
+        let mut config = InstanceConfig {
             msg: ChildInstantiateMsg {
                 text: String::from("flaming swords")
             },
-            funds: Vec::new()
+            funds: Vec::new(),
+            code_key: Some("second".into()),
+            label: None,
+            extra: None
         };
 
-        ensemble.execute(
+        let err = ensemble.execute(
             &ExecuteMsg::CreateInstance(config.clone()),
             MockEnv::new("not admin", &factory.address)
-        ).unwrap();
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::CodePaused {
+                code_key: Some("second".into())
+            }).to_string()
+        );
+
+        // Other codes are not affected.
+        config.code_key = None;
 
         ensemble.execute(
             &ExecuteMsg::CreateInstance(config),
-            MockEnv::new(ADMIN, &factory.address)
+            MockEnv::new("not admin", &factory.address)
         ).unwrap();
     }
 
     #[test]
-    fn instances_are_stored_with_extra_data() {
+    fn code_allowlist_restricts_creators() {
         let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
 
-        let config = InstanceConfig {
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::RegisterCode {
+                key: "treasury".into(),
+                code,
+                metadata: None
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::SetCodeAllowlist {
+                code_key: Some("treasury".into()),
+                addresses: Some(vec!["dao".into()])
+            },
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let allowlist: Option<Vec<Addr>> = ensemble.query(
+            &factory.address,
+            &QueryMsg::CodeAllowlist { code_key: Some("treasury".into()) }
+        ).unwrap();
+
+        assert_eq!(allowlist, Some(vec![Addr::unchecked("dao")]));
+
+        let mut config = InstanceConfig {
             msg: ChildInstantiateMsg {
                 text: String::from("flaming swords")
             },
-            funds: Vec::new()
+            funds: Vec::new(),
+            code_key: Some("treasury".into()),
+            label: None,
+            extra: None
         };
 
-        let resp = ensemble.execute(
+        let err = ensemble.execute(
             &ExecuteMsg::CreateInstance(config.clone()),
             MockEnv::new("not admin", &factory.address)
-        ).unwrap();
-
-        let addr = extract_instance_addr(&resp);
+        ).unwrap_err();
 
-        let instance: Instance<Addr, String> = ensemble.query(
-            &factory.address,
-            &QueryMsg::InstanceByAddr { addr }
-        )
-        .unwrap();
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            StdError::from(FactoryError::NotAllowedForCode {
+                code_key: Some("treasury".into())
+            }).to_string()
+        );
 
-        assert!(instance.contract.address.as_str().starts_with("fadroma factory child instance"));
-        assert_eq!(instance.contract.code_hash, "test_contract_0");
-        assert_eq!(instance.extra, "flaming swords");
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config.clone()),
+            MockEnv::new("dao", &factory.address)
+        ).unwrap();
 
-        let instance: Option<Instance<Addr, String>> = ensemble.query(
-            &factory.address,
-            &QueryMsg::InstanceByAddr { addr: "wrong addr".into() }
-        )
-        .unwrap();
+        config.code_key = None;
 
-        assert!(instance.is_none());
+        ensemble.execute(
+            &ExecuteMsg::CreateInstance(config),
+            MockEnv::new("not admin", &factory.address)
+        ).unwrap();
     }
 
     #[test]
-    fn list_instances() {
-        let Suite { mut ensemble, factory } = Suite::new::<true>();
+    fn code_history_is_recorded() {
+        let Suite { mut ensemble, factory } = Suite::new::<false>();
+        let code = ensemble.register(Box::new(Child));
 
-        let num_instances: u8 = 10;
+        let resp = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(code.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
 
-        for i in 0..num_instances {
-            let config = InstanceConfig {
-                msg: ChildInstantiateMsg {
-                    text: format!("extra data {i}")
-                },
-                funds: Vec::new()
-            };
+        let event = &resp.response.events[0];
+        assert_eq!(event.ty, CODE_CHANGE_EVENT);
 
-            ensemble.execute(
-                &ExecuteMsg::CreateInstance(config),
-                MockEnv::new(ADMIN, &factory.address)
-            ).unwrap();
-        }
+        let attrs = event.attributes.iter()
+            .map(|x| (x.key.as_str(), x.value.as_str()))
+            .collect::<Vec<_>>();
 
-        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+        assert_eq!(attrs, [
+            ("old_code_id", "0"),
+            ("old_code_hash", "test_contract_0"),
+            ("new_code_id", code.id.to_string().as_str()),
+            ("new_code_hash", code.code_hash.as_str()),
+            ("admin", ADMIN)
+        ]);
+
+        let current: ContractCode = ensemble.query(
             &factory.address,
-            &QueryMsg::ListInstances {
-                pagination: Pagination::new(0, num_instances / 2)
-            }
+            &QueryMsg::ContractCode { }
         ).unwrap();
 
-        assert_eq!(instances.total, num_instances as u64);
-        assert_eq!(instances.entries.len(), (num_instances / 2) as usize);
-
-        for (i, instance) in instances.entries.iter().enumerate() {
-            assert!(instance.contract.address.as_str().starts_with("fadroma factory child instance"));
-            assert_eq!(instance.contract.code_hash, "test_contract_0");
-            assert_eq!(instance.extra, format!("extra data {i}"));
-        }
+        assert_eq!(current, code);
 
-        let instances: PaginatedResponse<Instance<Addr, String>> = ensemble.query(
+        let history: PaginatedResponse<CodeChange<Addr>> = ensemble.query(
             &factory.address,
-            &QueryMsg::ListInstances {
-                pagination: Pagination::new((num_instances / 2) as u64, num_instances)
-            }
+            &QueryMsg::CodeHistory { pagination: Pagination::new(0, 10) }
         ).unwrap();
 
-        assert_eq!(instances.total, num_instances as u64);
-        assert_eq!(instances.entries.len(), (num_instances / 2) as usize);
+        assert_eq!(history.total, 2);
 
-        for (i, instance) in instances.entries.iter().enumerate() {
-            assert!(instance.contract.address.as_str().starts_with("fadroma factory child instance"));
-            assert_eq!(instance.contract.code_hash, "test_contract_0");
-            assert_eq!(instance.extra, format!("extra data {}", i as u8 + (num_instances / 2)));
-        }
+        let initial = &history.entries[0];
+        assert_eq!(initial.code.code_hash, "test_contract_0");
+        assert_eq!(initial.admin, ADMIN);
+        assert_eq!(initial.previous_code_hash, None);
+
+        let changed = &history.entries[1];
+        assert_eq!(changed.code, code);
+        assert_eq!(changed.admin, ADMIN);
+        assert_eq!(changed.previous_code_hash.as_deref(), Some("test_contract_0"));
+        assert!(changed.block_height > initial.block_height);
     }
 
     #[test]
-    fn only_admin_can_change_contract_code() {
+    fn queries_can_be_paused_with_killswitch() {
         let Suite { mut ensemble, factory } = Suite::new::<false>();
-        let err = ensemble.execute(
-            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(
-                ContractCode {
-                    id: 2,
-                    code_hash: "code_hash".into()
+
+        let list = QueryMsg::ListInstances {
+            pagination: Pagination::new(0, 10),
+            start_after: None,
+            filter: None
+        };
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::Killswitch(
+                killswitch::ExecuteMsg::SetStatus {
+                    status: killswitch::ContractStatus::Paused {
+                        reason: "incident".into()
+                    }
+                }
+            ),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.query::<_, PaginatedResponse<Instance<Addr, String>>>(
+            &factory.address,
+            &list
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::Killswitch(
+                killswitch::ExecuteMsg::SetStatus {
+                    status: killswitch::ContractStatus::Operational
                 }
             ),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let config = Config {
+            pause_queries: true,
+            ..Config::default()
+        };
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(config.clone()),
             MockEnv::new("not admin", &factory.address)
         ).unwrap_err();
 
@@ -666,14 +7878,124 @@ mod tests {
         );
 
         ensemble.execute(
-            &ExecuteMsg::<ChildInstantiateMsg>::ChangeContractCode(
-                ContractCode {
-                    id: 2,
-                    code_hash: "code_hash".into()
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateConfig(config.clone()),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::Killswitch(
+                killswitch::ExecuteMsg::SetStatus {
+                    status: killswitch::ContractStatus::Paused {
+                        reason: "incident".into()
+                    }
                 }
             ),
-            MockEnv::new(ADMIN, factory.address)
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.query::<_, PaginatedResponse<Instance<Addr, String>>>(
+            &factory.address,
+            &list
+        ).unwrap_err();
+
+        assert!(err.to_string().contains("Paused"));
+
+        let stored: Config = ensemble.query(
+            &factory.address,
+            &QueryMsg::Config { }
+        ).unwrap();
+
+        assert_eq!(stored, config);
+    }
+
+    #[cfg(feature = "cw-ownable")]
+    #[test]
+    fn cw_ownable_ownership() {
+        use ownable::{Action, Ownership, Expiration};
+
+        let Suite { mut ensemble, factory } = Suite::new::<true>();
+
+        let ownership: Ownership<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::Ownership { }
+        ).unwrap();
+
+        assert_eq!(ownership.owner.unwrap(), ADMIN);
+        assert!(ownership.pending_owner.is_none());
+
+        let height = ensemble.block().height;
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateOwnership(
+                Action::TransferOwnership {
+                    new_owner: "new owner".into(),
+                    expiry: Some(Expiration::AtHeight(height + 1))
+                }
+            ),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateOwnership(Action::AcceptOwnership),
+            MockEnv::new("new owner", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: The ownership transfer has expired."
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateOwnership(
+                Action::TransferOwnership {
+                    new_owner: "new owner".into(),
+                    expiry: None
+                }
+            ),
+            MockEnv::new(ADMIN, &factory.address)
+        ).unwrap();
+
+        let ownership: Ownership<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::Ownership { }
+        ).unwrap();
+
+        assert_eq!(ownership.pending_owner.unwrap(), "new owner");
+        assert!(ownership.pending_expiry.is_none());
+
+        let err = ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateOwnership(Action::AcceptOwnership),
+            MockEnv::new("not new owner", &factory.address)
+        ).unwrap_err();
+
+        assert_eq!(
+            err.unwrap_contract_error().to_string(),
+            "Generic error: Unauthorized"
+        );
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateOwnership(Action::AcceptOwnership),
+            MockEnv::new("new owner", &factory.address)
+        ).unwrap();
+
+        let admin: Option<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::Admin(admin::QueryMsg::Admin { })
         ).unwrap();
+
+        assert_eq!(admin.unwrap(), "new owner");
+
+        ensemble.execute(
+            &ExecuteMsg::<ChildInstantiateMsg>::UpdateOwnership(Action::RenounceOwnership),
+            MockEnv::new("new owner", &factory.address)
+        ).unwrap();
+
+        let ownership: Ownership<Addr> = ensemble.query(
+            &factory.address,
+            &QueryMsg::Ownership { }
+        ).unwrap();
+
+        assert!(ownership.owner.is_none());
+        assert!(ownership.pending_owner.is_none());
     }
 
     fn extract_instance_addr(resp: &ExecuteResponse) -> String {
@@ -0,0 +1,208 @@
+//! An ownership interface compatible with [cw-ownable](https://crates.io/crates/cw-ownable)
+//! so that standard tooling recognizes the factory admin as its owner. The owner is the
+//! same address as the one managed by [`fadroma::admin`] and both interfaces can be used
+//! interchangeably.
+//! *Feature flag: `cw-ownable`*
+
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, Env,
+        MessageInfo, Addr, Timestamp, BlockInfo, CanonicalAddr
+    },
+    bin_serde::{
+        self, FadromaSerialize, FadromaDeserialize,
+        Serializer, Deserializer
+    },
+    storage::SingleItem,
+    core::Canonize,
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Propose to transfer the ownership to a new address. Can only be
+    /// called by the current owner. The pending owner must call
+    /// [`Action::AcceptOwnership`] before the `expiry`, if any.
+    TransferOwnership {
+        new_owner: String,
+        expiry: Option<Expiration>
+    },
+    /// Accept the pending ownership transfer. Can only be
+    /// called by the pending owner.
+    AcceptOwnership,
+    /// Give up the ownership of the contract permanently.
+    /// Can only be called by the current owner.
+    RenounceOwnership
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct Ownership<T> {
+    pub owner: Option<T>,
+    pub pending_owner: Option<T>,
+    pub pending_expiry: Option<Expiration>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Expiration {
+    AtHeight(u64),
+    AtTime(Timestamp),
+    Never { }
+}
+
+/// The expiry is stored together with the address that it applies to
+/// so that it is ignored if the pending admin is later changed through
+/// [`fadroma::admin`] instead.
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct PendingExpiry {
+    owner: CanonicalAddr,
+    expiry: Expiration
+}
+
+namespace!(PendingExpiryNs, b"ownership_pending_expiry");
+const PENDING_EXPIRY: SingleItem<PendingExpiry, PendingExpiryNs> = SingleItem::new();
+
+impl Expiration {
+    #[inline]
+    pub fn is_expired(&self, block: &BlockInfo) -> bool {
+        match self {
+            Self::AtHeight(height) => block.height >= *height,
+            Self::AtTime(time) => block.time >= *time,
+            Self::Never { } => false
+        }
+    }
+}
+
+// Timestamp doesn't implement the Fadroma binary serialization
+// traits so we store it as nanoseconds instead.
+impl FadromaSerialize for Expiration {
+    #[inline]
+    fn size_hint(&self) -> usize {
+        1 + 8
+    }
+
+    fn to_bytes(&self, ser: &mut Serializer) -> bin_serde::Result<()> {
+        match self {
+            Self::AtHeight(height) => {
+                0u8.to_bytes(ser)?;
+                height.to_bytes(ser)
+            }
+            Self::AtTime(time) => {
+                1u8.to_bytes(ser)?;
+                time.nanos().to_bytes(ser)
+            }
+            Self::Never { } => 2u8.to_bytes(ser)
+        }
+    }
+}
+
+impl FadromaDeserialize for Expiration {
+    fn from_bytes(de: &mut Deserializer) -> bin_serde::Result<Self> {
+        match u8::from_bytes(de)? {
+            0 => Ok(Self::AtHeight(u64::from_bytes(de)?)),
+            1 => Ok(Self::AtTime(Timestamp::from_nanos(u64::from_bytes(de)?))),
+            2 => Ok(Self::Never { }),
+            _ => Err(bin_serde::Error::InvalidType)
+        }
+    }
+}
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    pub fn update_ownership(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        action: Action
+    ) -> StdResult<Response> {
+        match action {
+            Action::TransferOwnership { new_owner, expiry } => {
+                admin::assert(deps.as_ref(), &info)?;
+
+                let new_owner = new_owner.as_str().canonize(deps.api)?;
+                admin::PENDING_ADMIN.save(deps.storage, &new_owner)?;
+
+                if let Some(expiry) = expiry {
+                    if expiry.is_expired(&env.block) {
+                        return Err(StdError::generic_err("Expiry time has already passed."));
+                    }
+
+                    PENDING_EXPIRY.save(deps.storage, &PendingExpiry {
+                        owner: new_owner,
+                        expiry
+                    })?;
+                } else {
+                    PENDING_EXPIRY.remove(deps.storage);
+                }
+            }
+            Action::AcceptOwnership => {
+                let ownership = Self::ownership(deps.as_ref())?;
+
+                if ownership.pending_owner.as_ref() != Some(&info.sender) {
+                    return Err(StdError::generic_err("Unauthorized"));
+                }
+
+                if let Some(expiry) = ownership.pending_expiry {
+                    if expiry.is_expired(&env.block) {
+                        return Err(StdError::generic_err("The ownership transfer has expired."));
+                    }
+                }
+
+                admin::STORE.canonize_and_save(deps.branch(), info.sender.as_str())?;
+                Self::clear_pending_owner(deps);
+            }
+            Action::RenounceOwnership => {
+                admin::assert(deps.as_ref(), &info)?;
+
+                admin::STORE.remove(deps.storage);
+                Self::clear_pending_owner(deps);
+            }
+        }
+
+        Ok(Response::default().add_attribute("action", "update_ownership"))
+    }
+
+    pub fn ownership(deps: Deps) -> StdResult<Ownership<Addr>> {
+        let owner = admin::STORE.load_humanize(deps)?;
+        let pending_owner = admin::PENDING_ADMIN.load_humanize(deps)?;
+
+        // The Fadroma admin module doesn't clear the pending
+        // address once it accepts the role, so we ignore it here.
+        if pending_owner.is_none() || pending_owner == owner {
+            return Ok(Ownership {
+                owner,
+                pending_owner: None,
+                pending_expiry: None
+            });
+        }
+
+        let pending_expiry = match PENDING_EXPIRY.load(deps.storage)? {
+            Some(pending) if admin::PENDING_ADMIN.load(deps.storage)?.as_ref() == Some(&pending.owner) =>
+                Some(pending.expiry),
+            _ => None
+        };
+
+        Ok(Ownership {
+            owner,
+            pending_owner,
+            pending_expiry
+        })
+    }
+
+    #[inline]
+    fn clear_pending_owner(deps: DepsMut) {
+        admin::PENDING_ADMIN.remove(deps.storage);
+        PENDING_EXPIRY.remove(deps.storage);
+    }
+}
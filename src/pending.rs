@@ -0,0 +1,112 @@
+//! The instances whose instantiate submessages were dispatched but not
+//! replied to yet. Each instantiate submessage gets its own reply ID from
+//! the range reserved for the factory so that a reply can be matched with
+//! its instance regardless of the order in which replies arrive.
+
+use fadroma::{
+    cosmwasm_std::{StdResult, StdError, Storage, CanonicalAddr},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{SingleItem, ItemSpace, TypedKey},
+    namespace
+};
+
+use crate::{PendingInstance, FactoryError, reply::ReplyIdRange};
+
+#[derive(FadromaSerialize, FadromaDeserialize, Default)]
+struct Counter {
+    /// The number of instances ever pushed. Determines the next reply ID.
+    pushed: u64,
+    /// The number of instances currently pending.
+    len: u64
+}
+
+namespace!(PendingCounterNs, b"pending_counter");
+const COUNTER: SingleItem<Counter, PendingCounterNs> = SingleItem::new();
+
+namespace!(PendingNs, b"pending");
+const PENDING: ItemSpace<
+    PendingInstance,
+    PendingNs,
+    TypedKey<u64>
+> = ItemSpace::new();
+
+namespace!(PendingByCreatorNs, b"pending_by_creator");
+const PENDING_BY_CREATOR: ItemSpace<
+    u64,
+    PendingByCreatorNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+/// Returns the reply ID that the instantiate submessage must be sent with.
+pub(crate) fn push(
+    storage: &mut dyn Storage,
+    ids: &ReplyIdRange,
+    pending: &PendingInstance
+) -> StdResult<u64> {
+    let mut counter = COUNTER.load_or_default(storage)?;
+    let id = ids.id(counter.pushed % ids.size())?;
+
+    if PENDING.load(storage, &id)?.is_some() {
+        return Err(StdError::generic_err("Too many instances are pending."));
+    }
+
+    PENDING.save(storage, &id, pending)?;
+
+    let creator = &pending.created.creator;
+    let count = PENDING_BY_CREATOR.load_or_default(storage, creator)?;
+    PENDING_BY_CREATOR.save(storage, creator, &(count + 1))?;
+
+    counter.pushed += 1;
+    counter.len += 1;
+
+    COUNTER.save(storage, &counter)?;
+
+    Ok(id)
+}
+
+/// Removes the instance that the reply with `id` belongs to. Fails if
+/// there is none, i.e. the reply wasn't caused by the factory.
+pub(crate) fn take(storage: &mut dyn Storage, id: u64) -> StdResult<PendingInstance> {
+    let Some(pending) = PENDING.load(storage, &id)? else {
+        return Err(FactoryError::UnsolicitedReply { id }.into());
+    };
+
+    PENDING.remove(storage, &id);
+
+    let creator = &pending.created.creator;
+    let count = PENDING_BY_CREATOR.load_or_default(storage, creator)?;
+
+    if count > 1 {
+        PENDING_BY_CREATOR.save(storage, creator, &(count - 1))?;
+    } else {
+        PENDING_BY_CREATOR.remove(storage, creator);
+    }
+
+    let mut counter = COUNTER.load_or_default(storage)?;
+    counter.len -= 1;
+
+    COUNTER.save(storage, &counter)?;
+
+    Ok(pending)
+}
+
+/// The instance that the reply with `id` belongs to.
+pub(crate) fn peek(storage: &dyn Storage, id: u64) -> StdResult<Option<PendingInstance>> {
+    PENDING.load(storage, &id)
+}
+
+#[inline]
+pub(crate) fn len(storage: &dyn Storage) -> StdResult<u64> {
+    let counter = COUNTER.load_or_default(storage)?;
+
+    Ok(counter.len)
+}
+
+/// The number of pending instances created by `creator`.
+#[inline]
+pub(crate) fn count_by_creator(
+    storage: &dyn Storage,
+    creator: &CanonicalAddr
+) -> StdResult<u64> {
+    PENDING_BY_CREATOR.load_or_default(storage, creator)
+}
@@ -0,0 +1,152 @@
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, Env,
+        MessageInfo, Addr, Binary, to_binary, from_binary
+    },
+    storage::{SingleItem, ItemSpace, TypedKey},
+    crypto::sha_256,
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, InstanceConfig};
+
+/// Authorizes creating an instance with a particular config using
+/// [`crate::ExecuteMsg::CreateInstanceWithPermit`]. Signed off-chain
+/// by the key set with [`crate::ExecuteMsg::SetPermitSigner`].
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct CreationPermit {
+    /// The address that the instance is created on behalf of.
+    pub creator: String,
+    /// The time in seconds after which the permit cannot be used.
+    pub expiry: u64,
+    /// Each nonce can only be used once.
+    pub nonce: u64
+}
+
+/// The message that the permit signer signs. It is serialized as JSON,
+/// in this field order, and the signature is over its SHA-256 hash.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+pub struct SignedPermit {
+    /// The address of the factory, so that a permit
+    /// cannot be used with another factory.
+    pub factory: Addr,
+    pub creator: String,
+    /// The SHA-256 hash of the serialized [`InstanceConfig`]
+    /// exactly as sent to the factory.
+    pub config_hash: Binary,
+    pub expiry: u64,
+    pub nonce: u64
+}
+
+namespace!(PermitSignerNs, b"permit_signer");
+const PERMIT_SIGNER: SingleItem<Binary, PermitSignerNs> = SingleItem::new();
+
+namespace!(UsedPermitNoncesNs, b"used_permit_nonces");
+const USED_PERMIT_NONCES: ItemSpace<
+    bool,
+    UsedPermitNoncesNs,
+    TypedKey<u64>
+> = ItemSpace::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Sets the secp256k1 public key that signs creation permits.
+    /// Permits cannot be used if [`None`].
+    #[admin::require_admin]
+    pub fn set_permit_signer(
+        deps: DepsMut,
+        info: MessageInfo,
+        pubkey: Option<Binary>
+    ) -> StdResult<Response> {
+        match pubkey {
+            Some(pubkey) => PERMIT_SIGNER.save(deps.storage, &pubkey)?,
+            None => PERMIT_SIGNER.remove(deps.storage)
+        }
+
+        Ok(Response::default())
+    }
+
+    #[inline]
+    pub fn permit_signer(deps: Deps) -> StdResult<Option<Binary>> {
+        PERMIT_SIGNER.load(deps.storage)
+    }
+}
+
+impl<
+    MSG: Serialize + DeserializeOwned + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Creates an instance on behalf of the permit creator. Anyone can
+    /// present the permit and the creation fee is charged from them.
+    /// `config` is the serialized [`InstanceConfig`] that the permit was
+    /// signed for. The auth mode doesn't apply but blocked creators
+    /// cannot create instances. The deposit is refunded to the presenter.
+    pub fn create_instance_with_permit(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: Binary,
+        permit: CreationPermit,
+        signature: Binary
+    ) -> StdResult<Response> {
+        let Some(pubkey) = PERMIT_SIGNER.load(deps.storage)? else {
+            return Err(StdError::generic_err("Creation permits are not enabled."));
+        };
+
+        if env.block.time.seconds() > permit.expiry {
+            return Err(StdError::generic_err("The permit has expired."));
+        }
+
+        if USED_PERMIT_NONCES.load(deps.storage, &permit.nonce)?.is_some() {
+            return Err(StdError::generic_err("The permit was already used."));
+        }
+
+        let signed = to_binary(&SignedPermit {
+            factory: env.contract.address.clone(),
+            creator: permit.creator.clone(),
+            config_hash: Binary::from(sha_256(config.as_slice())),
+            expiry: permit.expiry,
+            nonce: permit.nonce
+        })?;
+
+        let valid = deps.api.secp256k1_verify(
+            &sha_256(signed.as_slice()),
+            signature.as_slice(),
+            pubkey.as_slice()
+        )?;
+
+        if !valid {
+            return Err(StdError::generic_err("Invalid permit signature."));
+        }
+
+        USED_PERMIT_NONCES.save(deps.storage, &permit.nonce, &true)?;
+
+        let creator = deps.api.addr_validate(&permit.creator)?;
+        Self::assert_not_blocked(deps.as_ref(), &creator)?;
+
+        let config: InstanceConfig<MSG> = from_binary(&config)?;
+        let refund = Self::charge_fee(deps.branch(), &info, &config.funds, 1, 1)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
+
+        // The instance funds were sent by whoever presented the permit.
+        let info = MessageInfo {
+            sender: creator,
+            funds: info.funds
+        };
+
+        let resp = Self::instantiate_child(deps, env, info, config, deposit)?;
+
+        Ok(resp.add_messages(refund))
+    }
+}
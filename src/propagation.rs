@@ -0,0 +1,123 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, Env, MessageInfo,
+        Addr, to_binary
+    },
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::SingleItem,
+    killswitch::{self, Killswitch, ContractStatus},
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, Config};
+
+/// A killswitch status being set on every registered instance.
+/// See [`Config::propagate_killswitch`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct KillswitchPropagation {
+    pub status: ContractStatus<Addr>,
+    /// The ID of the instance that the next batch starts from.
+    pub next_id: u64,
+    /// The number of instances that the status was sent to so far.
+    pub sent: u64
+}
+
+namespace!(KillswitchPropagationNs, b"killswitch_propagation");
+const PROPAGATION: SingleItem<KillswitchPropagation, KillswitchPropagationNs> = SingleItem::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Sets the killswitch status of the factory. If [`Config::propagate_killswitch`]
+    /// is set, pausing the factory or setting it to migrate also starts sending the
+    /// status to every registered instance, which is continued with
+    /// [`GenericFactory::continue_killswitch_propagation`]. Instances are not told
+    /// the new address of the factory when it migrates. Setting the factory back to
+    /// operational stops the propagation but doesn't affect the instances.
+    pub fn set_status(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        status: ContractStatus<Addr>
+    ) -> StdResult<Response> {
+        let resp = killswitch::DefaultImpl::set_status(
+            deps.branch(),
+            env,
+            info,
+            status.clone()
+        )?;
+
+        let status = match status {
+            ContractStatus::Operational => {
+                PROPAGATION.remove(deps.storage);
+
+                return Ok(resp);
+            }
+            ContractStatus::Paused { reason } =>
+                ContractStatus::Paused { reason },
+            ContractStatus::Migrating { reason, .. } =>
+                ContractStatus::Migrating { reason, new_address: None }
+        };
+
+        if Config::load(deps.storage)?.propagate_killswitch {
+            PROPAGATION.save(deps.storage, &KillswitchPropagation {
+                status,
+                next_id: 0,
+                sent: 0
+            })?;
+        }
+
+        Ok(resp)
+    }
+
+    /// Sends the propagated killswitch status to the instances with the next
+    /// `batch_size` IDs. Removed instances are skipped but count towards the batch.
+    /// The factory must be the Fadroma admin of its instances for them to accept it.
+    #[admin::require_admin]
+    pub fn continue_killswitch_propagation(
+        deps: DepsMut,
+        info: MessageInfo,
+        batch_size: u32
+    ) -> StdResult<Response> {
+        let Some(mut propagation) = PROPAGATION.load(deps.storage)? else {
+            return Err(StdError::generic_err("No killswitch propagation is in progress."));
+        };
+
+        let msg = to_binary(&killswitch::ExecuteMsg::SetStatus {
+            status: propagation.status.clone()
+        })?;
+
+        let (msgs, done) = Self::send_batch(
+            deps.as_ref(),
+            &msg,
+            &mut propagation.next_id,
+            batch_size
+        )?;
+
+        propagation.sent += msgs.len() as u64;
+
+        if done {
+            PROPAGATION.remove(deps.storage);
+        } else {
+            PROPAGATION.save(deps.storage, &propagation)?;
+        }
+
+        Ok(Response::default()
+            .add_messages(msgs)
+            .add_attribute_plaintext("killswitch_propagation_sent", propagation.sent.to_string())
+            .add_attribute_plaintext("killswitch_propagation_done", done.to_string())
+        )
+    }
+
+    #[inline]
+    pub fn killswitch_propagation(deps: Deps) -> StdResult<Option<KillswitchPropagation>> {
+        PROPAGATION.load(deps.storage)
+    }
+}
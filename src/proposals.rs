@@ -0,0 +1,306 @@
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, Env,
+        MessageInfo, Storage, Addr, CanonicalAddr, Binary, Timestamp
+    },
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{SingleItem, StaticKey, iterable::IterableStorage},
+    core::{Canonize, Humanize},
+    admin,
+    namespace
+};
+
+use crate::{
+    GenericFactory, ExtraData, FactoryHooks, Config, InstanceConfig,
+    Pagination, PaginatedResponse, Deposit
+};
+
+/// An instance that was proposed for creation with
+/// [`crate::ExecuteMsg::ProposeInstance`] and is created
+/// only once the admin approves it.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: Addr,
+    /// The config that the instance is created with.
+    /// The message is serialized.
+    pub config: InstanceConfig<Binary>,
+    pub proposed_at: Timestamp,
+    /// The time after which the proposal cannot be approved.
+    /// See [`Config::proposal_ttl`].
+    pub expires_at: Option<Timestamp>,
+    pub status: ProposalStatus
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    /// The proposal wasn't approved before it expired
+    /// and was pruned with [`crate::ExecuteMsg::PruneExpired`].
+    Expired
+}
+
+/// The proposal and expiry times are in nanoseconds since Timestamp
+/// doesn't implement the Fadroma binary serialization.
+#[derive(FadromaSerialize, FadromaDeserialize)]
+struct StoredProposal {
+    proposer: CanonicalAddr,
+    config: InstanceConfig<Binary>,
+    proposed_at: u64,
+    expires_at: Option<u64>,
+    status: ProposalStatus,
+    deposit: Option<Deposit>
+}
+
+// Proposals are never removed so that their index is their ID.
+const PROPOSALS: StaticKey = StaticKey(b"proposals");
+
+// All proposals with an ID lower than this are resolved
+// so that pruning doesn't have to go over them again.
+namespace!(UnresolvedProposalsStartNs, b"unresolved_proposals_start");
+const UNRESOLVED_START: SingleItem<u64, UnresolvedProposalsStartNs> = SingleItem::new();
+
+// The ID of the proposal that the next call to prune continues from.
+namespace!(PruneCursorNs, b"prune_expired_cursor");
+const PRUNE_CURSOR: SingleItem<u64, PruneCursorNs> = SingleItem::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Stores the config of an instance for the admin to approve
+    /// or reject. Anyone that isn't blocked can propose instances.
+    /// The deposit of [`crate::FeeConfig::deposit`] is locked until the
+    /// proposal is approved or expires and is forfeited if it's rejected.
+    /// [`FactoryHooks::before_create`] is called now as well as on approval
+    /// so that no deposit is locked for a config that would be refused.
+    pub fn propose_instance(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        config: InstanceConfig<MSG>
+    ) -> StdResult<Response> {
+        Self::assert_not_blocked(deps.as_ref(), &info.sender)?;
+
+        if !config.funds.is_empty() {
+            return Err(StdError::generic_err("Proposed instances cannot be sent funds."));
+        }
+
+        HOOKS::before_create(deps.as_ref(), &info, &config)?;
+
+        let refund = Self::charge_fee(deps.branch(), &info, &[], 0, 1)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
+
+        let ttl = Config::load(deps.storage)?.proposal_ttl;
+
+        let id = Self::proposals().push(deps.storage, &StoredProposal {
+            proposer: info.sender.as_str().canonize(deps.api)?,
+            config: InstanceConfig {
+                msg: Self::encode_msg(&config.msg)?,
+                funds: vec![],
+                code_key: config.code_key,
+                label: config.label,
+                extra: config.extra
+            },
+            proposed_at: env.block.time.nanos(),
+            expires_at: ttl.map(|ttl| env.block.time.plus_seconds(ttl).nanos()),
+            status: ProposalStatus::Pending,
+            deposit
+        })?;
+
+        Ok(Response::default()
+            .add_attribute_plaintext("proposal_id", id.to_string())
+            .add_messages(refund)
+        )
+    }
+
+    #[admin::require_admin]
+    pub fn reject_instance(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64
+    ) -> StdResult<Response> {
+        let proposal = Self::resolve_proposal(deps.storage, id, ProposalStatus::Rejected)?;
+
+        if let Some(deposit) = &proposal.deposit {
+            Self::forfeit_deposit(deps.storage, deposit)?;
+        }
+
+        Ok(Response::default())
+    }
+
+    /// Marks the pending proposals that have expired as [`ProposalStatus::Expired`]
+    /// and refunds their deposits. Can be called by anyone. At most `limit`
+    /// proposals are checked and the next call continues after them. Once
+    /// the last proposal is reached, pruning starts over.
+    pub fn prune_expired(deps: DepsMut, env: Env, limit: u32) -> StdResult<Response> {
+        if limit == 0 {
+            return Err(StdError::generic_err("Limit must be greater than zero."));
+        }
+
+        let mut proposals = Self::proposals();
+        let start = UNRESOLVED_START.load_or_default(deps.storage)?;
+        let cursor = PRUNE_CURSOR.load_or_default(deps.storage)?.max(start);
+        let len = proposals.len(deps.storage)?;
+        let end = len.min(cursor.saturating_add(limit as u64));
+
+        let mut unresolved_start = start;
+        let mut pruned = 0u64;
+        let mut refunds = vec![];
+
+        for id in cursor..end {
+            let Some(mut proposal) = proposals.get(deps.storage, id)? else {
+                continue;
+            };
+
+            if proposal.status == ProposalStatus::Pending {
+                if !proposal.is_expired(&env) {
+                    continue;
+                }
+
+                proposal.status = ProposalStatus::Expired;
+                proposals.set(deps.storage, id, &proposal)?;
+
+                if let Some(deposit) = proposal.deposit {
+                    refunds.push(Self::refund_deposit(deps.as_ref(), deposit)?);
+                }
+
+                pruned += 1;
+            }
+
+            if unresolved_start == id {
+                unresolved_start += 1;
+            }
+        }
+
+        let done = end >= len;
+
+        UNRESOLVED_START.save(deps.storage, &unresolved_start)?;
+        PRUNE_CURSOR.save(deps.storage, if done { &unresolved_start } else { &end })?;
+
+        Ok(Response::default()
+            .add_attribute_plaintext("pruned", pruned.to_string())
+            .add_attribute_plaintext("prune_done", done.to_string())
+            .add_messages(refunds)
+        )
+    }
+
+    pub fn proposal(deps: Deps, id: u64) -> StdResult<Option<Proposal>> {
+        Self::proposals()
+            .get(deps.storage, id)?
+            .map(|x| x.into_proposal(deps, id))
+            .transpose()
+    }
+
+    pub fn list_proposals(
+        deps: Deps,
+        pagination: Pagination
+    ) -> StdResult<PaginatedResponse<Proposal>> {
+        let config = Config::load(deps.storage)?;
+        let iter = Self::proposals().iter(deps.storage)?.enumerate();
+
+        pagination.paginate(
+            &config,
+            iter.map(|(id, x)| x.map(|x| (id as u64, x))),
+            |(id, x)| x.into_proposal(deps, id)
+        )
+    }
+
+    /// Marks a pending proposal as resolved with `status` and returns it.
+    fn resolve_proposal(
+        storage: &mut dyn Storage,
+        id: u64,
+        status: ProposalStatus
+    ) -> StdResult<StoredProposal> {
+        let mut proposals = Self::proposals();
+
+        let Some(mut proposal) = proposals.get(storage, id)? else {
+            return Err(StdError::generic_err(format!("Proposal {id} doesn't exist.")));
+        };
+
+        if proposal.status != ProposalStatus::Pending {
+            return Err(StdError::generic_err(format!("Proposal {id} was already resolved.")));
+        }
+
+        proposal.status = status;
+        proposals.set(storage, id, &proposal)?;
+
+        Ok(proposal)
+    }
+
+    #[inline]
+    fn proposals() -> IterableStorage<StoredProposal, StaticKey> {
+        IterableStorage::new(PROPOSALS)
+    }
+}
+
+impl<
+    MSG: Serialize + DeserializeOwned + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Creates the proposed instance on behalf of the proposer.
+    /// Expired proposals cannot be approved.
+    #[admin::require_admin]
+    pub fn approve_instance(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: u64
+    ) -> StdResult<Response> {
+        let proposal = Self::resolve_proposal(deps.storage, id, ProposalStatus::Approved)?;
+
+        if proposal.is_expired(&env) {
+            return Err(StdError::generic_err(format!("Proposal {id} has expired.")));
+        }
+
+        let refund = proposal.deposit
+            .map(|x| Self::refund_deposit(deps.as_ref(), x))
+            .transpose()?;
+
+        let info = MessageInfo {
+            sender: proposal.proposer.humanize(deps.api)?,
+            funds: vec![]
+        };
+
+        let config = InstanceConfig {
+            msg: Self::decode_msg(&proposal.config.msg)?,
+            funds: proposal.config.funds,
+            code_key: proposal.config.code_key,
+            label: proposal.config.label,
+            extra: proposal.config.extra
+        };
+
+        let resp = Self::instantiate_child(deps, env, info, config, None)?;
+
+        Ok(resp.add_messages(refund))
+    }
+}
+
+impl StoredProposal {
+    #[inline]
+    fn is_expired(&self, env: &Env) -> bool {
+        matches!(self.expires_at, Some(expires_at) if env.block.time.nanos() > expires_at)
+    }
+
+    fn into_proposal(self, deps: Deps, id: u64) -> StdResult<Proposal> {
+        Ok(Proposal {
+            id,
+            proposer: self.proposer.humanize(deps.api)?,
+            config: self.config,
+            proposed_at: Timestamp::from_nanos(self.proposed_at),
+            expires_at: self.expires_at.map(Timestamp::from_nanos),
+            status: self.status
+        })
+    }
+}
@@ -0,0 +1,85 @@
+use serde::Serialize;
+use fadroma::{
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut, MessageInfo, Coin, Binary,
+        CanonicalAddr, WasmMsg, WasmQuery, QueryRequest, Empty, SystemResult,
+        ContractResult, to_vec
+    },
+    core::Canonize,
+    admin
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks};
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Sends `msg` to a registered instance using its stored code hash.
+    /// `funds` are sent from the balance of the factory so they must be
+    /// attached to this message unless the factory already holds them.
+    #[admin::require_admin]
+    pub fn execute_on_instance(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+        msg: Binary,
+        funds: Vec<Coin>
+    ) -> StdResult<Response> {
+        let code_hash = Self::instance_code_hash(deps.as_ref(), &addr)?;
+
+        Ok(Response::default().add_message(WasmMsg::Execute {
+            contract_addr: addr,
+            code_hash,
+            msg,
+            funds
+        }))
+    }
+
+    /// Performs a smart query on a registered instance using its
+    /// stored code hash and returns the raw result.
+    pub fn query_instance(deps: Deps, addr: String, msg: Binary) -> StdResult<Binary> {
+        let code_hash = Self::instance_code_hash(deps, &addr)?;
+
+        Self::smart_query(deps, addr, code_hash, msg)
+    }
+
+    /// Performs a smart query without deserializing the result.
+    pub(crate) fn smart_query(
+        deps: Deps,
+        addr: String,
+        code_hash: String,
+        msg: Binary
+    ) -> StdResult<Binary> {
+        let request = to_vec(&QueryRequest::<Empty>::Wasm(WasmQuery::Smart {
+            contract_addr: addr,
+            code_hash,
+            msg
+        }))?;
+
+        match deps.querier.raw_query(&request) {
+            SystemResult::Err(err) => Err(StdError::generic_err(
+                format!("Querier system error: {err}")
+            )),
+            SystemResult::Ok(ContractResult::Err(err)) => Err(StdError::generic_err(
+                format!("Querier contract error: {err}")
+            )),
+            SystemResult::Ok(ContractResult::Ok(result)) => Ok(result)
+        }
+    }
+
+    fn instance_code_hash(deps: Deps, addr: &str) -> StdResult<String> {
+        let canonical: CanonicalAddr = addr.canonize(deps.api)?;
+
+        let Some(instance) = Self::instances().load(deps.storage, &canonical)? else {
+            return Err(StdError::generic_err(
+                format!("No instance with address: {addr}.")
+            ));
+        };
+
+        Ok(instance.contract.code_hash)
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{StdResult, DepsMut, Env, MessageInfo, CanonicalAddr},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{ItemSpace, TypedKey},
+    core::Canonize,
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, FactoryError, Config};
+
+/// Limits how many instances a single address can create within a
+/// sliding window. Set with [`Config::rate_limit`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+pub struct RateLimit {
+    /// The maximum number of instances created in a window.
+    pub max_creations: u32,
+    /// The length of the window in seconds.
+    pub window: u64
+}
+
+// The times in seconds of the creations of each
+// address that fall within the current window.
+namespace!(RecentCreationsNs, b"recent_creations");
+const RECENT_CREATIONS: ItemSpace<
+    Vec<u64>,
+    RecentCreationsNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Records a creation by the sender, failing with [`FactoryError::RateLimited`]
+    /// if it exceeds [`Config::rate_limit`]. The admin is not rate limited.
+    pub(crate) fn apply_rate_limit(
+        deps: DepsMut,
+        env: &Env,
+        info: &MessageInfo
+    ) -> StdResult<()> {
+        let Some(limit) = Config::load(deps.storage)?.rate_limit else {
+            return Ok(());
+        };
+
+        if admin::assert(deps.as_ref(), info).is_ok() {
+            return Ok(());
+        }
+
+        let sender = info.sender.as_str().canonize(deps.api)?;
+        let now = env.block.time.seconds();
+
+        let mut recent = RECENT_CREATIONS.load(deps.storage, &sender)?.unwrap_or_default();
+        recent.retain(|time| time + limit.window > now);
+
+        if recent.len() >= limit.max_creations as usize {
+            return Err(FactoryError::RateLimited {
+                retry_after: recent[0] + limit.window
+            }.into());
+        }
+
+        recent.push(now);
+
+        RECENT_CREATIONS.save(deps.storage, &sender, &recent)
+    }
+}
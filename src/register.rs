@@ -0,0 +1,580 @@
+use serde::Serialize;
+use fadroma::{
+    cosmwasm_std::{
+        StdResult, StdError, Response, DepsMut, Env, MessageInfo, Storage,
+        Binary, CanonicalAddr, WasmQuery, ContractInfoResponse, from_binary, to_vec
+    },
+    storage::{SingleItem, ItemSpace, TypedKey},
+    core::Canonize,
+    crypto::{sha_256, Prng},
+    admin,
+    namespace
+};
+
+use crate::{
+    GenericFactory, ExtraData, FactoryHooks, Config, PendingInstance, InstanceStatus,
+    InstanceCreation, INSTANCE_IDS, INSTANCE_ADDR_ATTR
+};
+
+/// The field of the instantiate message that the registration token is
+/// added as when [`crate::Config::require_self_registration`] is set.
+pub const REGISTRATION_TOKEN_FIELD: &str = "registration_token";
+
+// The hashes of the tokens that pending instances must register themselves with.
+namespace!(RegistrationTokensNs, b"registration_tokens");
+const REGISTRATION_TOKENS: ItemSpace<
+    Binary,
+    RegistrationTokensNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+// Registration tokens are generated from this instead of public inputs only.
+namespace!(RegistrationSeedNs, b"registration_seed");
+const REGISTRATION_SEED: SingleItem<Binary, RegistrationSeedNs> = SingleItem::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Adds a contract that wasn't created by the factory to its instances,
+    /// for factories adopted by a project that already has deployments. The
+    /// contract must run the default code or a code in the registry, which is
+    /// checked by querying its code ID from the chain. Its creator is the one
+    /// recorded on chain and it is considered created at the current block
+    /// since the actual time isn't known. The chain doesn't report the label
+    /// so it must be passed as `label`. `extra` is the JSON encoded `EXTRA`,
+    /// which is set with `set_extra` so that [`GenericFactory::execute_indexed`]
+    /// indexes it. Counts towards the same caps as creating an instance.
+    #[admin::require_admin]
+    pub fn register_existing(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        address: String,
+        label: String,
+        extra: Binary,
+        set_extra: fn(DepsMut, &CanonicalAddr, &Binary) -> StdResult<bool>
+    ) -> StdResult<Response> {
+        let address = deps.api.addr_validate(&address)?;
+        let canonical = address.as_str().canonize(deps.api)?;
+
+        if INSTANCE_IDS.load(deps.storage, &canonical)?.is_some() {
+            return Err(StdError::generic_err(
+                format!("Instance is already registered: {address}.")
+            ));
+        }
+
+        let contract_info: ContractInfoResponse = deps.querier.query(
+            &WasmQuery::ContractInfo { contract_addr: address.to_string() }.into()
+        )?;
+
+        let Some(code) = Self::find_code_by_id(deps.as_ref(), contract_info.code_id)? else {
+            return Err(StdError::generic_err(format!(
+                "Contract {address} doesn't run a code known to the factory."
+            )));
+        };
+
+        let creator = contract_info.creator.as_str().canonize(deps.api)?;
+        let settings = Config::load(deps.storage)?;
+
+        Self::assert_below_caps(deps.as_ref(), &settings, &creator)?;
+        Self::validate_label(deps.as_ref(), &label)?;
+
+        let pending = PendingInstance {
+            code,
+            label,
+            funds: vec![],
+            created: InstanceCreation {
+                creator,
+                block_height: env.block.height,
+                block_time: env.block.time
+            },
+            init: None,
+            seq: None,
+            deposit: None,
+            extra: None,
+            config: None,
+            callback: None,
+            registration: None
+        };
+
+        Self::register_instance(deps.branch(), canonical.clone(), pending, from_binary(&extra)?)?;
+        set_extra(deps, &canonical, &extra)?;
+
+        Ok(Response::default().add_attribute_plaintext(INSTANCE_ADDR_ATTR, address))
+    }
+
+    /// Updates the stored code of an instance to the one it runs on chain,
+    /// for instances that were migrated by their admin. The new code must
+    /// be the default code or a code in the registry since the chain only
+    /// reports the code ID. Anyone can call this as it only brings the
+    /// stored code in line with the chain.
+    pub fn sync_instance_code_hash(deps: DepsMut, addr: String) -> StdResult<Response> {
+        let canonical = addr.as_str().canonize(deps.api)?;
+
+        let instances = Self::instances();
+        let Some(mut instance) = instances.load(deps.storage, &canonical)? else {
+            return Err(StdError::generic_err(
+                format!("No instance with address: {addr}.")
+            ));
+        };
+
+        let contract_info: ContractInfoResponse = deps.querier.query(
+            &WasmQuery::ContractInfo { contract_addr: addr.clone() }.into()
+        )?;
+
+        if contract_info.code_id == instance.code_id {
+            return Ok(Response::default());
+        }
+
+        let Some(code) = Self::find_code_by_id(deps.as_ref(), contract_info.code_id)? else {
+            return Err(StdError::generic_err(format!(
+                "Contract {addr} doesn't run a code known to the factory."
+            )));
+        };
+
+        Self::code_instances(&instance.code_id).remove(deps.storage, &canonical)?;
+        Self::code_instances(&code.id).push(deps.storage, &canonical)?;
+        Self::code_hash_instances(&instance.contract.code_hash).remove(deps.storage, &canonical)?;
+        Self::code_hash_instances(&code.code_hash).push(deps.storage, &canonical)?;
+
+        instance.code_id = code.id;
+        instance.contract.code_hash = code.code_hash;
+        instances.save(deps.storage, &canonical, &instance)?;
+
+        Ok(Response::default())
+    }
+
+    /// Activates the pending instance that sends this with the token that
+    /// it was given in its instantiate message. Each token can only be used once.
+    pub fn register_self(
+        deps: DepsMut,
+        info: MessageInfo,
+        token: Binary
+    ) -> StdResult<Response> {
+        let canonical = info.sender.as_str().canonize(deps.api)?;
+
+        let Some(hash) = REGISTRATION_TOKENS.load(deps.storage, &canonical)? else {
+            return Err(StdError::generic_err(format!(
+                "Instance {} isn't awaiting registration.",
+                info.sender
+            )));
+        };
+
+        if hash != token_hash(&token) {
+            return Err(StdError::generic_err("Invalid registration token."));
+        }
+
+        REGISTRATION_TOKENS.remove(deps.storage, &canonical);
+
+        let instances = Self::instances();
+
+        if let Some(mut instance) = instances.load(deps.storage, &canonical)? {
+            // The admin may have changed the status in the meantime.
+            if instance.status == InstanceStatus::Pending {
+                Self::status_ids(InstanceStatus::Pending).remove(deps.storage, instance.id)?;
+                Self::status_ids(InstanceStatus::Active).insert(deps.storage, instance.id)?;
+
+                instance.status = InstanceStatus::Active;
+                instances.save(deps.storage, &canonical, &instance)?;
+            }
+        }
+
+        Ok(Response::default().add_attribute_plaintext(INSTANCE_ADDR_ATTR, info.sender))
+    }
+}
+
+/// Generates the registration token of the instance created with `seq` from
+/// a seed that is only kept in the state of the factory. The seed is replaced
+/// every time and mixes in the instantiate message of each instance, which is
+/// encrypted on chain, so tokens can't be derived from public data.
+pub(crate) fn registration_token(
+    storage: &mut dyn Storage,
+    env: &Env,
+    creator: &CanonicalAddr,
+    seq: u64,
+    msg: &Binary
+) -> StdResult<Binary> {
+    let seed = REGISTRATION_SEED.load_or_default(storage)?;
+
+    let entropy = [
+        env.contract.address.as_bytes(),
+        creator.as_slice(),
+        &seq.to_be_bytes(),
+        &env.block.time.nanos().to_be_bytes(),
+        msg.as_slice()
+    ].concat();
+
+    let mut rng = Prng::new(seed.as_slice(), &entropy);
+    let token = rng.rand_bytes();
+
+    REGISTRATION_SEED.save(storage, &Binary::from(rng.rand_bytes().as_slice()))?;
+
+    Ok(Binary::from(token.as_slice()))
+}
+
+/// Makes the instance at `addr` await registration with the token hashed to `hash`.
+#[inline]
+pub(crate) fn await_registration(
+    storage: &mut dyn Storage,
+    addr: &CanonicalAddr,
+    hash: &Binary
+) -> StdResult<()> {
+    REGISTRATION_TOKENS.save(storage, addr, hash)
+}
+
+#[inline]
+pub(crate) fn remove_registration(storage: &mut dyn Storage, addr: &CanonicalAddr) {
+    REGISTRATION_TOKENS.remove(storage, addr);
+}
+
+/// Only the hash of a registration token is stored.
+#[inline]
+pub(crate) fn token_hash(token: &Binary) -> Binary {
+    Binary::from(sha_256(token.as_slice()).as_slice())
+}
+
+/// Adds `token` to `msg` as the [`REGISTRATION_TOKEN_FIELD`],
+/// which requires `msg` to be a JSON object.
+pub(crate) fn with_registration_token(msg: &Binary, token: &Binary) -> StdResult<Binary> {
+    let bytes = msg.as_slice();
+
+    let Some(start) = bytes.iter().position(|x| !x.is_ascii_whitespace()) else {
+        return Err(not_an_object());
+    };
+
+    if bytes[start] != b'{' {
+        return Err(not_an_object());
+    }
+
+    let empty = bytes[start + 1..].iter()
+        .find(|x| !x.is_ascii_whitespace())
+        .map(|x| *x == b'}')
+        .ok_or_else(not_an_object)?;
+
+    let mut result = Vec::with_capacity(bytes.len() + 64);
+    result.extend_from_slice(&bytes[..=start]);
+    result.extend_from_slice(&to_vec(REGISTRATION_TOKEN_FIELD)?);
+    result.push(b':');
+    result.extend_from_slice(&to_vec(token)?);
+
+    if !empty {
+        result.push(b',');
+    }
+
+    result.extend_from_slice(&bytes[start + 1..]);
+
+    Ok(result.into())
+}
+
+#[inline]
+fn not_an_object() -> StdError {
+    StdError::generic_err(
+        "The instantiate message must be a JSON object to add the registration token to."
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fadroma::{
+        cosmwasm_std::{
+            Empty, SystemResult, ContractResult, OwnedDeps, to_binary,
+            testing::{
+                MockStorage, MockApi, MockQuerier,
+                mock_dependencies, mock_env, mock_info
+            }
+        },
+        core::ContractCode
+    };
+    use crate::{InstantiateMsg, InstanceFilter, Pagination, Config, FactoryError};
+
+    type Factory = GenericFactory<Binary, Empty, false>;
+
+    #[test]
+    fn existing_contracts_can_be_registered() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("admin", &[]);
+
+        Factory::instantiate(deps.as_mut(), mock_env(), info.clone(), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        deps.querier.update_wasm(|query| {
+            let WasmQuery::ContractInfo { contract_addr } = query else {
+                panic!("Expecting a contract info query.");
+            };
+
+            let code_id = if contract_addr == "unknown" { 2 } else { 1 };
+            let resp = ContractInfoResponse::new(code_id, "deployer");
+
+            SystemResult::Ok(ContractResult::Ok(to_binary(&resp).unwrap()))
+        });
+
+        let extra = to_binary(&Empty { }).unwrap();
+
+        let err = Factory::register_existing(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            "existing".into(),
+            "existing".into(),
+            extra.clone(),
+            Factory::set_instance_extra
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::generic_err("Unauthorized"));
+
+        let err = Factory::register_existing(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            "unknown".into(),
+            "unknown".into(),
+            extra.clone(),
+            Factory::set_instance_extra
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::generic_err(
+            "Contract unknown doesn't run a code known to the factory."
+        ));
+
+        Factory::register_existing(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            "existing".into(),
+            "existing".into(),
+            extra.clone(),
+            Factory::set_instance_extra
+        ).unwrap();
+
+        let instance = Factory::instance_by_addr(deps.as_ref(), "existing".into())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(instance.id, 0);
+        assert_eq!(instance.contract.code_hash, "child");
+        assert_eq!(instance.created.creator.as_str(), "deployer");
+
+        let err = Factory::register_existing(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            "existing".into(),
+            "existing".into(),
+            extra.clone(),
+            Factory::set_instance_extra
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::generic_err("Instance is already registered: existing."));
+
+        let instance = Factory::instance_by_label(deps.as_ref(), "existing".into())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(instance.contract.address.as_str(), "existing");
+
+        let err = Factory::register_existing(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            "other".into(),
+            "existing".into(),
+            extra.clone(),
+            Factory::set_instance_extra
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::generic_err("Label \"existing\" is already taken."));
+
+        // Counts towards the caps of the factory.
+        Factory::update_config(deps.as_mut(), mock_env(), info.clone(), Config {
+            max_instances_per_creator: Some(1),
+            ..Config::default()
+        }).unwrap();
+
+        let err = Factory::register_existing(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            "other".into(),
+            "other".into(),
+            extra,
+            Factory::set_instance_extra
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::from(FactoryError::CreatorCapReached { max: 1 }));
+    }
+
+    #[test]
+    fn code_hash_is_synced_with_chain() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("admin", &[]);
+
+        Factory::instantiate(deps.as_mut(), mock_env(), info.clone(), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        Factory::register_code(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            "v2".into(),
+            ContractCode { id: 2, code_hash: "child_v2".into() },
+            None
+        ).unwrap();
+
+        fn update_code_id(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, code_id: u64) {
+            deps.querier.update_wasm(move |_| {
+                let resp = ContractInfoResponse::new(code_id, "deployer");
+
+                SystemResult::Ok(ContractResult::Ok(to_binary(&resp).unwrap()))
+            });
+        }
+
+        update_code_id(&mut deps, 1);
+
+        Factory::register_existing(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            "existing".into(),
+            "existing".into(),
+            to_binary(&Empty { }).unwrap(),
+            Factory::set_instance_extra
+        ).unwrap();
+
+        update_code_id(&mut deps, 3);
+
+        let err = Factory::sync_instance_code_hash(deps.as_mut(), "existing".into()).unwrap_err();
+        assert_eq!(err, StdError::generic_err(
+            "Contract existing doesn't run a code known to the factory."
+        ));
+
+        update_code_id(&mut deps, 2);
+        Factory::sync_instance_code_hash(deps.as_mut(), "existing".into()).unwrap();
+
+        let instance = Factory::instance_by_addr(deps.as_ref(), "existing".into())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(instance.code_id, 2);
+        assert_eq!(instance.contract.code_hash, "child_v2");
+
+        let by_code = Factory::instances_by_code_id(deps.as_ref(), 2, Pagination::new(0, 10))
+            .unwrap();
+
+        assert_eq!(by_code.total, 1);
+
+        for (hash, total) in [("child", 0), ("child_v2", 1)] {
+            let by_hash = Factory::list_instances(
+                deps.as_ref(),
+                Pagination::new(0, 10),
+                None,
+                Some(InstanceFilter { code_hash: Some(hash.into()), ..InstanceFilter::default() })
+            ).unwrap();
+
+            assert_eq!(by_hash.total, total);
+        }
+    }
+
+    #[test]
+    fn code_hash_can_have_several_code_ids() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("admin", &[]);
+
+        Factory::instantiate(deps.as_mut(), mock_env(), info.clone(), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        // The same code stored again.
+        Factory::register_code(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            "copy".into(),
+            ContractCode { id: 2, code_hash: "child".into() },
+            None
+        ).unwrap();
+
+        deps.querier.update_wasm(|query| {
+            let WasmQuery::ContractInfo { contract_addr } = query else {
+                panic!("Expecting a contract info query.");
+            };
+
+            let code_id = if contract_addr == "original" { 1 } else { 2 };
+            let resp = ContractInfoResponse::new(code_id, "deployer");
+
+            SystemResult::Ok(ContractResult::Ok(to_binary(&resp).unwrap()))
+        });
+
+        for addr in ["original", "copy"] {
+            Factory::register_existing(
+                deps.as_mut(),
+                mock_env(),
+                info.clone(),
+                addr.into(),
+                addr.into(),
+                to_binary(&Empty { }).unwrap(),
+                Factory::set_instance_extra
+            ).unwrap();
+        }
+
+        let list = |code_id: Option<u64>, code_hash: &str| Factory::list_instances(
+            deps.as_ref(),
+            Pagination::new(0, 10),
+            None,
+            Some(InstanceFilter {
+                code_id,
+                code_hash: Some(code_hash.into()),
+                status: None
+            })
+        ).unwrap().entries.into_iter().map(|x| x.contract.address.into_string()).collect::<Vec<_>>();
+
+        assert_eq!(list(None, "child"), ["original", "copy"]);
+        assert_eq!(list(Some(2), "child"), ["copy"]);
+        assert!(list(Some(2), "other").is_empty());
+    }
+
+    #[test]
+    fn registration_tokens_are_not_repeated() {
+        let mut storage = MockStorage::new();
+        let creator = CanonicalAddr::from(b"creator".as_slice());
+        let msg = Binary::from(b"{}");
+
+        let first = registration_token(&mut storage, &mock_env(), &creator, 0, &msg).unwrap();
+        let second = registration_token(&mut storage, &mock_env(), &creator, 0, &msg).unwrap();
+
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn registration_token_is_added_to_msg() {
+        let token = Binary::from(b"token");
+
+        for (msg, expected) in [
+            (r#"{"text":"a"}"#, r#"{"registration_token":"dG9rZW4=","text":"a"}"#),
+            (r#" { } "#, r#" {"registration_token":"dG9rZW4=" } "#)
+        ] {
+            let msg = with_registration_token(&Binary::from(msg.as_bytes()), &token).unwrap();
+
+            assert_eq!(String::from_utf8(msg.0).unwrap(), expected);
+        }
+
+        for msg in [r#""text""#, "", "{"] {
+            with_registration_token(&Binary::from(msg.as_bytes()), &token).unwrap_err();
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Helpers for routing submessage replies when the factory is
+//! embedded into a contract that dispatches its own submessages.
+
+use serde::Serialize;
+use fadroma::cosmwasm_std::{StdResult, StdError, Event, Binary};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, REPLY_ID};
+
+/// The number of reply IDs reserved for the factory, starting from [`REPLY_ID`].
+pub const FACTORY_REPLY_IDS_LEN: u64 = 1 << 16;
+
+/// The reply IDs reserved for a factory using the default [`REPLY_ID`].
+/// Embedding contracts should not use IDs from this range for their own
+/// submessages. Use [`ReplyIdRange::after`] to allocate a non-overlapping
+/// range. See [`GenericFactory::REPLY_IDS`] if the factory uses another ID.
+pub const FACTORY_REPLY_IDS: ReplyIdRange = ReplyIdRange::new(REPLY_ID, FACTORY_REPLY_IDS_LEN);
+
+/// A contiguous range of submessage reply IDs.
+///
+/// # Examples
+///
+/// ```
+/// use generic_factory::reply::{ReplyIdRange, FACTORY_REPLY_IDS};
+///
+/// // Reserve 10 IDs for our own submessages right after the factory ones.
+/// const OWN_IDS: ReplyIdRange = FACTORY_REPLY_IDS.after(10);
+///
+/// let id = OWN_IDS.id(3).unwrap();
+///
+/// assert!(!FACTORY_REPLY_IDS.contains(id));
+/// assert!(!OWN_IDS.overlaps(&FACTORY_REPLY_IDS));
+/// assert_eq!(OWN_IDS.offset(id), Some(3));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReplyIdRange {
+    start: u64,
+    size: u64
+}
+
+impl ReplyIdRange {
+    /// Creates a range of `size` IDs starting from `start`.
+    /// Panics if the range overflows `u64` or is empty.
+    #[inline]
+    pub const fn new(start: u64, size: u64) -> Self {
+        assert!(size > 0, "Reply ID range cannot be empty.");
+        assert!(start.checked_add(size).is_some(), "Reply ID range overflows.");
+
+        Self { start, size }
+    }
+
+    /// Creates a range of `size` IDs immediately following this one.
+    #[inline]
+    pub const fn after(&self, size: u64) -> Self {
+        Self::new(self.end(), size)
+    }
+
+    /// The first ID in the range.
+    #[inline]
+    pub const fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// The number of IDs in the range.
+    #[inline]
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The first ID after the range (exclusive).
+    #[inline]
+    pub const fn end(&self) -> u64 {
+        self.start + self.size
+    }
+
+    #[inline]
+    pub const fn contains(&self, id: u64) -> bool {
+        id >= self.start && id < self.end()
+    }
+
+    #[inline]
+    pub const fn overlaps(&self, other: &Self) -> bool {
+        self.start < other.end() && other.start < self.end()
+    }
+
+    /// Returns the ID at the given offset from the start of the range.
+    pub fn id(&self, offset: u64) -> StdResult<u64> {
+        if offset >= self.size {
+            return Err(StdError::generic_err(format!(
+                "Offset {} is outside of the reply ID range {}..{}.",
+                offset,
+                self.start,
+                self.end()
+            )));
+        }
+
+        Ok(self.start + offset)
+    }
+
+    /// Returns the offset of the given ID from the start
+    /// of the range or [`None`] if it is not in the range.
+    #[inline]
+    pub fn offset(&self, id: u64) -> Option<u64> {
+        self.contains(id).then(|| id - self.start)
+    }
+}
+
+impl<
+    MSG: Serialize,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// The reply IDs reserved for the factory, starting from its `REPLY`
+    /// parameter. Pick a `REPLY` such that this range doesn't overlap with
+    /// the IDs used by the rest of the contract that embeds the factory.
+    pub const REPLY_IDS: ReplyIdRange = ReplyIdRange::new(REPLY, FACTORY_REPLY_IDS_LEN);
+}
+
+/// The fields of a protobuf encoded `MsgInstantiateContractResponse`,
+/// which is the reply data of an instantiation on some chains.
+pub(crate) struct InstantiateResponse {
+    pub address: String,
+    /// The data set by the instantiated contract.
+    pub data: Option<Binary>
+}
+
+/// Decodes a `MsgInstantiateContractResponse`. Returns [`None`]
+/// if `data` is not one, for example if it's JSON.
+pub(crate) fn parse_instantiate_response(mut data: &[u8]) -> Option<InstantiateResponse> {
+    let mut address = None;
+    let mut contract_data = None;
+
+    while !data.is_empty() {
+        let key = read_varint(&mut data)?;
+
+        match key & 0b111 {
+            // Varint
+            0 => { read_varint(&mut data)?; }
+            // 64 bit
+            1 => data = data.get(8..)?,
+            // Length delimited
+            2 => {
+                let len = usize::try_from(read_varint(&mut data)?).ok()?;
+                let value = data.get(..len)?;
+                data = &data[len..];
+
+                match key >> 3 {
+                    1 => address = Some(String::from_utf8(value.to_vec()).ok()?),
+                    2 => contract_data = Some(Binary::from(value)),
+                    _ => { }
+                }
+            }
+            // 32 bit
+            5 => data = data.get(4..)?,
+            _ => return None
+        }
+    }
+
+    Some(InstantiateResponse {
+        address: address?,
+        data: contract_data.filter(|x| !x.is_empty())
+    })
+}
+
+/// Finds the address of the instantiated contract in the
+/// standard `instantiate` event emitted by the chain.
+pub(crate) fn instantiated_address(events: &[Event]) -> Option<String> {
+    events.iter()
+        .filter(|x| x.ty == "instantiate")
+        .flat_map(|x| x.attributes.iter())
+        .find(|x| x.key == "_contract_address" || x.key == "contract_address")
+        .map(|x| x.value.clone())
+}
+
+/// The label of the new contract as reported by the `instantiate`
+/// event, if the chain includes it.
+pub(crate) fn instantiated_label(events: &[Event]) -> Option<String> {
+    events.iter()
+        .filter(|x| x.ty == "instantiate")
+        .flat_map(|x| x.attributes.iter())
+        .find(|x| x.key == "label")
+        .map(|x| x.value.clone())
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut result = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data.split_first()?;
+        *data = rest;
+
+        result |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fadroma::{
+        cosmwasm_std::{
+            Reply, SubMsgResult, SubMsgResponse, Addr, Empty, to_binary,
+            testing::{mock_dependencies, mock_env, mock_info}
+        },
+        core::ContractCode
+    };
+    use crate::{
+        FactoryError, Config, InstantiateMsg, InstanceConfig, InstantiateReplyData, REPLY_ID
+    };
+
+    type Factory = GenericFactory<Binary, Empty, false>;
+
+    #[test]
+    fn instantiate_response_is_parsed() {
+        let mut data = vec![0x0a, 4];
+        data.extend_from_slice(b"addr");
+        data.extend_from_slice(&[0x12, 2, b'{', b'}']);
+
+        let resp = parse_instantiate_response(&data).unwrap();
+        assert_eq!(resp.address, "addr");
+        assert_eq!(resp.data, Some(Binary::from(b"{}")));
+
+        assert!(parse_instantiate_response(b"{\"address\":\"addr\"}").is_none());
+        assert!(parse_instantiate_response(&[0x0a, 5, b'a']).is_none());
+    }
+
+    #[test]
+    fn unsolicited_replies_are_rejected() {
+        let mut deps = mock_dependencies();
+
+        Factory::instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        let reply = |id| Reply {
+            id,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![
+                    Event::new("instantiate").add_attribute("contract_address", "spoofed")
+                ],
+                data: Some(to_binary(&InstantiateReplyData::new(
+                    Addr::unchecked("spoofed")
+                )).unwrap())
+            })
+        };
+
+        let err = Factory::reply(deps.as_mut(), mock_env(), reply(REPLY_ID)).unwrap_err();
+        assert_eq!(err, FactoryError::UnsolicitedReply { id: REPLY_ID }.into());
+
+        let resp = Factory::create_instance(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            InstanceConfig {
+                msg: Binary::default(),
+                funds: vec![],
+                code_key: None,
+                label: None,
+                extra: None
+            }
+        ).unwrap();
+
+        let id = resp.messages[0].id;
+
+        let err = Factory::reply(deps.as_mut(), mock_env(), reply(id + 1)).unwrap_err();
+        assert_eq!(err, FactoryError::UnsolicitedReply { id: id + 1 }.into());
+
+        Factory::reply(deps.as_mut(), mock_env(), reply(id)).unwrap();
+
+        let err = Factory::reply(deps.as_mut(), mock_env(), reply(id)).unwrap_err();
+        assert_eq!(err, FactoryError::UnsolicitedReply { id }.into());
+    }
+
+    #[test]
+    fn unverified_addresses_are_rejected() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("admin", &[]);
+
+        Factory::instantiate(deps.as_mut(), mock_env(), info.clone(), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        let config = InstanceConfig {
+            msg: Binary::default(),
+            funds: vec![],
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        // Only the child says where it is.
+        let resp = || SubMsgResponse {
+            events: vec![],
+            data: Some(to_binary(&InstantiateReplyData::new(
+                Addr::unchecked("child")
+            )).unwrap())
+        };
+
+        let created = Factory::create_instance(deps.as_mut(), mock_env(), info.clone(), config.clone())
+            .unwrap();
+
+        let err = Factory::handle_reply(deps.as_mut(), created.messages[0].id, resp()).unwrap_err();
+        assert_eq!(err, FactoryError::UnverifiedAddress { claimed: "child".into() }.into());
+
+        Factory::update_config(deps.as_mut(), mock_env(), info.clone(), Config {
+            allow_unverified_addresses: true,
+            ..Config::default()
+        }).unwrap();
+
+        let created = Factory::create_instance(deps.as_mut(), mock_env(), info, config).unwrap();
+        let instance = Factory::handle_reply(deps.as_mut(), created.messages[0].id, resp()).unwrap();
+
+        assert_eq!(instance.contract.address, "child");
+    }
+
+    #[test]
+    fn label_reported_by_chain_is_stored() {
+        let mut deps = mock_dependencies();
+
+        Factory::instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        let resp = Factory::create_instance(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            InstanceConfig {
+                msg: Binary::default(),
+                funds: vec![],
+                code_key: None,
+                label: Some("Requested Label".into()),
+                extra: None
+            }
+        ).unwrap();
+
+        let instance = Factory::handle_reply(deps.as_mut(), resp.messages[0].id, SubMsgResponse {
+            events: vec![
+                Event::new("instantiate")
+                    .add_attribute("contract_address", "child")
+                    .add_attribute("label", "requested-label")
+            ],
+            data: None
+        }).unwrap();
+
+        assert_eq!(instance.label, "requested-label");
+
+        let by_label = Factory::instance_by_label(
+            deps.as_ref(),
+            "requested-label".into()
+        ).unwrap();
+
+        assert_eq!(by_label.unwrap().contract.address, "child");
+    }
+
+    #[test]
+    fn reply_id_range() {
+        let range = ReplyIdRange::new(10, 5);
+
+        assert_eq!(range.end(), 15);
+        assert!(range.contains(10));
+        assert!(range.contains(14));
+        assert!(!range.contains(15));
+        assert!(!range.contains(9));
+
+        assert_eq!(range.id(0).unwrap(), 10);
+        assert_eq!(range.id(4).unwrap(), 14);
+        range.id(5).unwrap_err();
+
+        assert_eq!(range.offset(12), Some(2));
+        assert_eq!(range.offset(15), None);
+
+        let next = range.after(3);
+        assert_eq!(next.start(), 15);
+        assert!(!next.overlaps(&range));
+        assert!(ReplyIdRange::new(14, 2).overlaps(&range));
+        assert!(ReplyIdRange::new(0, 100).overlaps(&range));
+
+        assert!(FACTORY_REPLY_IDS.contains(REPLY_ID));
+    }
+}
@@ -0,0 +1,177 @@
+use serde::Serialize;
+use fadroma::{
+    cosmwasm_std::{StdResult, Response, DepsMut, Env, MessageInfo, Binary, Coin}
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, InstanceConfig};
+
+/// Implement this on the `MSG` type of a factory that creates several different
+/// types of child contracts, each with its own instantiate message. Usually
+/// `MSG` is an enum with a variant per child type and each variant maps to the
+/// key of its code in the code registry. Call [`GenericFactory::create_slot_instance`]
+/// from your contract's execute function to create instances of it.
+///
+/// # Examples
+///
+/// ```
+/// use generic_factory::CodeSlot;
+/// use fadroma::cosmwasm_std::{StdResult, Binary, to_binary};
+/// # #[derive(serde::Serialize)] struct RouterInit { }
+/// # #[derive(serde::Serialize)] struct PairInit { }
+///
+/// #[derive(serde::Serialize)]
+/// enum ChildMsg {
+///     Router(RouterInit),
+///     Pair(PairInit)
+/// }
+///
+/// impl CodeSlot for ChildMsg {
+///     fn code_key(&self) -> &str {
+///         match self {
+///             Self::Router(_) => "router",
+///             Self::Pair(_) => "pair"
+///         }
+///     }
+///
+///     fn to_child_msg(&self) -> StdResult<Binary> {
+///         match self {
+///             Self::Router(msg) => to_binary(msg),
+///             Self::Pair(msg) => to_binary(msg)
+///         }
+///     }
+/// }
+/// ```
+pub trait CodeSlot {
+    /// The key in the code registry of the code to instantiate.
+    fn code_key(&self) -> &str;
+    /// The instantiate message to send to the child.
+    fn to_child_msg(&self) -> StdResult<Binary>;
+}
+
+impl<
+    MSG: Serialize + CodeSlot + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Creates an instance from the code registered under the
+    /// key of the `msg` slot, passing it the slot's message.
+    pub fn create_slot_instance(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: MSG,
+        funds: Vec<Coin>
+    ) -> StdResult<Response> {
+        Self::assert_can_create(deps.as_ref(), &info)?;
+        Self::apply_rate_limit(deps.branch(), &env, &info)?;
+        let refund = Self::charge_fee(deps.branch(), &info, &funds, 1, 1)?;
+        let deposit = Self::deposit_of(deps.as_ref(), &info)?;
+
+        let config = InstanceConfig {
+            code_key: Some(msg.code_key().to_string()),
+            msg,
+            funds,
+            label: None,
+            extra: None
+        };
+
+        // The hooks check the slot itself since the child message isn't an `MSG`.
+        HOOKS::before_create(deps.as_ref(), &info, &config)?;
+
+        let resp = Self::dispatch_child(deps, env, info, InstanceConfig {
+            msg: config.msg.to_child_msg()?,
+            funds: config.funds,
+            code_key: config.code_key,
+            label: None,
+            extra: None
+        }, true, deposit)?;
+
+        Ok(resp.add_messages(refund))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fadroma::{
+        cosmwasm_std::{
+            CosmosMsg, WasmMsg, Empty, to_binary,
+            testing::{mock_dependencies, mock_env, mock_info}
+        },
+        core::ContractCode
+    };
+    use crate::InstantiateMsg;
+
+    #[derive(Serialize)]
+    enum ChildMsg {
+        Router { fee: u8 },
+        Pair { token: String }
+    }
+
+    impl CodeSlot for ChildMsg {
+        fn code_key(&self) -> &str {
+            match self {
+                Self::Router { .. } => "router",
+                Self::Pair { .. } => "pair"
+            }
+        }
+
+        fn to_child_msg(&self) -> StdResult<Binary> {
+            match self {
+                Self::Router { fee } => to_binary(fee),
+                Self::Pair { token } => to_binary(token)
+            }
+        }
+    }
+
+    type Factory = GenericFactory<ChildMsg, Empty, false>;
+
+    #[test]
+    fn slot_selects_code_and_message() {
+        let mut deps = mock_dependencies();
+        let info = mock_info("admin", &[]);
+
+        Factory::instantiate(deps.as_mut(), mock_env(), info.clone(), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "default".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        for (key, id) in [("router", 2), ("pair", 3)] {
+            Factory::register_code(
+                deps.as_mut(),
+                mock_env(),
+                info.clone(),
+                key.into(),
+                ContractCode { id, code_hash: key.into() },
+                None
+            ).unwrap();
+        }
+
+        let slots = [
+            (ChildMsg::Router { fee: 3 }, 2, to_binary(&3u8).unwrap()),
+            (ChildMsg::Pair { token: "sSCRT".into() }, 3, to_binary("sSCRT").unwrap())
+        ];
+
+        for (slot, expected_id, expected_msg) in slots {
+            let resp = Factory::create_slot_instance(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("anyone", &[]),
+                slot,
+                vec![]
+            ).unwrap();
+
+            let CosmosMsg::Wasm(WasmMsg::Instantiate { code_id, msg, .. }) =
+                &resp.messages[0].msg else {
+                panic!("Expecting an instantiate message.");
+            };
+
+            assert_eq!(*code_id, expected_id);
+            assert_eq!(*msg, expected_msg);
+        }
+    }
+}
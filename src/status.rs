@@ -0,0 +1,88 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{StdResult, StdError, Response, DepsMut, MessageInfo},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    core::Canonize,
+    admin
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, INSTANCE_IDS, id_list::IdList};
+
+/// The lifecycle stage of an instance. Only active instances are
+/// listed by [`crate::QueryMsg::ListInstances`] unless another
+/// status is requested in its filter.
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceStatus {
+    #[default]
+    Active,
+    Archived,
+    Retired,
+    /// Awaiting [`crate::ExecuteMsg::RegisterSelf`] from the instance.
+    /// See [`crate::Config::require_self_registration`].
+    Pending
+}
+
+impl InstanceStatus {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Archived => "archived",
+            Self::Retired => "retired",
+            Self::Pending => "pending"
+        }
+    }
+}
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Changes the status of an instance. The instance itself is not
+    /// affected. Instances can't be set to [`InstanceStatus::Pending`]
+    /// since only the factory makes them await registration.
+    #[admin::require_admin]
+    pub fn set_instance_status(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+        status: InstanceStatus
+    ) -> StdResult<Response> {
+        if status == InstanceStatus::Pending {
+            return Err(StdError::generic_err("Instances can't be set to pending."));
+        }
+
+        let canonical = addr.as_str().canonize(deps.api)?;
+
+        let instances = Self::instances();
+        let Some(mut instance) = instances.load(deps.storage, &canonical)? else {
+            return Err(StdError::generic_err(
+                format!("No instance with address: {addr}.")
+            ));
+        };
+
+        if instance.status == status {
+            return Ok(Response::default());
+        }
+
+        let id = INSTANCE_IDS.load_or_error(deps.storage, &canonical)?;
+        Self::status_ids(instance.status).remove(deps.storage, id)?;
+        Self::status_ids(status).insert(deps.storage, id)?;
+
+        instance.status = status;
+        instances.save(deps.storage, &canonical, &instance)?;
+
+        Ok(Response::default())
+    }
+
+    /// The IDs of all instances with the given status.
+    #[inline]
+    pub(crate) fn status_ids(status: InstanceStatus) -> IdList {
+        IdList::new(&[b"status_ids", status.as_str().as_bytes()])
+    }
+}
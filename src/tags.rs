@@ -0,0 +1,135 @@
+use serde::Serialize;
+use fadroma::{
+    cosmwasm_std::{
+        StdResult, StdError, Response, Deps, DepsMut,
+        MessageInfo, Storage, Addr, CanonicalAddr
+    },
+    storage::{ItemSpace, TypedKey},
+    core::Canonize,
+    admin,
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, Instance, Pagination, PaginatedResponse, AddrList};
+
+namespace!(InstanceTagsNs, b"instance_tags");
+const INSTANCE_TAGS: ItemSpace<
+    Vec<String>,
+    InstanceTagsNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Tags the instance with `tag`. Can be called by the admin
+    /// or by the address that created the instance.
+    pub fn tag_instance(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+        tag: String
+    ) -> StdResult<Response> {
+        if tag.is_empty() {
+            return Err(StdError::generic_err("Tag cannot be empty."));
+        }
+
+        let addr = Self::assert_can_tag(deps.as_ref(), &info, addr)?;
+
+        let mut tags = INSTANCE_TAGS.load_or_default(deps.storage, &addr)?;
+
+        if tags.contains(&tag) {
+            return Err(StdError::generic_err(
+                format!("Instance is already tagged with \"{tag}\".")
+            ));
+        }
+
+        Self::tagged_instances(&tag).push(deps.storage, &addr)?;
+
+        tags.push(tag);
+        INSTANCE_TAGS.save(deps.storage, &addr, &tags)?;
+
+        Ok(Response::default())
+    }
+
+    /// Removes `tag` from the instance. Can be called by the admin
+    /// or by the address that created the instance.
+    pub fn untag_instance(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+        tag: String
+    ) -> StdResult<Response> {
+        let addr = Self::assert_can_tag(deps.as_ref(), &info, addr)?;
+
+        let mut tags = INSTANCE_TAGS.load_or_default(deps.storage, &addr)?;
+        let Some(pos) = tags.iter().position(|x| *x == tag) else {
+            return Err(StdError::generic_err(
+                format!("Instance isn't tagged with \"{tag}\".")
+            ));
+        };
+
+        tags.swap_remove(pos);
+        INSTANCE_TAGS.save(deps.storage, &addr, &tags)?;
+
+        Self::tagged_instances(&tag).remove(deps.storage, &addr)?;
+
+        Ok(Response::default())
+    }
+
+    /// Lists the instances tagged with `tag`. Untagging an instance
+    /// moves the most recently tagged one into its place.
+    pub fn instances_by_tag(deps: Deps, tag: String, pagination: Pagination) ->
+        StdResult<PaginatedResponse<Instance<Addr, EXTRA>>>
+    {
+        Self::paginate_index(deps, Self::tagged_instances(&tag), pagination)
+    }
+
+    pub fn instance_tags(deps: Deps, addr: String) -> StdResult<Vec<String>> {
+        let addr = addr.as_str().canonize(deps.api)?;
+
+        INSTANCE_TAGS.load_or_default(deps.storage, &addr)
+    }
+
+    /// Removes all tags of an instance that is being removed.
+    pub(crate) fn remove_tags(storage: &mut dyn Storage, addr: &CanonicalAddr) -> StdResult<()> {
+        for tag in INSTANCE_TAGS.load_or_default(storage, addr)? {
+            Self::tagged_instances(&tag).remove(storage, addr)?;
+        }
+
+        INSTANCE_TAGS.remove(storage, addr);
+
+        Ok(())
+    }
+
+    fn assert_can_tag(
+        deps: Deps,
+        info: &MessageInfo,
+        addr: String
+    ) -> StdResult<CanonicalAddr> {
+        let canonical = addr.as_str().canonize(deps.api)?;
+
+        let Some(instance) = Self::instances().load(deps.storage, &canonical)? else {
+            return Err(StdError::generic_err(
+                format!("No instance with address: {addr}.")
+            ));
+        };
+
+        if admin::assert(deps, info).is_err() &&
+            instance.created.creator != info.sender.as_str().canonize(deps.api)?
+        {
+            return Err(StdError::generic_err("Unauthorized"));
+        }
+
+        Ok(canonical)
+    }
+
+    #[inline]
+    fn tagged_instances(tag: &str) -> AddrList {
+        AddrList::new(&[b"tagged_instances", tag.as_bytes()])
+    }
+}
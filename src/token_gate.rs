@@ -0,0 +1,109 @@
+use serde::{Serialize, Deserialize};
+use fadroma::{
+    schemars::{self, JsonSchema},
+    cosmwasm_std::{StdResult, StdError, Response, Deps, DepsMut, MessageInfo, Addr, CanonicalAddr, Uint128},
+    bin_serde::{FadromaSerialize, FadromaDeserialize},
+    storage::{ItemSpace, TypedKey},
+    core::{ContractLink, Canonize},
+    namespace
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, FactoryError};
+
+/// The token that creators must hold when the auth mode
+/// is [`crate::AuthMode::TokenGated`]. Since balances are private,
+/// creators must first give the factory a viewing key for the token
+/// using [`crate::ExecuteMsg::SetTokenGateKey`].
+#[derive(Serialize, Deserialize, JsonSchema, FadromaSerialize, FadromaDeserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenGate {
+    /// A minimum balance of a SNIP-20 token.
+    Snip20 { token: ContractLink<Addr>, min_balance: Uint128 },
+    /// Any token of a SNIP-721 collection.
+    Snip721 { collection: ContractLink<Addr> }
+}
+
+// The viewing key of each creator for the gate token.
+namespace!(GateKeysNs, b"token_gate_keys");
+const GATE_KEYS: ItemSpace<
+    String,
+    GateKeysNs,
+    TypedKey<CanonicalAddr>
+> = ItemSpace::new();
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum GateQuery<'a> {
+    Balance { address: &'a str, key: &'a str },
+    Tokens { owner: &'a str, viewing_key: &'a str, limit: u32 }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum GateQueryAnswer {
+    Balance { amount: Uint128 },
+    TokenList { tokens: Vec<String> }
+}
+
+impl<
+    MSG: Serialize + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Stores the sender's viewing key for the gate token so that
+    /// the factory can check their holdings when they create instances.
+    pub fn set_token_gate_key(
+        deps: DepsMut,
+        info: MessageInfo,
+        key: String
+    ) -> StdResult<Response> {
+        let sender = info.sender.as_str().canonize(deps.api)?;
+        GATE_KEYS.save(deps.storage, &sender, &key)?;
+
+        Ok(Response::default())
+    }
+
+    /// Fails with [`FactoryError::NotTokenHolder`]
+    /// if `sender` doesn't hold the gate token.
+    pub fn assert_holds_token(deps: Deps, gate: &TokenGate, sender: &Addr) -> StdResult<()> {
+        let canonical = sender.as_str().canonize(deps.api)?;
+
+        let Some(key) = GATE_KEYS.load(deps.storage, &canonical)? else {
+            return Err(FactoryError::TokenGateKeyMissing.into());
+        };
+
+        let (contract, query) = match gate {
+            TokenGate::Snip20 { token, .. } => (token, GateQuery::Balance {
+                address: sender.as_str(),
+                key: &key
+            }),
+            TokenGate::Snip721 { collection } => (collection, GateQuery::Tokens {
+                owner: sender.as_str(),
+                viewing_key: &key,
+                limit: 1
+            })
+        };
+
+        let answer: GateQueryAnswer = deps.querier.query_wasm_smart(
+            &contract.code_hash,
+            &contract.address,
+            &query
+        )?;
+
+        let holds = match (gate, answer) {
+            (TokenGate::Snip20 { min_balance, .. }, GateQueryAnswer::Balance { amount }) =>
+                amount >= *min_balance,
+            (TokenGate::Snip721 { .. }, GateQueryAnswer::TokenList { tokens }) =>
+                !tokens.is_empty(),
+            _ => return Err(StdError::generic_err("Unexpected response from the gate token."))
+        };
+
+        if !holds {
+            return Err(FactoryError::NotTokenHolder.into());
+        }
+
+        Ok(())
+    }
+}
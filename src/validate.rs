@@ -0,0 +1,218 @@
+use std::marker::PhantomData;
+
+use serde::{Serialize, de::DeserializeOwned};
+use fadroma::{
+    cosmwasm_std::{StdResult, Response, Deps, DepsMut, Env, MessageInfo, Addr, SubMsg}
+};
+
+use crate::{GenericFactory, ExtraData, FactoryHooks, ExecuteMsg, InstanceConfig, Instance};
+
+/// Implement this on the `MSG` type to check the instantiate message of a
+/// child before it is sent to it. Set [`Validated`] as the `HOOKS` parameter
+/// of [`GenericFactory`] so that invalid configs fail when creating the
+/// instance instead of in the child's instantiate function.
+///
+/// # Examples
+///
+/// ```
+/// use generic_factory::ValidateConfig;
+/// use fadroma::cosmwasm_std::{StdResult, StdError, Deps};
+///
+/// struct PairInit {
+///     token_0: String,
+///     token_1: String
+/// }
+///
+/// impl ValidateConfig for PairInit {
+///     fn validate(&self, _deps: Deps) -> StdResult<()> {
+///         if self.token_0 == self.token_1 {
+///             return Err(StdError::generic_err("A pair needs two different tokens."));
+///         }
+///
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait ValidateConfig {
+    fn validate(&self, deps: Deps) -> StdResult<()>;
+}
+
+/// Hooks that validate the message of every instance being created
+/// with [`ValidateConfig`] before calling the given `HOOKS`.
+pub struct Validated<HOOKS = ()>(PhantomData<HOOKS>);
+
+impl<
+    MSG: ValidateConfig,
+    EXTRA: ExtraData,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> FactoryHooks<MSG, EXTRA> for Validated<HOOKS> {
+    #[inline]
+    fn before_create(
+        deps: Deps,
+        info: &MessageInfo,
+        config: &InstanceConfig<MSG>
+    ) -> StdResult<()> {
+        config.msg.validate(deps)?;
+
+        HOOKS::before_create(deps, info, config)
+    }
+
+    #[inline]
+    fn after_registered(
+        deps: DepsMut,
+        instance: &Instance<Addr, EXTRA>
+    ) -> StdResult<Vec<SubMsg>> {
+        HOOKS::after_registered(deps, instance)
+    }
+}
+
+impl<
+    MSG: Serialize + DeserializeOwned + ValidateConfig + 'static,
+    EXTRA: ExtraData,
+    const AUTH: bool,
+    const REPLY: u64,
+    HOOKS: FactoryHooks<MSG, EXTRA>
+> GenericFactory<MSG, EXTRA, AUTH, REPLY, HOOKS> {
+    /// Same as [`GenericFactory::execute`] but also validates the instantiate
+    /// messages of the instances being created. Prefer setting [`Validated`] as
+    /// the `HOOKS` parameter, which also covers [`crate::InstantiateMsg::initial_instance`].
+    #[inline]
+    pub fn execute_validated(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: ExecuteMsg<MSG>
+    ) -> StdResult<Response> {
+        GenericFactory::<MSG, EXTRA, AUTH, REPLY, Validated<HOOKS>>::execute(deps, env, info, msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use fadroma::{
+        schemars::{self, JsonSchema},
+        cosmwasm_std::{
+            StdError, Empty,
+            testing::{mock_dependencies, mock_env, mock_info}
+        },
+        core::ContractCode
+    };
+    use crate::{InstantiateMsg, REPLY_ID};
+
+    #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+    struct ChildMsg {
+        fee: u8
+    }
+
+    impl ValidateConfig for ChildMsg {
+        fn validate(&self, _deps: Deps) -> StdResult<()> {
+            if self.fee > 100 {
+                return Err(StdError::generic_err("Fee cannot be more than 100."));
+            }
+
+            Ok(())
+        }
+    }
+
+    type Factory = GenericFactory<ChildMsg, Empty, false>;
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let mut deps = mock_dependencies();
+
+        Factory::instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance: None
+        }).unwrap();
+
+        let config = |fee| InstanceConfig {
+            msg: ChildMsg { fee },
+            funds: vec![],
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let err = Factory::execute_validated(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::CreateInstances(vec![config(3), config(101)])
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::generic_err("Fee cannot be more than 100."));
+
+        let resp = Factory::execute_validated(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::CreateInstance(config(3))
+        ).unwrap();
+
+        assert_eq!(resp.messages.len(), 1);
+    }
+
+    #[test]
+    fn every_creation_is_validated() {
+        type Factory = GenericFactory<ChildMsg, Empty, false, REPLY_ID, Validated>;
+
+        let mut deps = mock_dependencies();
+
+        let config = |fee| InstanceConfig {
+            msg: ChildMsg { fee },
+            funds: vec![],
+            code_key: None,
+            label: None,
+            extra: None
+        };
+
+        let msg = |initial_instance| InstantiateMsg {
+            admin: None,
+            code: ContractCode { id: 1, code_hash: "child".into() },
+            config: None,
+            initial_instance
+        };
+
+        let err = Factory::instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            msg(Some(config(101)))
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::generic_err("Fee cannot be more than 100."));
+
+        Factory::instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg(None)).unwrap();
+
+        // Proposals are validated when submitted so that
+        // no deposit is locked for an invalid config.
+        let err = Factory::execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::ProposeInstance(config(101))
+        ).unwrap_err();
+
+        assert_eq!(err, StdError::generic_err("Fee cannot be more than 100."));
+
+        Factory::execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::ProposeInstance(config(3))
+        ).unwrap();
+
+        let resp = Factory::execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            ExecuteMsg::ApproveInstance { id: 0 }
+        ).unwrap();
+
+        assert_eq!(resp.messages.len(), 1);
+    }
+}